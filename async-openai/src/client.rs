@@ -7,7 +7,7 @@ use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
     config::{Config, OpenAIConfig},
-    error::{map_deserialization_error, OpenAIError, WrappedError},
+    error::{map_deserialization_error, retry_after_from_headers, OpenAIError, WrappedError},
     file::Files,
     image::Images,
     moderation::Moderations,
@@ -303,33 +303,38 @@ impl<C: Config> Client<C> {
                 .map_err(backoff::Error::Permanent)?;
 
             let status = response.status();
+            let retry_after = retry_after_from_headers(response.headers());
             let bytes = response
                 .bytes()
                 .await
                 .map_err(OpenAIError::Reqwest)
                 .map_err(backoff::Error::Permanent)?;
 
-            // Deserialize response body from either error object or actual response object
+            // Keep the status, raw body and Retry-After so callers can classify the failure;
+            // OpenAI-compatible servers don't always send an OpenAI-shaped error object.
             if !status.is_success() {
-                let wrapped_error: WrappedError = serde_json::from_slice(bytes.as_ref())
-                    .map_err(|e| map_deserialization_error(e, bytes.as_ref()))
-                    .map_err(backoff::Error::Permanent)?;
-
-                if status.as_u16() == 429
-                    // API returns 429 also when:
-                    // "You exceeded your current quota, please check your plan and billing details."
-                    && wrapped_error.error.r#type != Some("insufficient_quota".to_string())
-                {
+                let api_error = serde_json::from_slice::<WrappedError>(bytes.as_ref())
+                    .ok()
+                    .map(|wrapped| wrapped.error);
+                // API returns 429 also when:
+                // "You exceeded your current quota, please check your plan and billing details."
+                let rate_limited = status.as_u16() == 429
+                    && api_error.as_ref().and_then(|e| e.r#type.as_deref())
+                        != Some("insufficient_quota");
+
+                let err = OpenAIError::HttpStatus {
+                    status: status.as_u16(),
+                    body: String::from_utf8_lossy(bytes.as_ref()).into_owned(),
+                    api_error,
+                    retry_after,
+                };
+
+                if rate_limited {
                     // Rate limited retry...
-                    tracing::warn!("Rate limited: {}", wrapped_error.error.message);
-                    return Err(backoff::Error::Transient {
-                        err: OpenAIError::ApiError(wrapped_error.error),
-                        retry_after: None,
-                    });
+                    tracing::warn!("Rate limited: {}", err);
+                    return Err(backoff::Error::Transient { err, retry_after });
                 } else {
-                    return Err(backoff::Error::Permanent(OpenAIError::ApiError(
-                        wrapped_error.error,
-                    )));
+                    return Err(backoff::Error::Permanent(err));
                 }
             }
 
@@ -437,6 +442,20 @@ where
     tokio::spawn(async move {
         while let Some(ev) = event_source.next().await {
             match ev {
+                Err(reqwest_eventsource::Error::InvalidStatusCode(status, response)) => {
+                    let retry_after = retry_after_from_headers(response.headers());
+                    let body = response.text().await.unwrap_or_default();
+                    let api_error = serde_json::from_str::<WrappedError>(&body)
+                        .ok()
+                        .map(|wrapped| wrapped.error);
+                    let _ = tx.send(Err(OpenAIError::HttpStatus {
+                        status: status.as_u16(),
+                        body,
+                        api_error,
+                        retry_after,
+                    }));
+                    break;
+                }
                 Err(e) => {
                     if let Err(_e) = tx.send(Err(OpenAIError::StreamError(e.to_string()))) {
                         // rx dropped
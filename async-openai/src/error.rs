@@ -9,6 +9,15 @@ pub enum OpenAIError {
     /// OpenAI returns error object with details of API call failure
     #[error("{0}")]
     ApiError(ApiError),
+    /// Non-success HTTP status, with the raw response body, the parsed error object
+    /// when the body has one, and the server's Retry-After hint
+    #[error("http status {status}: {}", api_error.as_ref().map(|e| e.to_string()).unwrap_or_else(|| body.clone()))]
+    HttpStatus {
+        status: u16,
+        body: String,
+        api_error: Option<ApiError>,
+        retry_after: Option<std::time::Duration>,
+    },
     /// Error when a response cannot be deserialized into a Rust type
     #[error("failed to deserialize api response: {0}")]
     JSONDeserialize(serde_json::Error),
@@ -67,6 +76,22 @@ pub(crate) struct WrappedError {
     pub(crate) error: ApiError,
 }
 
+/// Reads `retry-after-ms` (sent by OpenAI) or `retry-after` in whole seconds
+pub fn retry_after_from_headers(
+    headers: &reqwest::header::HeaderMap,
+) -> Option<std::time::Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    header("retry-after-ms")
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .map(|ms| std::time::Duration::from_secs_f64(ms.max(0.0) / 1000.0))
+        .or_else(|| {
+            header("retry-after")
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(std::time::Duration::from_secs)
+        })
+}
+
 pub(crate) fn map_deserialization_error(e: serde_json::Error, bytes: &[u8]) -> OpenAIError {
     tracing::error!(
         "failed deserialization of: {}",
@@ -312,6 +312,21 @@ pub struct FunctionObject {
 pub enum ChatCompletionResponseFormatType {
     Text,
     JsonObject,
+    JsonSchema,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ResponseFormatJsonSchema {
+    /// The name of the response format. Must be a-z, A-Z, 0-9, or contain underscores and dashes.
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The schema for the response format, described as a JSON Schema object.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
+    /// Whether to enable strict schema adherence when generating the output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
@@ -323,8 +338,12 @@ pub struct ChatCompletionResponseFormat {
     /// content may be partial (i.e. cut off) if `finish_reason="length"`, which indicates the generation
     /// exceeded `max_tokens` or the conversation exceeded the max context length.
     ///
-    /// Must be one of `text` or `json_object`.
+    /// Must be one of `text`, `json_object` or `json_schema`.
     pub r#type: ChatCompletionResponseFormatType,
+
+    /// Required when `type` is `json_schema`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json_schema: Option<ResponseFormatJsonSchema>,
 }
 
 #[derive(Clone, Serialize, Default, Debug, Deserialize, PartialEq)]
@@ -1,14 +1,22 @@
-use crate::agent_runtime::{ActorContext, AgentId, MessageContext, RouterCommand, TopicId};
-use crate::immutable_agent::{LlmAgent, Message};
+use crate::agent_runtime::{
+    events::SystemEvent, ActorContext, AgentId, MessageContext, RouterCommand, TopicId,
+    SYSTEM_TOPIC,
+};
+use crate::immutable_agent::{LlmAgent, Message, TraceStep};
 use crate::llama::Content;
 use crate::llama::LlamaResponseMessage;
 use async_openai::types::Role;
 use ractor::{Actor, ActorProcessingErr, ActorRef, MessagingErr};
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use thiserror::Error;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, PartialEq)]
+// Message names used on an agent's stream topic to tell partial output from the final answer
+pub const STREAM_DELTA_NAME: &str = "stream_delta";
+pub const STREAM_FINAL_NAME: &str = "stream_final";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ProcessingState {
     Ready,
     Processing,
@@ -49,6 +57,14 @@ impl AgentState {
     pub fn get_context(&self) -> ActorContext {
         self.context.clone()
     }
+
+    pub fn processing_state(&self) -> &ProcessingState {
+        &self.processing_state
+    }
+
+    pub fn set_processing_state(&mut self, processing_state: ProcessingState) {
+        self.processing_state = processing_state;
+    }
 }
 
 #[derive(Debug, Error)]
@@ -77,6 +93,66 @@ impl AgentActor {
             llm,
         }
     }
+
+    fn publish_stream(&self, topic: &TopicId, text: &str, name: &str, state: &AgentState) {
+        let route_msg = RouterCommand::RouteMessage {
+            topic: topic.clone(),
+            message: Message::new(
+                Content::Text(text.to_string()),
+                Some(name.to_string()),
+                Role::Assistant,
+            ),
+            context: state.get_context(),
+        };
+        if let Err(e) = self.router.send_message(route_msg) {
+            log::warn!(
+                "Agent {} failed to publish stream chunk: {:?}",
+                self.agent_id,
+                e
+            );
+        }
+    }
+
+    fn publish_trace(&self, step: &TraceStep) {
+        let trace = RouterCommand::AgentTrace {
+            agent_id: self.agent_id,
+            step: step.clone(),
+        };
+        if let Err(e) = self.router.send_message(trace) {
+            log::warn!("Agent {} failed to publish trace: {:?}", self.agent_id, e);
+        }
+    }
+
+    // Acknowledges a work item whether it was answered, failed or ignored, so the
+    // router's load count for this agent goes back down
+    fn finish_work(&self, context: &ActorContext) {
+        if !context.is_work_item() {
+            return;
+        }
+        let done = RouterCommand::WorkDone {
+            agent_id: self.agent_id,
+        };
+        if let Err(e) = self.router.send_message(done) {
+            log::warn!(
+                "Agent {} failed to report finished work: {:?}",
+                self.agent_id,
+                e
+            );
+        }
+    }
+
+    fn set_processing_state(
+        &self,
+        state: &mut AgentState,
+        processing_state: ProcessingState,
+    ) -> Result<(), AgentActorError> {
+        state.processing_state = processing_state.clone();
+        self.router.send_message(RouterCommand::AgentStateChanged {
+            agent_id: self.agent_id,
+            processing_state,
+        })?;
+        Ok(())
+    }
 }
 
 impl Actor for AgentActor {
@@ -115,37 +191,82 @@ impl Actor for AgentActor {
                 },
                 ProcessingState::Ready,
             ) => {
-                if context.sender == Some(self.agent_id) {
+                // Handling another agent's state change would change this agent's state
+                // in turn, so two agents on the system topic would wake each other forever
+                let is_activity = topic == SYSTEM_TOPIC
+                    && SystemEvent::from_message(&message).is_some_and(|e| e.is_activity());
+                if context.sender == Some(self.agent_id) || is_activity {
+                    self.finish_work(&context);
                     return Ok(());
                 }
-                state.processing_state = ProcessingState::Processing;
+                self.set_processing_state(state, ProcessingState::Processing)?;
                 let input = message.content.content_to_string();
 
                 println!("Agent {} processing message: {:?}", self.agent_id, input);
 
-                match self.llm.default_method(&input).await {
+                let result = match self.llm.stream_topic.clone() {
+                    Some(stream_topic) if self.llm.can_stream() => {
+                        let result = self
+                            .llm
+                            .stream_method(&input, |delta| {
+                                self.publish_stream(&stream_topic, delta, STREAM_DELTA_NAME, state)
+                            })
+                            .await;
+                        if let Ok(response) = &result {
+                            self.publish_stream(
+                                &stream_topic,
+                                &response.content.content_to_string(),
+                                STREAM_FINAL_NAME,
+                                state,
+                            );
+                        }
+                        result
+                    }
+                    _ if self.llm.is_react() => {
+                        self.llm
+                            .react_method(&input, |step| self.publish_trace(step))
+                            .await
+                    }
+                    _ => self.llm.default_method(&input).await,
+                };
+                self.finish_work(&context);
+
+                match result {
                     Ok(llama_response) => {
                         println!("LLM response (Llama): {:?}", llama_response);
-                        let route_msg = RouterCommand::RouteMessage {
-                            topic: topic.clone(),
-                            message: Message::new(
-                                Content::Text(llama_response.content.content_to_string()),
-                                None,
-                                Role::Assistant,
-                            ),
-                            context: state.get_context(),
-                        };
-                        self.router
-                            .send_message(route_msg)
-                            .map_err(AgentActorError::from)?;
-
-                        state.processing_state = ProcessingState::Ready;
+                        // The system topic carries router events only; an answer to one
+                        // published there would wake every other subscriber again
+                        if topic != SYSTEM_TOPIC {
+                            let route_msg = RouterCommand::RouteMessage {
+                                topic: topic.clone(),
+                                message: Message {
+                                    model: llama_response.model.clone(),
+                                    ..Message::new(
+                                        Content::Text(llama_response.content.content_to_string()),
+                                        None,
+                                        Role::Assistant,
+                                    )
+                                },
+                                context: state.get_context(),
+                            };
+                            self.router
+                                .send_message(route_msg)
+                                .map_err(AgentActorError::from)?;
+                        }
+
+                        self.set_processing_state(state, ProcessingState::Ready)?;
                         Ok(())
                     }
                     Err(e) => Err(Box::new(AgentActorError::LlmProcessing(e.into()))),
                 }
             }
 
+            // Off: the message is dropped, but a work item still has to be acknowledged
+            (RouterCommand::RouteMessage { context, .. }, _) => {
+                self.finish_work(&context);
+                Ok(())
+            }
+
             (RouterCommand::ShutdownAgent { agent_id }, _) => {
                 if agent_id != self.agent_id {
                     return Err(Box::new(AgentActorError::ShutdownFailure(agent_id.into())));
@@ -0,0 +1,98 @@
+use crate::agent_runtime::{
+    agent::ProcessingState, router::RouterStatus, AgentId, TopicId, SYSTEM_TOPIC,
+};
+use crate::immutable_agent::{Message, TraceStep};
+use crate::llama::Content;
+use async_openai::types::Role;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SystemEvent {
+    AgentSpawned {
+        agent_id: AgentId,
+        topic: TopicId,
+    },
+    AgentStopped {
+        agent_id: AgentId,
+    },
+    AgentCrashed {
+        agent_id: AgentId,
+        reason: String,
+    },
+    // Follows AgentCrashed when the router brought the agent back under the same id;
+    // `restarts` counts the restarts so far
+    AgentRestarted {
+        agent_id: AgentId,
+        restarts: usize,
+    },
+    AgentSubscribed {
+        agent_id: AgentId,
+        topic: TopicId,
+    },
+    AgentUnsubscribed {
+        agent_id: AgentId,
+        topic: TopicId,
+    },
+    AgentStateChanged {
+        agent_id: AgentId,
+        processing_state: ProcessingState,
+    },
+    RoutingFailed {
+        topic: TopicId,
+        agent_id: Option<AgentId>,
+        reason: String,
+    },
+    RouterStatusChanged {
+        status: RouterStatus,
+    },
+    AgentTrace {
+        agent_id: AgentId,
+        step: TraceStep,
+    },
+}
+
+impl SystemEvent {
+    // The agent the event is about, used as the sender so an agent never hears about itself
+    pub fn subject(&self) -> Option<AgentId> {
+        match self {
+            SystemEvent::AgentSpawned { agent_id, .. }
+            | SystemEvent::AgentStopped { agent_id }
+            | SystemEvent::AgentCrashed { agent_id, .. }
+            | SystemEvent::AgentRestarted { agent_id, .. }
+            | SystemEvent::AgentSubscribed { agent_id, .. }
+            | SystemEvent::AgentUnsubscribed { agent_id, .. }
+            | SystemEvent::AgentStateChanged { agent_id, .. }
+            | SystemEvent::AgentTrace { agent_id, .. } => Some(*agent_id),
+            SystemEvent::RoutingFailed { agent_id, .. } => *agent_id,
+            SystemEvent::RouterStatusChanged { .. } => None,
+        }
+    }
+
+    // What agents are doing turn by turn, as opposed to lifecycle changes. Meant for
+    // loggers and UIs; agents ignore them.
+    pub fn is_activity(&self) -> bool {
+        matches!(
+            self,
+            SystemEvent::AgentStateChanged { .. } | SystemEvent::AgentTrace { .. }
+        )
+    }
+
+    pub fn to_message(&self) -> Message {
+        let payload = serde_json::to_string(self).unwrap_or_else(|e| {
+            format!("{{\"event\":\"serialization_failed\",\"error\":\"{}\"}}", e)
+        });
+        Message::new(
+            Content::Text(payload),
+            Some(SYSTEM_TOPIC.to_string()),
+            Role::System,
+        )
+    }
+
+    pub fn from_message(message: &Message) -> Option<Self> {
+        match &message.content {
+            Content::Text(text) => serde_json::from_str(text).ok(),
+            _ => None,
+        }
+    }
+}
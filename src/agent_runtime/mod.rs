@@ -1,7 +1,15 @@
 pub mod agent;
+pub mod events;
 pub mod router;
+pub mod subscription;
+pub mod team;
+#[cfg(test)]
+pub(crate) mod testing;
 
-use crate::immutable_agent::{LlmAgent, Message};
+use crate::agent_runtime::{
+    agent::ProcessingState, router::DeliveryMode, subscription::Subscription, team::TeamSpec,
+};
+use crate::immutable_agent::{LlmAgent, Message, TraceStep};
 use crate::FormatterWrapper;
 use ractor::{ActorRef, RpcReplyPort};
 use serde_json::Value;
@@ -12,11 +20,15 @@ use uuid::Uuid;
 pub type AgentId = Uuid;
 pub type TopicId = String;
 
+pub const SYSTEM_TOPIC: &str = "system";
+
 #[derive(Debug, Clone)]
 pub struct Context<M> {
     sender: Option<AgentId>,
     topic_id: Option<TopicId>,
     timestamp: SystemTime,
+    // Set by the router on messages it hands out from a work queue
+    work_item: bool,
     marker: PhantomData<M>,
 }
 
@@ -26,6 +38,7 @@ impl<M> Context<M> {
             sender: None,
             topic_id: None,
             timestamp: SystemTime::now(),
+            work_item: false,
             marker: PhantomData,
         }
     }
@@ -39,6 +52,17 @@ impl<M> Context<M> {
         self.topic_id = Some(topic);
         self
     }
+
+    pub fn as_work_item(mut self) -> Self {
+        self.work_item = true;
+        self
+    }
+
+    // Work items must be acknowledged with `RouterCommand::WorkDone` once handled, even
+    // when the receiver ignores them
+    pub fn is_work_item(&self) -> bool {
+        self.work_item
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -66,11 +90,27 @@ pub enum RouterCommand {
     SubscribeAgent {
         agent_id: AgentId,
         topic: TopicId,
+        subscription: Option<Subscription>,
     },
     UnsubscribeAgent {
         agent_id: AgentId,
         topic: TopicId,
     },
+    SetDeliveryMode {
+        topic: TopicId,
+        mode: DeliveryMode,
+    },
+    AgentStateChanged {
+        agent_id: AgentId,
+        processing_state: ProcessingState,
+    },
+    AgentTrace {
+        agent_id: AgentId,
+        step: TraceStep,
+    },
+    WorkDone {
+        agent_id: AgentId,
+    },
 
     SpawnAgent {
         system_prompt: String,
@@ -80,6 +120,24 @@ pub enum RouterCommand {
         tools_map_meta: Option<Value>,
         description: String,
     },
+
+    SpawnLlmAgent {
+        llm_agent: LlmAgent,
+        topic: TopicId,
+        reply_to: RpcReplyPort<SpawnAgentResponse>,
+    },
+
+    SpawnTeam {
+        spec: TeamSpec,
+        topic: TopicId,
+        reply_to: RpcReplyPort<SpawnAgentResponse>,
+    },
+
+    RegisterAgent {
+        agent_id: AgentId,
+        agent_ref: ActorRef<RouterCommand>,
+        topic: TopicId,
+    },
 }
 
 pub type SpawnAgentResponse = Result<AgentId, String>;
@@ -103,16 +161,43 @@ impl std::fmt::Debug for RouterCommand {
                 .debug_struct("ShutdownAgent")
                 .field("agent_id", agent_id)
                 .finish(),
-            RouterCommand::SubscribeAgent { agent_id, topic } => f
+            RouterCommand::SubscribeAgent {
+                agent_id,
+                topic,
+                subscription,
+            } => f
                 .debug_struct("SubscribeAgent")
                 .field("agent_id", agent_id)
                 .field("topic", topic)
+                .field("subscription", subscription)
                 .finish(),
             RouterCommand::UnsubscribeAgent { agent_id, topic } => f
                 .debug_struct("UnsubscribeAgent")
                 .field("agent_id", agent_id)
                 .field("topic", topic)
                 .finish(),
+            RouterCommand::SetDeliveryMode { topic, mode } => f
+                .debug_struct("SetDeliveryMode")
+                .field("topic", topic)
+                .field("mode", mode)
+                .finish(),
+            RouterCommand::AgentStateChanged {
+                agent_id,
+                processing_state,
+            } => f
+                .debug_struct("AgentStateChanged")
+                .field("agent_id", agent_id)
+                .field("processing_state", processing_state)
+                .finish(),
+            RouterCommand::AgentTrace { agent_id, step } => f
+                .debug_struct("AgentTrace")
+                .field("agent_id", agent_id)
+                .field("step", step)
+                .finish(),
+            RouterCommand::WorkDone { agent_id } => f
+                .debug_struct("WorkDone")
+                .field("agent_id", agent_id)
+                .finish(),
             RouterCommand::SpawnAgent {
                 system_prompt,
                 user_prompt_formatter: _,
@@ -129,6 +214,35 @@ impl std::fmt::Debug for RouterCommand {
                     .field("tools_map_meta", tools_map_meta)
                     .finish()
             }
+            RouterCommand::SpawnLlmAgent {
+                llm_agent,
+                topic,
+                reply_to,
+            } => f
+                .debug_struct("SpawnLlmAgent")
+                .field("description", &llm_agent.description)
+                .field("topic", topic)
+                .field("reply_to", reply_to)
+                .finish(),
+            RouterCommand::SpawnTeam {
+                spec,
+                topic,
+                reply_to,
+            } => f
+                .debug_struct("SpawnTeam")
+                .field("spec", spec)
+                .field("topic", topic)
+                .field("reply_to", reply_to)
+                .finish(),
+            RouterCommand::RegisterAgent {
+                agent_id,
+                agent_ref: _,
+                topic,
+            } => f
+                .debug_struct("RegisterAgent")
+                .field("agent_id", agent_id)
+                .field("topic", topic)
+                .finish(),
         }
     }
 }
@@ -1,22 +1,36 @@
 use crate::agent_runtime::{
-    agent::{AgentActor, AgentState},
-    ActorContext, AgentId, RouterCommand, SpawnAgentResponse, TopicId,
+    agent::{AgentActor, AgentState, ProcessingState},
+    events::SystemEvent,
+    subscription::Subscription,
+    team::{TeamActor, TeamSpec},
+    ActorContext, AgentId, RouterCommand, SpawnAgentResponse, TopicId, SYSTEM_TOPIC,
 };
 use crate::immutable_agent::{LlmAgent, Message};
 use crate::FormatterWrapper;
-use ractor::{Actor, ActorCell, ActorProcessingErr, ActorRef};
+use ractor::{Actor, ActorCell, ActorProcessingErr, ActorRef, SupervisionEvent};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::result::Result as StdResult;
 use thiserror::Error;
 
-#[derive(Debug, Default, Clone, PartialEq)]
+const MAX_AGENT_RESTARTS: usize = 3;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub enum RouterStatus {
     Ready,
     #[default]
     Off,
 }
 
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum DeliveryMode {
+    #[default]
+    Broadcast,
+    RoundRobin,
+    LeastBusy,
+}
+
 #[derive(Debug, Error, Clone)]
 pub enum RouterError {
     #[error("Router is not in ready state")]
@@ -36,6 +50,9 @@ pub enum RouterError {
 
     #[error("Invalid state: {0}")]
     InvalidState(String),
+
+    #[error("Team setup failed: {0}")]
+    TeamSetupFailed(String),
     // #[error("Agent actor failure: {0}")]
     // ActorFailure(#[from] ActorProcessingErr),
 }
@@ -45,7 +62,14 @@ pub struct RouterState {
     agents: HashMap<AgentId, ActorRef<RouterCommand>>,
     topic_subscriptions: HashMap<TopicId, Vec<AgentId>>,
     agent_subscriptions: HashMap<AgentId, Vec<TopicId>>,
+    subscription_rules: HashMap<(TopicId, AgentId), Subscription>,
     agent_states: HashMap<AgentId, AgentState>,
+    agent_load: HashMap<AgentId, usize>,
+    delivery_modes: HashMap<TopicId, DeliveryMode>,
+    round_robin_cursors: HashMap<TopicId, usize>,
+    // What the router needs to rebuild the agents it spawned itself after a crash
+    llm_agents: HashMap<AgentId, LlmAgent>,
+    restarts: HashMap<AgentId, usize>,
     state: RouterStatus,
     router: Option<ActorRef<RouterCommand>>,
 }
@@ -56,7 +80,13 @@ impl Default for RouterState {
             agents: HashMap::new(),
             topic_subscriptions: HashMap::new(),
             agent_subscriptions: HashMap::new(),
+            subscription_rules: HashMap::new(),
             agent_states: HashMap::new(),
+            agent_load: HashMap::new(),
+            delivery_modes: HashMap::new(),
+            round_robin_cursors: HashMap::new(),
+            llm_agents: HashMap::new(),
+            restarts: HashMap::new(),
             state: RouterStatus::default(),
             router: None,
         }
@@ -91,15 +121,37 @@ impl RouterState {
             return Err(RouterError::AgentNotFound(agent_id));
         }
 
-        self.topic_subscriptions
+        // Subscribing again leaves a single, unfiltered subscription
+        self.subscription_rules.remove(&(topic.clone(), agent_id));
+
+        let subscribers = self
+            .topic_subscriptions
             .entry(topic.clone())
-            .or_insert_with(Vec::new)
-            .push(agent_id);
+            .or_insert_with(Vec::new);
+        if subscribers.contains(&agent_id) {
+            return Ok(());
+        }
+        subscribers.push(agent_id);
 
         self.agent_subscriptions
             .entry(agent_id)
             .or_insert_with(Vec::new)
-            .push(topic);
+            .push(topic.clone());
+
+        self.publish_event(SystemEvent::AgentSubscribed { agent_id, topic });
+
+        Ok(())
+    }
+
+    pub fn subscribe_agent_with(
+        &mut self,
+        agent_id: AgentId,
+        topic: TopicId,
+        subscription: Subscription,
+    ) -> StdResult<(), RouterError> {
+        self.subscribe_agent(agent_id, topic.clone())?;
+        self.subscription_rules
+            .insert((topic, agent_id), subscription);
 
         Ok(())
     }
@@ -123,6 +175,13 @@ impl RouterState {
             topics.retain(|t| t != topic);
         }
 
+        self.subscription_rules.remove(&(topic.clone(), agent_id));
+
+        self.publish_event(SystemEvent::AgentUnsubscribed {
+            agent_id,
+            topic: topic.clone(),
+        });
+
         Ok(())
     }
 
@@ -161,66 +220,278 @@ impl RouterState {
             description,
         ) {
             Ok(llm_agent) => {
-                let agent_actor = AgentActor::new(
-                    new_agent_id,
-                    self.router
-                        .as_ref()
-                        .ok_or(RouterError::InvalidState("Router reference missing".into()))?
-                        .clone(),
-                    llm_agent.clone(),
-                );
-
-                let (agent_ref, _) = Actor::spawn_linked(
-                    None,
-                    agent_actor,
-                    (
-                        new_agent_id,
-                        self.router
-                            .as_ref()
-                            .ok_or(RouterError::InvalidState("Router reference missing".into()))?
-                            .clone(),
-                        llm_agent,
-                    ),
-                    self.router
-                        .as_ref()
-                        .ok_or(RouterError::InvalidState("Router reference missing".into()))?
-                        .clone()
-                        .into(),
-                )
-                .await
-                .map_err(|e| RouterError::SpawnFailed(e.to_string()))?;
-
-                self.agents.insert(new_agent_id, agent_ref);
-                self.agent_states
-                    .insert(new_agent_id, AgentState::new(new_agent_id));
-                self.agent_subscriptions.insert(new_agent_id, Vec::new());
-
-                self.subscribe_agent(new_agent_id, topic)?;
-
-                Ok(new_agent_id)
+                self.spawn_llm_agent_w_actor(new_agent_id, llm_agent, topic)
+                    .await
             }
             Err(e) => Err(RouterError::AgentBuildFailed(new_agent_id)),
         }
     }
 
+    async fn spawn_llm_agent_w_actor(
+        &mut self,
+        new_agent_id: AgentId,
+        llm_agent: LlmAgent,
+        topic: TopicId,
+    ) -> StdResult<AgentId, RouterError> {
+        self.ensure_ready()?;
+
+        let router = self
+            .router
+            .as_ref()
+            .ok_or(RouterError::InvalidState("Router reference missing".into()))?
+            .clone();
+
+        let agent_ref = spawn_agent_actor(new_agent_id, router, llm_agent.clone())
+            .await
+            .map_err(|e| RouterError::SpawnFailed(e.to_string()))?;
+
+        self.register_agent(new_agent_id, agent_ref, topic)?;
+        self.llm_agents.insert(new_agent_id, llm_agent);
+
+        Ok(new_agent_id)
+    }
+
+    async fn spawn_team_w_actor(
+        &mut self,
+        spec: TeamSpec,
+        topic: TopicId,
+    ) -> StdResult<AgentId, RouterError> {
+        self.ensure_ready()?;
+
+        let router = self
+            .router
+            .as_ref()
+            .ok_or(RouterError::InvalidState("Router reference missing".into()))?
+            .clone();
+
+        let team_id = AgentId::new_v4();
+        let team_actor = TeamActor::new(team_id, router.clone(), spec);
+
+        let (team_ref, _) = Actor::spawn_linked(None, team_actor, (), router.into())
+            .await
+            .map_err(|e| RouterError::TeamSetupFailed(e.to_string()))?;
+
+        self.register_agent(team_id, team_ref, topic)?;
+
+        Ok(team_id)
+    }
+
+    pub fn register_agent(
+        &mut self,
+        agent_id: AgentId,
+        agent_ref: ActorRef<RouterCommand>,
+        topic: TopicId,
+    ) -> StdResult<(), RouterError> {
+        self.ensure_ready()?;
+
+        self.agents.insert(agent_id, agent_ref);
+        self.agent_states
+            .insert(agent_id, AgentState::new(agent_id));
+        self.agent_subscriptions.insert(agent_id, Vec::new());
+
+        self.publish_event(SystemEvent::AgentSpawned {
+            agent_id,
+            topic: topic.clone(),
+        });
+
+        self.subscribe_agent(agent_id, topic)
+    }
+
     fn shutdown_agent(&mut self, agent_id: AgentId) -> StdResult<(), RouterError> {
         let agent_ref = self
-            .agents
-            .remove(&agent_id)
+            .remove_agent(agent_id)
             .ok_or(RouterError::AgentNotFound(agent_id))?;
 
+        agent_ref.stop(None);
+
+        self.publish_event(SystemEvent::AgentStopped { agent_id });
+
+        Ok(())
+    }
+
+    fn remove_agent(&mut self, agent_id: AgentId) -> Option<ActorRef<RouterCommand>> {
+        let agent_ref = self.agents.remove(&agent_id)?;
+
         if let Some(topics) = self.agent_subscriptions.remove(&agent_id) {
             for topic in topics {
                 if let Some(subscribers) = self.topic_subscriptions.get_mut(&topic) {
                     subscribers.retain(|id| *id != agent_id);
                 }
+                self.subscription_rules.remove(&(topic, agent_id));
             }
         }
 
-        agent_ref.stop(None);
         self.agent_states.remove(&agent_id);
+        self.agent_load.remove(&agent_id);
+        self.llm_agents.remove(&agent_id);
+        self.restarts.remove(&agent_id);
 
-        Ok(())
+        Some(agent_ref)
+    }
+
+    fn find_agent_by_cell(&self, cell: &ActorCell) -> Option<AgentId> {
+        self.agents
+            .iter()
+            .find(|(_, agent_ref)| agent_ref.get_id() == cell.get_id())
+            .map(|(agent_id, _)| *agent_id)
+    }
+
+    async fn handle_child_exit(&mut self, cell: &ActorCell, crash_reason: Option<String>) {
+        // Agents removed through shutdown_agent are already gone from the map
+        let Some(agent_id) = self.find_agent_by_cell(cell) else {
+            return;
+        };
+
+        match crash_reason {
+            Some(reason) => {
+                self.publish_event(SystemEvent::AgentCrashed { agent_id, reason });
+                if !self.restart_agent(agent_id).await {
+                    self.remove_agent(agent_id);
+                }
+            }
+            None => {
+                self.remove_agent(agent_id);
+                self.publish_event(SystemEvent::AgentStopped { agent_id });
+            }
+        }
+    }
+
+    // Brings a crashed LLM agent back under the same id, keeping its subscriptions.
+    // Teams and agents registered from outside can't be rebuilt, and neither can an
+    // agent that has already used up MAX_AGENT_RESTARTS.
+    async fn restart_agent(&mut self, agent_id: AgentId) -> bool {
+        let (Some(llm_agent), Some(router)) =
+            (self.llm_agents.get(&agent_id).cloned(), self.router.clone())
+        else {
+            return false;
+        };
+        let restarts = self.restarts.get(&agent_id).cloned().unwrap_or(0) + 1;
+        if restarts > MAX_AGENT_RESTARTS {
+            log::warn!("Agent {} crashed too often, not restarting it", agent_id);
+            return false;
+        }
+
+        match spawn_agent_actor(agent_id, router, llm_agent).await {
+            Ok(agent_ref) => {
+                self.agents.insert(agent_id, agent_ref);
+                self.agent_states
+                    .insert(agent_id, AgentState::new(agent_id));
+                // Whatever work the crashed actor held went down with it
+                self.agent_load.remove(&agent_id);
+                self.restarts.insert(agent_id, restarts);
+                self.publish_event(SystemEvent::AgentRestarted { agent_id, restarts });
+                true
+            }
+            Err(e) => {
+                log::warn!("Failed to restart agent {}: {}", agent_id, e);
+                false
+            }
+        }
+    }
+
+    pub fn set_status(&mut self, status: RouterStatus) {
+        if self.state == status {
+            return;
+        }
+        self.state = status.clone();
+        self.publish_event(SystemEvent::RouterStatusChanged { status });
+    }
+
+    // Events go out best-effort: a system topic without subscribers is not an error
+    pub fn publish_event(&mut self, event: SystemEvent) {
+        let topic = TopicId::from(SYSTEM_TOPIC);
+        let subscribers = match self.topic_subscriptions.get(&topic) {
+            Some(subscribers) if !subscribers.is_empty() => subscribers.clone(),
+            _ => return,
+        };
+
+        let subject = event.subject();
+        let message = event.to_message();
+        let mut context = ActorContext::new().with_topic(topic.clone());
+        if let Some(subject) = subject {
+            context = context.with_sender(subject);
+        }
+
+        for agent_id in subscribers {
+            if subject == Some(agent_id) {
+                continue;
+            }
+            self.deliver(agent_id, &topic, &message, &context);
+        }
+    }
+
+    pub fn set_delivery_mode(&mut self, topic: TopicId, mode: DeliveryMode) {
+        self.round_robin_cursors.remove(&topic);
+        self.delivery_modes.insert(topic, mode);
+    }
+
+    pub fn get_delivery_mode(&self, topic: &TopicId) -> DeliveryMode {
+        self.delivery_modes.get(topic).cloned().unwrap_or_default()
+    }
+
+    pub fn update_agent_state(&mut self, agent_id: AgentId, processing_state: ProcessingState) {
+        if let Some(agent_state) = self.agent_states.get_mut(&agent_id) {
+            agent_state.set_processing_state(processing_state.clone());
+        }
+
+        self.publish_event(SystemEvent::AgentStateChanged {
+            agent_id,
+            processing_state,
+        });
+    }
+
+    // `agent_load` counts work items handed to an agent and not yet acknowledged, so
+    // broadcasts and system events never make an agent look busy
+    pub fn finish_work(&mut self, agent_id: AgentId) {
+        if let Some(load) = self.agent_load.get_mut(&agent_id) {
+            *load = load.saturating_sub(1);
+        }
+    }
+
+    pub fn agent_load(&self, agent_id: &AgentId) -> usize {
+        self.agent_load.get(agent_id).cloned().unwrap_or(0)
+    }
+
+    // Returns whether the subscription accepted the message and it was handed to the agent
+    fn deliver(
+        &mut self,
+        agent_id: AgentId,
+        topic: &TopicId,
+        message: &Message,
+        context: &ActorContext,
+    ) -> bool {
+        let delivered = match self.subscription_rules.get(&(topic.clone(), agent_id)) {
+            Some(subscription) => match subscription.apply(message, context) {
+                Some(delivered) => delivered,
+                None => return false,
+            },
+            None => message.clone(),
+        };
+
+        let Some(agent_ref) = self.agents.get(&agent_id) else {
+            return false;
+        };
+
+        if let Err(e) = agent_ref.cast(RouterCommand::RouteMessage {
+            topic: topic.clone(),
+            message: delivered,
+            context: context.clone(),
+        }) {
+            log::warn!("Failed to route message to agent {}: {:?}", agent_id, e);
+            if topic != SYSTEM_TOPIC {
+                self.publish_event(SystemEvent::RoutingFailed {
+                    topic: topic.clone(),
+                    agent_id: Some(agent_id),
+                    reason: e.to_string(),
+                });
+            }
+            return false;
+        }
+
+        if context.is_work_item() {
+            *self.agent_load.entry(agent_id).or_insert(0) += 1;
+        }
+        true
     }
 
     fn route_message(
@@ -240,20 +511,49 @@ impl RouterState {
             return Err(RouterError::TopicNotFound(topic));
         }
 
-        for agent_id in agent_ids {
-            // Don't route message back to sender using context
-            if context.sender == Some(*agent_id) {
-                continue;
-            }
+        // Don't route message back to sender using context
+        let candidates: Vec<AgentId> = agent_ids
+            .iter()
+            .filter(|agent_id| context.sender != Some(**agent_id))
+            .cloned()
+            .collect();
 
-            if let Some(agent_ref) = self.agents.get(agent_id) {
-                if let Err(e) = agent_ref.cast(RouterCommand::RouteMessage {
-                    topic: topic.clone(),
-                    message: message.clone(),
-                    context: context.clone(),
-                }) {
-                    log::warn!("Failed to route message to agent {}: {:?}", agent_id, e);
+        match self.get_delivery_mode(&topic) {
+            DeliveryMode::Broadcast => {
+                for agent_id in candidates {
+                    self.deliver(agent_id, &topic, &message, &context);
+                }
+            }
+            DeliveryMode::RoundRobin => {
+                let context = context.as_work_item();
+                let start = self.round_robin_cursors.get(&topic).cloned().unwrap_or(0);
+                let count = candidates.len();
+                for offset in 0..count {
+                    let index = (start + offset) % count;
+                    if self.deliver(candidates[index], &topic, &message, &context) {
+                        self.round_robin_cursors.insert(topic.clone(), index + 1);
+                        return Ok(());
+                    }
+                }
+                log::warn!("No subscriber of work queue {} accepted the message", topic);
+            }
+            DeliveryMode::LeastBusy => {
+                let context = context.as_work_item();
+                let mut ranked = candidates;
+                ranked.sort_by_key(|agent_id| {
+                    let idle = self
+                        .agent_states
+                        .get(agent_id)
+                        .map(|s| *s.processing_state() == ProcessingState::Ready)
+                        .unwrap_or(false);
+                    (self.agent_load(agent_id), !idle)
+                });
+                for agent_id in ranked {
+                    if self.deliver(agent_id, &topic, &message, &context) {
+                        return Ok(());
+                    }
                 }
+                log::warn!("No subscriber of work queue {} accepted the message", topic);
             }
         }
 
@@ -261,6 +561,22 @@ impl RouterState {
     }
 }
 
+async fn spawn_agent_actor(
+    agent_id: AgentId,
+    router: ActorRef<RouterCommand>,
+    llm_agent: LlmAgent,
+) -> StdResult<ActorRef<RouterCommand>, ractor::SpawnErr> {
+    let agent_actor = AgentActor::new(agent_id, router.clone(), llm_agent.clone());
+    let (agent_ref, _) = Actor::spawn_linked(
+        None,
+        agent_actor,
+        (agent_id, router.clone(), llm_agent),
+        router.into(),
+    )
+    .await?;
+    Ok(agent_ref)
+}
+
 pub struct RouterActor;
 
 impl Default for RouterActor {
@@ -285,7 +601,13 @@ impl Actor for RouterActor {
             agents: HashMap::new(),
             topic_subscriptions: HashMap::new(),
             agent_subscriptions: HashMap::new(),
+            subscription_rules: HashMap::new(),
             agent_states: HashMap::new(),
+            agent_load: HashMap::new(),
+            delivery_modes: HashMap::new(),
+            round_robin_cursors: HashMap::new(),
+            llm_agents: HashMap::new(),
+            restarts: HashMap::new(),
             state: RouterStatus::Off,
             router: Some(myself), // Store the actor's own reference
         })
@@ -321,38 +643,504 @@ impl Actor for RouterActor {
                     }
                 }
                 Err(e) => {
-                    let response =
-                        SpawnAgentResponse::Err(format!("spawn agent on topic: {} failed", topic));
+                    let response = SpawnAgentResponse::Err(format!(
+                        "spawn agent on topic: {} failed: {}",
+                        topic, e
+                    ));
                     if !reply_to.is_closed() {
                         let _ = reply_to.send(response);
                     }
                 }
             },
 
+            RouterCommand::SpawnLlmAgent {
+                llm_agent,
+                topic,
+                reply_to,
+            } => match state
+                .spawn_llm_agent_w_actor(AgentId::new_v4(), llm_agent, topic.clone())
+                .await
+            {
+                Ok(agent_id) => {
+                    if !reply_to.is_closed() {
+                        let _ = reply_to.send(SpawnAgentResponse::Ok(agent_id));
+                    }
+                }
+                Err(e) => {
+                    let response = SpawnAgentResponse::Err(format!(
+                        "spawn agent on topic: {} failed: {}",
+                        topic, e
+                    ));
+                    if !reply_to.is_closed() {
+                        let _ = reply_to.send(response);
+                    }
+                }
+            },
+
+            RouterCommand::SpawnTeam {
+                spec,
+                topic,
+                reply_to,
+            } => match state.spawn_team_w_actor(spec, topic.clone()).await {
+                Ok(team_id) => {
+                    if !reply_to.is_closed() {
+                        let _ = reply_to.send(SpawnAgentResponse::Ok(team_id));
+                    }
+                }
+                Err(e) => {
+                    let response = SpawnAgentResponse::Err(format!(
+                        "spawn team on topic: {} failed: {}",
+                        topic, e
+                    ));
+                    if !reply_to.is_closed() {
+                        let _ = reply_to.send(response);
+                    }
+                }
+            },
+
+            RouterCommand::RegisterAgent {
+                agent_id,
+                agent_ref,
+                topic,
+            } => {
+                state.register_agent(agent_id, agent_ref, topic)?;
+            }
+
             RouterCommand::RouteMessage {
                 topic,
                 message,
                 context,
             } => {
-                state.route_message(topic, message, context)?;
+                // A publish to a topic nobody listens on (e.g. a stream topic before the UI
+                // attaches) is reported, not fatal to the router.
+                if let Err(e) = state.route_message(topic.clone(), message, context) {
+                    log::warn!("Failed to route message on topic {}: {}", topic, e);
+                    state.publish_event(SystemEvent::RoutingFailed {
+                        topic,
+                        agent_id: None,
+                        reason: e.to_string(),
+                    });
+                }
             }
 
             RouterCommand::ShutdownAgent { agent_id } => {
                 state.shutdown_agent(agent_id)?;
             }
             RouterCommand::Off => {
-                state.state = RouterStatus::Off;
+                state.set_status(RouterStatus::Off);
             }
             RouterCommand::Ready => {
-                state.state = RouterStatus::Ready;
-            }
-            RouterCommand::SubscribeAgent { agent_id, topic } => {
-                state.subscribe_agent(agent_id, topic)?;
+                state.set_status(RouterStatus::Ready);
             }
+            RouterCommand::SubscribeAgent {
+                agent_id,
+                topic,
+                subscription,
+            } => match subscription {
+                Some(subscription) => {
+                    state.subscribe_agent_with(agent_id, topic, subscription)?;
+                }
+                None => {
+                    state.subscribe_agent(agent_id, topic)?;
+                }
+            },
             RouterCommand::UnsubscribeAgent { agent_id, topic } => {
                 state.unsubscribe_agent(agent_id, &topic)?;
             }
+            RouterCommand::SetDeliveryMode { topic, mode } => {
+                state.set_delivery_mode(topic, mode);
+            }
+            RouterCommand::AgentStateChanged {
+                agent_id,
+                processing_state,
+            } => {
+                state.update_agent_state(agent_id, processing_state);
+            }
+            RouterCommand::AgentTrace { agent_id, step } => {
+                state.publish_event(SystemEvent::AgentTrace { agent_id, step });
+            }
+            RouterCommand::WorkDone { agent_id } => {
+                state.finish_work(agent_id);
+            }
+        }
+        Ok(())
+    }
+
+    // ractor's default stops a supervisor as soon as any child exits, which would take
+    // the router and every other agent down with one agent. The router outlives its
+    // agents instead: a stopped agent is removed, a crashed LLM agent is restarted up
+    // to MAX_AGENT_RESTARTS times and removed after that, and each exit is published
+    // on the system topic.
+    async fn handle_supervisor_evt(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        message: SupervisionEvent,
+        state: &mut Self::State,
+    ) -> StdResult<(), ActorProcessingErr> {
+        match message {
+            SupervisionEvent::ActorTerminated(cell, _, _) => {
+                state.handle_child_exit(&cell, None).await;
+            }
+            SupervisionEvent::ActorFailed(cell, err) => {
+                log::warn!("Agent actor {:?} failed: {}", cell.get_id(), err);
+                state.handle_child_exit(&cell, Some(err.to_string())).await;
+            }
+            _ => {}
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent_runtime::agent::{STREAM_DELTA_NAME, STREAM_FINAL_NAME};
+    use crate::agent_runtime::testing::Delivery;
+    use crate::agent_runtime::testing::{
+        collect, drain, drain_all, publish, recv, spawn_collector, spawn_llm_agent, start_router,
+    };
+    use crate::llama::mock::MockLlm;
+    use crate::llama::retry::RetryPolicy;
+    use crate::TOGETHER_CONFIG;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn mock_agent(mock: MockLlm) -> LlmAgent {
+        LlmAgent::build(
+            "You are a helpful assistant.".to_string(),
+            None,
+            None,
+            None,
+            "assistant".to_string(),
+        )
+        .unwrap()
+        .with_provider(Arc::new(mock))
+    }
+
+    #[tokio::test]
+    async fn agent_reply_is_routed_back_on_the_topic() {
+        let router = start_router().await;
+        let (user, mut rx) = collect(&router, "chat").await;
+        let agent_id = spawn_llm_agent(
+            &router,
+            mock_agent(MockLlm::new().with_regex("ping", "pong").unwrap()),
+            "chat",
+        )
+        .await
+        .unwrap();
+
+        publish(&router, "chat", user, "ping");
+        let reply = recv(&mut rx).await;
+        assert_eq!(reply.topic, "chat");
+        assert_eq!(reply.message.content.content_to_string(), "pong");
+        assert_eq!(
+            reply.message.model.as_deref(),
+            Some(TOGETHER_CONFIG.model.as_str())
+        );
+        assert_eq!(reply.context.sender, Some(agent_id));
+    }
+
+    #[tokio::test]
+    async fn streamed_reply_reaches_the_stream_topic_in_order() {
+        let router = start_router().await;
+        let (user, mut replies) = collect(&router, "chat").await;
+        let (_, mut stream) = collect(&router, "chat.stream").await;
+        let mock = MockLlm::new()
+            .with_response("Hello streaming world")
+            .with_stream_chunks(5);
+        spawn_llm_agent(
+            &router,
+            mock_agent(mock).with_stream_topic("chat.stream".to_string()),
+            "chat",
+        )
+        .await
+        .unwrap();
+
+        publish(&router, "chat", user, "hi");
+        let reply = recv(&mut replies).await;
+        assert_eq!(
+            reply.message.content.content_to_string(),
+            "Hello streaming world"
+        );
+
+        let streamed = drain(&mut stream).await;
+        let named = |name: &str| -> Vec<String> {
+            streamed
+                .iter()
+                .filter(|d| d.message.name.as_deref() == Some(name))
+                .map(|d| d.message.content.content_to_string())
+                .collect()
+        };
+        assert_eq!(
+            named(STREAM_DELTA_NAME),
+            ["Hello", " stre", "aming", " worl", "d"]
+        );
+        assert_eq!(named(STREAM_FINAL_NAME), ["Hello streaming world"]);
+        assert!(streamed.iter().all(|d| d.topic == "chat.stream"));
+    }
+
+    #[tokio::test]
+    async fn spawn_failure_reports_the_reason() {
+        let router = start_router().await;
+        router.cast(RouterCommand::Off).unwrap();
+
+        let response = spawn_llm_agent(
+            &router,
+            mock_agent(MockLlm::new().with_fallback("hi")),
+            "chat",
+        )
+        .await;
+        let error = response.unwrap_err();
+        assert!(error.contains("chat"), "{}", error);
+        assert!(
+            error.contains(&RouterError::NotReady.to_string()),
+            "{}",
+            error
+        );
+    }
+
+    fn ready_state() -> RouterState {
+        let mut state = RouterState::default();
+        state.set_status(RouterStatus::Ready);
+        state
+    }
+
+    fn text(text: &str) -> Message {
+        Message::new(
+            crate::llama::Content::Text(text.to_string()),
+            None,
+            async_openai::types::Role::User,
+        )
+    }
+
+    #[tokio::test]
+    async fn broadcasts_and_system_events_do_not_count_as_load() {
+        let mut state = ready_state();
+        let (agent_ref, mut rx) = spawn_collector().await;
+        let agent_id = AgentId::new_v4();
+        state
+            .register_agent(agent_id, agent_ref, "chat".to_string())
+            .unwrap();
+        state
+            .subscribe_agent(agent_id, SYSTEM_TOPIC.to_string())
+            .unwrap();
+
+        for _ in 0..3 {
+            state
+                .route_message("chat".to_string(), text("hello"), ActorContext::new())
+                .unwrap();
+        }
+        state.publish_event(SystemEvent::RouterStatusChanged {
+            status: RouterStatus::Ready,
+        });
+
+        assert_eq!(drain(&mut rx).await.len(), 4);
+        assert_eq!(state.agent_load(&agent_id), 0);
+    }
+
+    #[tokio::test]
+    async fn resubscribing_replaces_the_subscription_rule() {
+        use crate::agent_runtime::subscription::MessageFilter;
+        use async_openai::types::Role;
+
+        let mut state = ready_state();
+        let (agent_ref, mut rx) = spawn_collector().await;
+        let agent_id = AgentId::new_v4();
+        let topic = "chat".to_string();
+        state
+            .register_agent(agent_id, agent_ref, topic.clone())
+            .unwrap();
+        let only_assistant = Subscription::new().with_filter(MessageFilter::Role(Role::Assistant));
+
+        // Already subscribed: the rule still takes effect
+        state
+            .subscribe_agent_with(agent_id, topic.clone(), only_assistant)
+            .unwrap();
+        state
+            .route_message(topic.clone(), text("filtered"), ActorContext::new())
+            .unwrap();
+        assert!(drain(&mut rx).await.is_empty());
+
+        // A plain subscription clears it, and never delivers twice
+        state.subscribe_agent(agent_id, topic.clone()).unwrap();
+        state
+            .route_message(topic.clone(), text("delivered"), ActorContext::new())
+            .unwrap();
+        let received = drain(&mut rx).await;
+        assert_eq!(received.len(), 1);
+        assert_eq!(state.get_topic_subscribers(&topic).unwrap(), vec![agent_id]);
+    }
+
+    // Collectors never acknowledge work, so their load only goes down through finish_work
+    #[tokio::test]
+    async fn least_busy_counts_work_until_it_is_acknowledged() {
+        let mut state = ready_state();
+        let topic = "jobs".to_string();
+        state.set_delivery_mode(topic.clone(), DeliveryMode::LeastBusy);
+        let mut workers = Vec::new();
+        for _ in 0..2 {
+            let (agent_ref, rx) = spawn_collector().await;
+            let agent_id = AgentId::new_v4();
+            state
+                .register_agent(agent_id, agent_ref, topic.clone())
+                .unwrap();
+            workers.push((agent_id, rx));
+        }
+
+        for _ in 0..4 {
+            state
+                .route_message(topic.clone(), text("work"), ActorContext::new())
+                .unwrap();
+        }
+        for (agent_id, rx) in &mut workers {
+            let deliveries = drain(rx).await;
+            assert_eq!(deliveries.len(), 2);
+            assert!(deliveries.iter().all(|d| d.context.is_work_item()));
+            assert_eq!(state.agent_load(agent_id), 2);
+        }
+
+        let (first, _) = &workers[0];
+        state.finish_work(*first);
+        state.finish_work(*first);
+        state.finish_work(*first);
+        assert_eq!(state.agent_load(first), 0);
+        state
+            .route_message(topic.clone(), text("work"), ActorContext::new())
+            .unwrap();
+        assert_eq!(state.agent_load(first), 1);
+    }
+
+    // The collector stands in for the router, so it sees the agent's reply and its
+    // acknowledgements
+    #[tokio::test]
+    async fn agents_acknowledge_work_whether_answered_or_ignored() {
+        let (router, mut rx) = spawn_collector().await;
+        let agent_id = AgentId::new_v4();
+        let llm = mock_agent(MockLlm::new().with_fallback("done"));
+        let (agent_ref, _) = Actor::spawn(
+            None,
+            AgentActor::new(agent_id, router.clone(), llm.clone()),
+            (agent_id, router, llm),
+        )
+        .await
+        .unwrap();
+        let work = |text_: &str| RouterCommand::RouteMessage {
+            topic: "jobs".to_string(),
+            message: text(text_),
+            context: ActorContext::new().as_work_item(),
+        };
+        let is_done = |msg: &RouterCommand| matches!(msg, RouterCommand::WorkDone { agent_id: id } if *id == agent_id);
+
+        agent_ref.cast(work("answer this")).unwrap();
+        let received = drain_all(&mut rx).await;
+        assert_eq!(received.iter().filter(|m| is_done(m)).count(), 1);
+        assert!(received
+            .iter()
+            .any(|m| matches!(m, RouterCommand::RouteMessage { .. })));
+
+        agent_ref.cast(RouterCommand::Off).unwrap();
+        agent_ref.cast(work("ignore this")).unwrap();
+        let received = drain_all(&mut rx).await;
+        assert_eq!(received.len(), 1);
+        assert!(is_done(&received[0]));
+
+        // Broadcasts need no acknowledgement
+        agent_ref.cast(RouterCommand::Ready).unwrap();
+        agent_ref
+            .cast(RouterCommand::RouteMessage {
+                topic: "chat".to_string(),
+                message: text("hello"),
+                context: ActorContext::new(),
+            })
+            .unwrap();
+        assert!(!drain_all(&mut rx).await.iter().any(is_done));
+    }
+
+    fn events(deliveries: &[Delivery]) -> Vec<SystemEvent> {
+        deliveries
+            .iter()
+            .filter(|d| d.topic == SYSTEM_TOPIC)
+            .filter_map(|d| SystemEvent::from_message(&d.message))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn system_subscribers_do_not_wake_each_other() {
+        let router = start_router().await;
+        let (_, mut observer) = collect(&router, SYSTEM_TOPIC).await;
+        let (user, mut rx) = collect(&router, "a").await;
+        let mocks = [
+            Arc::new(MockLlm::new().with_fallback("noted")),
+            Arc::new(MockLlm::new().with_fallback("noted")),
+        ];
+        for (mock, topic) in mocks.iter().zip(["a", "b"]) {
+            let llm = mock_agent(MockLlm::new()).with_provider(mock.clone());
+            let agent_id = spawn_llm_agent(&router, llm, topic).await.unwrap();
+            router
+                .cast(RouterCommand::SubscribeAgent {
+                    agent_id,
+                    topic: SYSTEM_TOPIC.to_string(),
+                    subscription: None,
+                })
+                .unwrap();
+        }
+
+        publish(&router, "a", user, "hello");
+        assert_eq!(
+            recv(&mut rx).await.message.content.content_to_string(),
+            "noted"
+        );
+        let seen = tokio::time::timeout(Duration::from_secs(10), drain(&mut observer))
+            .await
+            .expect("system traffic settles");
+
+        assert!(events(&seen)
+            .iter()
+            .any(|e| matches!(e, SystemEvent::AgentStateChanged { .. })));
+        for mock in &mocks {
+            assert!(mock
+                .calls()
+                .iter()
+                .all(|call| !call.input.contains("agent_state_changed")));
+        }
+        // Agent b only ever hears system events, and never answers them on the topic
+        assert!(drain(&mut rx).await.is_empty());
+        assert!(seen
+            .iter()
+            .all(|d| d.message.role == async_openai::types::Role::System));
+    }
+
+    #[tokio::test]
+    async fn crashed_agents_are_restarted_until_the_limit() {
+        let router = start_router().await;
+        let (_, mut observer) = collect(&router, SYSTEM_TOPIC).await;
+        let (user, _rx) = collect(&router, "chat").await;
+        // Nothing scripted, so every message makes the agent fail
+        let llm = mock_agent(MockLlm::new()).with_retry_policy(RetryPolicy::none());
+        let agent_id = spawn_llm_agent(&router, llm, "chat").await.unwrap();
+        drain(&mut observer).await;
+
+        for restarts in 1..=MAX_AGENT_RESTARTS {
+            publish(&router, "chat", user, "hello");
+            let seen = events(&drain(&mut observer).await);
+            assert!(seen.iter().any(
+                |e| matches!(e, SystemEvent::AgentCrashed { agent_id: id, .. } if *id == agent_id)
+            ));
+            assert!(seen.contains(&SystemEvent::AgentRestarted { agent_id, restarts }));
+        }
+
+        publish(&router, "chat", user, "hello");
+        let seen = events(&drain(&mut observer).await);
+        assert!(seen
+            .iter()
+            .any(|e| matches!(e, SystemEvent::AgentCrashed { .. })));
+        assert!(!seen
+            .iter()
+            .any(|e| matches!(e, SystemEvent::AgentRestarted { .. })));
+
+        // Gone for good: the next message only reaches the other subscriber, so nothing
+        // happens on the system topic
+        publish(&router, "chat", user, "hello");
+        assert!(events(&drain(&mut observer).await).is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+use crate::agent_runtime::{ActorContext, AgentId};
+use crate::immutable_agent::Message;
+use crate::llama::{Content, StructuredText};
+use async_openai::types::Role;
+use std::sync::Arc;
+
+pub type MessagePredicate = Arc<dyn Fn(&Message, &ActorContext) -> bool + Send + Sync>;
+pub type MessageTransform = Arc<dyn Fn(Message) -> Message + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentKind {
+    Text,
+    ToolCall,
+    Tasks,
+    Expanded,
+}
+
+impl ContentKind {
+    pub fn of(content: &Content) -> Self {
+        match content {
+            Content::Text(_) => ContentKind::Text,
+            Content::Structured(StructuredText::ToolCall(_)) => ContentKind::ToolCall,
+            Content::Structured(StructuredText::Tasks(_)) => ContentKind::Tasks,
+            Content::Structured(StructuredText::Expanded(_)) => ContentKind::Expanded,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum MessageFilter {
+    Sender(AgentId),
+    Role(Role),
+    Content(ContentKind),
+    Custom(MessagePredicate),
+    Not(Box<MessageFilter>),
+    All(Vec<MessageFilter>),
+    Any(Vec<MessageFilter>),
+}
+
+impl MessageFilter {
+    pub fn matches(&self, message: &Message, context: &ActorContext) -> bool {
+        match self {
+            MessageFilter::Sender(agent_id) => context.sender == Some(*agent_id),
+            MessageFilter::Role(role) => message.role == *role,
+            MessageFilter::Content(kind) => ContentKind::of(&message.content) == *kind,
+            MessageFilter::Custom(predicate) => predicate(message, context),
+            MessageFilter::Not(filter) => !filter.matches(message, context),
+            MessageFilter::All(filters) => filters.iter().all(|f| f.matches(message, context)),
+            MessageFilter::Any(filters) => filters.iter().any(|f| f.matches(message, context)),
+        }
+    }
+}
+
+impl std::fmt::Debug for MessageFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessageFilter::Sender(agent_id) => f.debug_tuple("Sender").field(agent_id).finish(),
+            MessageFilter::Role(role) => f.debug_tuple("Role").field(role).finish(),
+            MessageFilter::Content(kind) => f.debug_tuple("Content").field(kind).finish(),
+            MessageFilter::Custom(_) => f.debug_tuple("Custom").finish(),
+            MessageFilter::Not(filter) => f.debug_tuple("Not").field(filter).finish(),
+            MessageFilter::All(filters) => f.debug_tuple("All").field(filters).finish(),
+            MessageFilter::Any(filters) => f.debug_tuple("Any").field(filters).finish(),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Subscription {
+    pub filter: Option<MessageFilter>,
+    pub transform: Option<MessageTransform>,
+}
+
+impl Subscription {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_filter(mut self, filter: MessageFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn with_transform(mut self, transform: MessageTransform) -> Self {
+        self.transform = Some(transform);
+        self
+    }
+
+    // Returns the message to deliver, or None when the filter rejects it
+    pub fn apply(&self, message: &Message, context: &ActorContext) -> Option<Message> {
+        if let Some(filter) = &self.filter {
+            if !filter.matches(message, context) {
+                return None;
+            }
+        }
+
+        match &self.transform {
+            Some(transform) => Some(transform(message.clone())),
+            None => Some(message.clone()),
+        }
+    }
+}
+
+impl std::fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscription")
+            .field("filter", &self.filter)
+            .field("transform", &self.transform.as_ref().map(|_| "<transform>"))
+            .finish()
+    }
+}
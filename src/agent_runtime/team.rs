@@ -0,0 +1,506 @@
+use crate::agent_runtime::{
+    router::RouterActor, ActorContext, AgentId, RouterCommand, SpawnAgentResponse, TopicId,
+};
+use crate::immutable_agent::{LlmAgent, Message};
+use crate::llama::provider::LlmProvider;
+use crate::FormatterWrapper;
+use async_openai::types::Role;
+use ractor::{rpc::CallResult, Actor, ActorProcessingErr, ActorRef};
+use serde_json::Value;
+use std::sync::Arc;
+use thiserror::Error;
+
+pub type TerminationFn = Arc<dyn Fn(&Message, usize) -> bool + Send + Sync>;
+
+#[derive(Clone)]
+pub enum TerminationCondition {
+    MaxTurns(usize),
+    TextMention(String),
+    Custom(TerminationFn),
+    Any(Vec<TerminationCondition>),
+}
+
+impl TerminationCondition {
+    // `turns` counts the member replies seen since the current task was handed in
+    pub fn is_met(&self, message: &Message, turns: usize) -> bool {
+        match self {
+            TerminationCondition::MaxTurns(max) => turns >= *max,
+            TerminationCondition::TextMention(text) => {
+                message.content.content_to_string().contains(text.as_str())
+            }
+            TerminationCondition::Custom(f) => f(message, turns),
+            TerminationCondition::Any(conditions) => {
+                conditions.iter().any(|c| c.is_met(message, turns))
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for TerminationCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TerminationCondition::MaxTurns(max) => f.debug_tuple("MaxTurns").field(max).finish(),
+            TerminationCondition::TextMention(text) => {
+                f.debug_tuple("TextMention").field(text).finish()
+            }
+            TerminationCondition::Custom(_) => f.debug_tuple("Custom").finish(),
+            TerminationCondition::Any(conditions) => {
+                f.debug_tuple("Any").field(conditions).finish()
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TeamMember {
+    pub system_prompt: String,
+    pub user_prompt_formatter: Option<FormatterWrapper>,
+    pub tools_map_meta: Option<Value>,
+    pub description: String,
+    pub provider: Option<Arc<dyn LlmProvider>>,
+}
+
+impl TeamMember {
+    pub fn new(system_prompt: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            system_prompt: system_prompt.into(),
+            user_prompt_formatter: None,
+            tools_map_meta: None,
+            description: description.into(),
+            provider: None,
+        }
+    }
+
+    pub fn with_user_prompt_formatter(mut self, formatter: FormatterWrapper) -> Self {
+        self.user_prompt_formatter = Some(formatter);
+        self
+    }
+
+    pub fn with_tools(mut self, tools_map_meta: Value) -> Self {
+        self.tools_map_meta = Some(tools_map_meta);
+        self
+    }
+
+    pub fn with_provider(mut self, provider: Arc<dyn LlmProvider>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+}
+
+impl std::fmt::Debug for TeamMember {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TeamMember")
+            .field("system_prompt", &self.system_prompt)
+            // Skip the formatter field entirely
+            .field("tools_map_meta", &self.tools_map_meta)
+            .field("description", &self.description)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TeamSpec {
+    pub name: String,
+    pub internal_topic: TopicId,
+    pub members: Vec<TeamMember>,
+    pub termination: TerminationCondition,
+}
+
+impl TeamSpec {
+    pub fn new(name: impl Into<String>, termination: TerminationCondition) -> Self {
+        Self {
+            name: name.into(),
+            internal_topic: TopicId::from("team"),
+            members: Vec::new(),
+            termination,
+        }
+    }
+
+    pub fn with_internal_topic(mut self, topic: TopicId) -> Self {
+        self.internal_topic = topic;
+        self
+    }
+
+    pub fn with_member(mut self, member: TeamMember) -> Self {
+        self.members.push(member);
+        self
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TeamError {
+    #[error("Failed to start inner router: {0}")]
+    RouterStart(String),
+
+    #[error("Failed to spawn team member: {0}")]
+    MemberSpawn(String),
+}
+
+pub struct TeamState {
+    inner_router: ActorRef<RouterCommand>,
+    member_ids: Vec<AgentId>,
+    parent_topic: Option<TopicId>,
+    // Whether the current task came from a parent work queue and still needs a WorkDone
+    pending_work: bool,
+    turns: usize,
+    // Whether the members are subscribed to the internal topic; they are taken off it
+    // once a task is finished so they stop answering each other
+    members_listening: bool,
+}
+
+impl TeamState {
+    pub fn member_ids(&self) -> &[AgentId] {
+        &self.member_ids
+    }
+}
+
+pub struct TeamActor {
+    team_id: AgentId,
+    parent: ActorRef<RouterCommand>,
+    spec: TeamSpec,
+}
+
+impl TeamActor {
+    pub fn new(team_id: AgentId, parent: ActorRef<RouterCommand>, spec: TeamSpec) -> Self {
+        Self {
+            team_id,
+            parent,
+            spec,
+        }
+    }
+
+    fn finish_work(&self, state: &mut TeamState) {
+        if !std::mem::take(&mut state.pending_work) {
+            return;
+        }
+        let done = RouterCommand::WorkDone {
+            agent_id: self.team_id,
+        };
+        if let Err(e) = self.parent.cast(done) {
+            log::warn!(
+                "Team {} failed to report finished work: {:?}",
+                self.team_id,
+                e
+            );
+        }
+    }
+
+    fn publish_result(&self, state: &mut TeamState, message: Message) {
+        let Some(parent_topic) = state.parent_topic.take() else {
+            return;
+        };
+        self.finish_work(state);
+
+        let result = Message {
+            model: message.model,
+            ..Message::new(
+                message.content,
+                Some(self.spec.name.clone()),
+                Role::Assistant,
+            )
+        };
+        let context = ActorContext::new()
+            .with_sender(self.team_id)
+            .with_topic(parent_topic.clone());
+
+        if let Err(e) = self.parent.cast(RouterCommand::RouteMessage {
+            topic: parent_topic,
+            message: result,
+            context,
+        }) {
+            log::warn!("Team {} failed to publish result: {:?}", self.team_id, e);
+        }
+        state.turns = 0;
+    }
+
+    fn set_members_listening(
+        &self,
+        state: &mut TeamState,
+        listening: bool,
+    ) -> Result<(), ActorProcessingErr> {
+        if state.members_listening == listening {
+            return Ok(());
+        }
+        for &agent_id in &state.member_ids {
+            let topic = self.spec.internal_topic.clone();
+            let command = if listening {
+                RouterCommand::SubscribeAgent {
+                    agent_id,
+                    topic,
+                    subscription: None,
+                }
+            } else {
+                RouterCommand::UnsubscribeAgent { agent_id, topic }
+            };
+            state.inner_router.cast(command)?;
+        }
+        state.members_listening = listening;
+        Ok(())
+    }
+}
+
+impl Actor for TeamActor {
+    type Msg = RouterCommand;
+    type State = TeamState;
+    type Arguments = ();
+
+    async fn pre_start(
+        &self,
+        myself: ActorRef<Self::Msg>,
+        _args: Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        let (inner_router, _) =
+            Actor::spawn_linked(None, RouterActor::default(), (), myself.get_cell())
+                .await
+                .map_err(|e| TeamError::RouterStart(e.to_string()))?;
+        inner_router.cast(RouterCommand::Ready)?;
+
+        let mut member_ids = Vec::with_capacity(self.spec.members.len());
+        for member in self.spec.members.iter().cloned() {
+            let mut llm_agent = LlmAgent::build(
+                member.system_prompt,
+                member.user_prompt_formatter,
+                None,
+                member.tools_map_meta,
+                member.description,
+            )
+            .map_err(|e| TeamError::MemberSpawn(e.to_string()))?;
+            if let Some(provider) = member.provider {
+                llm_agent = llm_agent.with_provider(provider);
+            }
+
+            let topic = self.spec.internal_topic.clone();
+            let response = inner_router
+                .call(
+                    |reply_to| RouterCommand::SpawnLlmAgent {
+                        llm_agent,
+                        topic,
+                        reply_to,
+                    },
+                    None,
+                )
+                .await
+                .map_err(|e| TeamError::MemberSpawn(e.to_string()))?;
+
+            match response {
+                CallResult::Success(Ok(agent_id)) => member_ids.push(agent_id),
+                CallResult::Success(Err(e)) => return Err(Box::new(TeamError::MemberSpawn(e))),
+                CallResult::Timeout => {
+                    return Err(Box::new(TeamError::MemberSpawn("timed out".into())))
+                }
+                CallResult::SenderError => {
+                    return Err(Box::new(TeamError::MemberSpawn("sender error".into())))
+                }
+            }
+        }
+
+        // The team listens on its own internal topic so it can watch the conversation
+        // and decide when to hand the result back to the parent.
+        inner_router.cast(RouterCommand::RegisterAgent {
+            agent_id: self.team_id,
+            agent_ref: myself,
+            topic: self.spec.internal_topic.clone(),
+        })?;
+
+        Ok(TeamState {
+            inner_router,
+            member_ids,
+            parent_topic: None,
+            pending_work: false,
+            turns: 0,
+            members_listening: true,
+        })
+    }
+
+    async fn post_stop(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        state.inner_router.stop(None);
+        Ok(())
+    }
+
+    async fn handle(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        msg: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match msg {
+            RouterCommand::RouteMessage {
+                topic,
+                message,
+                context,
+            } => {
+                if context.sender == Some(self.team_id) {
+                    return Ok(());
+                }
+
+                // Member replies arrive through the inner router; telling them apart by
+                // sender lets the internal topic share a name with the parent's topic
+                let from_member = context
+                    .sender
+                    .map_or(false, |sender| state.member_ids.contains(&sender));
+                if from_member {
+                    // A reply still in flight when the last task finished
+                    if state.parent_topic.is_none() {
+                        return Ok(());
+                    }
+                    state.turns += 1;
+                    if self.spec.termination.is_met(&message, state.turns) {
+                        self.publish_result(state, message);
+                        self.set_members_listening(state, false)?;
+                    }
+                    return Ok(());
+                }
+
+                log::debug!("Team {} received task on topic {}", self.team_id, topic);
+                // A new task replaces one still in progress
+                self.finish_work(state);
+                state.pending_work = context.is_work_item();
+                state.parent_topic = Some(topic);
+                state.turns = 0;
+                // The inner router handles commands in order, so the members are back
+                // on the internal topic before the task reaches it
+                self.set_members_listening(state, true)?;
+
+                let internal_topic = self.spec.internal_topic.clone();
+                state.inner_router.cast(RouterCommand::RouteMessage {
+                    topic: internal_topic.clone(),
+                    message,
+                    context: ActorContext::new()
+                        .with_sender(self.team_id)
+                        .with_topic(internal_topic),
+                })?;
+                Ok(())
+            }
+
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent_runtime::testing::{
+        collect, drain, publish, recv, spawn_team, start_router, Delivery, Inbox,
+    };
+    use crate::llama::mock::MockLlm;
+    use std::time::Duration;
+
+    const TEAM: &str = "writers";
+
+    fn member(description: &str, mock: impl Into<Arc<MockLlm>>) -> TeamMember {
+        let mock: Arc<MockLlm> = mock.into();
+        TeamMember::new("You are a helpful assistant.", description).with_provider(mock)
+    }
+
+    // The task itself also reaches the collector, so only the team's reply counts
+    async fn team_result(rx: &mut Inbox) -> Delivery {
+        loop {
+            let delivery = recv(rx).await;
+            if delivery.message.name.as_deref() == Some(TEAM) {
+                return delivery;
+            }
+        }
+    }
+
+    async fn run_team(spec: TeamSpec, tasks: &[&str]) -> (Delivery, Inbox) {
+        let router = start_router().await;
+        let (user, mut rx) = collect(&router, "tasks").await;
+        spawn_team(&router, spec, "tasks").await.unwrap();
+        for task in tasks {
+            publish(&router, "tasks", user, task);
+        }
+        let result = team_result(&mut rx).await;
+        (result, rx)
+    }
+
+    #[tokio::test]
+    async fn max_turns_ends_the_conversation() {
+        let writer = Arc::new(MockLlm::new().with_fallback("draft"));
+        let critic = Arc::new(MockLlm::new().with_fallback("notes"));
+        let spec = TeamSpec::new(TEAM, TerminationCondition::MaxTurns(3))
+            .with_member(member("writer", writer.clone()))
+            .with_member(member("critic", critic.clone()));
+        let calls = || writer.call_count() + critic.call_count();
+
+        let router = start_router().await;
+        let (user, mut rx) = collect(&router, "tasks").await;
+        spawn_team(&router, spec, "tasks").await.unwrap();
+        publish(&router, "tasks", user, "write a haiku");
+        let result = team_result(&mut rx).await;
+        assert_eq!(result.topic, "tasks");
+        assert_eq!(result.message.role, Role::Assistant);
+
+        // Once the task is answered the members stop talking to each other
+        drain(&mut rx).await;
+        let settled = calls();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(calls(), settled);
+
+        // and the next task starts a new conversation
+        publish(&router, "tasks", user, "write another");
+        team_result(&mut rx).await;
+        assert!(calls() > settled);
+    }
+
+    #[tokio::test]
+    async fn text_mention_ends_the_conversation() {
+        let critic = MockLlm::new().with_closure(|_, input| {
+            Some(
+                if input.contains("draft") {
+                    "APPROVED"
+                } else {
+                    "waiting for a draft"
+                }
+                .to_string(),
+            )
+        });
+        let spec = TeamSpec::new(TEAM, TerminationCondition::TextMention("APPROVED".into()))
+            .with_member(member("writer", MockLlm::new().with_fallback("draft")))
+            .with_member(member("critic", critic));
+
+        let (result, _) = run_team(spec, &["write a haiku"]).await;
+        assert_eq!(result.message.content.content_to_string(), "APPROVED");
+    }
+
+    #[tokio::test]
+    async fn custom_condition_ends_the_conversation() {
+        let finished: TerminationFn =
+            Arc::new(|message, _| message.content.content_to_string().ends_with('.'));
+        let writer = MockLlm::new().with_responses(["still thinking", "Done."]);
+        let spec = TeamSpec::new(TEAM, TerminationCondition::Custom(finished))
+            .with_member(member("writer", writer));
+
+        // The first task's only reply doesn't meet the condition, the second's does
+        let (result, _) = run_team(spec, &["first", "second"]).await;
+        assert_eq!(result.message.content.content_to_string(), "Done.");
+    }
+
+    #[tokio::test]
+    async fn any_condition_ends_on_the_first_met() {
+        let termination = TerminationCondition::Any(vec![
+            TerminationCondition::MaxTurns(10),
+            TerminationCondition::TextMention("DONE".into()),
+        ]);
+        let spec = TeamSpec::new(TEAM, termination)
+            .with_member(member("writer", MockLlm::new().with_response("DONE")));
+
+        let (result, _) = run_team(spec, &["write a haiku"]).await;
+        assert_eq!(result.message.content.content_to_string(), "DONE");
+    }
+
+    #[tokio::test]
+    async fn internal_topic_may_share_the_parent_topic_name() {
+        let spec = TeamSpec::new(TEAM, TerminationCondition::MaxTurns(1))
+            .with_internal_topic(TopicId::from("tasks"))
+            .with_member(member("writer", MockLlm::new().with_response("a haiku")));
+
+        let (result, _) = run_team(spec, &["write a haiku"]).await;
+        assert_eq!(result.topic, "tasks");
+        assert_eq!(result.message.content.content_to_string(), "a haiku");
+    }
+}
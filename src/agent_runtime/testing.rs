@@ -0,0 +1,187 @@
+use crate::agent_runtime::{
+    router::RouterActor, team::TeamSpec, ActorContext, AgentId, RouterCommand, SpawnAgentResponse,
+    TopicId,
+};
+use crate::immutable_agent::{LlmAgent, Message};
+use ractor::{rpc::CallResult, Actor, ActorProcessingErr, ActorRef};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Delivery {
+    pub topic: TopicId,
+    pub message: Message,
+    pub context: ActorContext,
+}
+
+// Stands in for a subscriber, or for the router when handed to an actor as its router,
+// and passes on every message routed to it and every work acknowledgement
+pub struct Collector(UnboundedSender<RouterCommand>);
+
+pub type Inbox = UnboundedReceiver<RouterCommand>;
+
+impl Actor for Collector {
+    type Msg = RouterCommand;
+    type State = ();
+    type Arguments = ();
+
+    async fn pre_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        _args: Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        Ok(())
+    }
+
+    async fn handle(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        msg: Self::Msg,
+        _state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        if matches!(
+            msg,
+            RouterCommand::RouteMessage { .. } | RouterCommand::WorkDone { .. }
+        ) {
+            let _ = self.0.send(msg);
+        }
+        Ok(())
+    }
+}
+
+pub async fn start_router() -> ActorRef<RouterCommand> {
+    let (router, _) = Actor::spawn(None, RouterActor::default(), ())
+        .await
+        .expect("router starts");
+    router.cast(RouterCommand::Ready).unwrap();
+    router
+}
+
+pub async fn spawn_collector() -> (ActorRef<RouterCommand>, Inbox) {
+    let (tx, rx) = unbounded_channel();
+    let (collector, _) = Actor::spawn(None, Collector(tx), ())
+        .await
+        .expect("collector starts");
+    (collector, rx)
+}
+
+pub async fn collect(router: &ActorRef<RouterCommand>, topic: &str) -> (AgentId, Inbox) {
+    let (collector, rx) = spawn_collector().await;
+    let agent_id = AgentId::new_v4();
+    router
+        .cast(RouterCommand::RegisterAgent {
+            agent_id,
+            agent_ref: collector,
+            topic: topic.to_string(),
+        })
+        .unwrap();
+    (agent_id, rx)
+}
+
+pub async fn spawn_llm_agent(
+    router: &ActorRef<RouterCommand>,
+    llm_agent: LlmAgent,
+    topic: &str,
+) -> SpawnAgentResponse {
+    let topic = topic.to_string();
+    match router
+        .call(
+            |reply_to| RouterCommand::SpawnLlmAgent {
+                llm_agent,
+                topic,
+                reply_to,
+            },
+            Some(RECV_TIMEOUT),
+        )
+        .await
+        .expect("router answers")
+    {
+        CallResult::Success(response) => response,
+        other => panic!("spawn call failed: {:?}", other),
+    }
+}
+
+pub async fn spawn_team(
+    router: &ActorRef<RouterCommand>,
+    spec: TeamSpec,
+    topic: &str,
+) -> SpawnAgentResponse {
+    let topic = topic.to_string();
+    match router
+        .call(
+            |reply_to| RouterCommand::SpawnTeam {
+                spec,
+                topic,
+                reply_to,
+            },
+            Some(RECV_TIMEOUT),
+        )
+        .await
+        .expect("router answers")
+    {
+        CallResult::Success(response) => response,
+        other => panic!("spawn call failed: {:?}", other),
+    }
+}
+
+pub fn publish(router: &ActorRef<RouterCommand>, topic: &str, sender: AgentId, text: &str) {
+    router
+        .cast(RouterCommand::RouteMessage {
+            topic: topic.to_string(),
+            message: Message::new(
+                crate::llama::Content::Text(text.to_string()),
+                None,
+                async_openai::types::Role::User,
+            ),
+            context: ActorContext::new()
+                .with_sender(sender)
+                .with_topic(topic.to_string()),
+        })
+        .unwrap();
+}
+
+fn delivery(msg: RouterCommand) -> Option<Delivery> {
+    match msg {
+        RouterCommand::RouteMessage {
+            topic,
+            message,
+            context,
+        } => Some(Delivery {
+            topic,
+            message,
+            context,
+        }),
+        _ => None,
+    }
+}
+
+// The next routed message, skipping acknowledgements
+pub async fn recv(rx: &mut Inbox) -> Delivery {
+    loop {
+        let msg = tokio::time::timeout(RECV_TIMEOUT, rx.recv())
+            .await
+            .expect("a delivery arrives in time")
+            .expect("the collector is still running");
+        if let Some(delivery) = delivery(msg) {
+            return delivery;
+        }
+    }
+}
+
+// Waits until nothing more arrives for a moment and returns everything that did
+pub async fn drain_all(rx: &mut Inbox) -> Vec<RouterCommand> {
+    let mut received = Vec::new();
+    while let Ok(Some(msg)) = tokio::time::timeout(Duration::from_millis(300), rx.recv()).await {
+        received.push(msg);
+    }
+    received
+}
+
+pub async fn drain(rx: &mut Inbox) -> Vec<Delivery> {
+    drain_all(rx)
+        .await
+        .into_iter()
+        .filter_map(delivery)
+        .collect()
+}
@@ -0,0 +1,377 @@
+use crate::immutable_agent::{DefaultMethodError, LlmAgent};
+use crate::llama::plan::{validate_plan, PlanError};
+use crate::llama::{Content, StructuredText, Task};
+use crate::STORE;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::collections::HashMap;
+use thiserror::Error;
+
+const DEFAULT_MAX_DEPTH: usize = 3;
+const DEFAULT_MAX_BREADTH: usize = 6;
+
+#[derive(Debug, Error)]
+pub enum DecomposeError {
+    #[error("Planner failed: {0}")]
+    Planner(#[from] DefaultMethodError),
+    #[error("Planner did not return a task list: {0}")]
+    NotATaskList(String),
+    #[error("Planner returned invalid subtasks: {}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; "))]
+    InvalidPlan(Vec<PlanError>),
+    #[error("Subtask {task} depends on {dependency}, which was dropped at the breadth limit")]
+    DroppedDependency { task: String, dependency: String },
+}
+
+// One task of a decomposition tree. Ids are positional and hierarchical ("2", "2.1",
+// "2.1.3"), and `depends_on` only names siblings; `flatten` turns that into plan-wide
+// dependencies. The root stands for the goal itself and has an empty id.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanNode {
+    pub task: Task,
+    pub depth: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<PlanNode>,
+}
+
+impl PlanNode {
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    pub fn leaves(&self) -> Vec<&PlanNode> {
+        if self.is_leaf() {
+            return vec![self];
+        }
+        self.children.iter().flat_map(|c| c.leaves()).collect()
+    }
+
+    // The leaves as an executable plan, in tree order. A leaf waits for everything its
+    // own node and each of its ancestors depended on, and depending on a composite
+    // task means depending on all of that task's leaves.
+    pub fn flatten(&self) -> Vec<Task> {
+        let mut leaves_of: HashMap<&str, Vec<String>> = HashMap::new();
+        self.collect_leaves(&mut leaves_of);
+
+        let mut tasks = Vec::new();
+        self.flatten_into(&[], &leaves_of, &mut tasks);
+        tasks
+    }
+
+    fn collect_leaves<'a>(&'a self, leaves_of: &mut HashMap<&'a str, Vec<String>>) {
+        for child in &self.children {
+            child.collect_leaves(leaves_of);
+        }
+        let leaves = self.leaves().iter().map(|l| l.task.id.clone()).collect();
+        leaves_of.insert(self.task.id.as_str(), leaves);
+    }
+
+    fn flatten_into(
+        &self,
+        inherited: &[String],
+        leaves_of: &HashMap<&str, Vec<String>>,
+        tasks: &mut Vec<Task>,
+    ) {
+        let mut depends_on = inherited.to_vec();
+        for dependency in &self.task.depends_on {
+            for leaf in leaves_of.get(dependency.as_str()).into_iter().flatten() {
+                if !depends_on.contains(leaf) {
+                    depends_on.push(leaf.clone());
+                }
+            }
+        }
+
+        if self.is_leaf() {
+            let mut task = self.task.clone();
+            task.depends_on = depends_on;
+            task.complex = false;
+            tasks.push(task);
+            return;
+        }
+        for child in &self.children {
+            child.flatten_into(&depends_on, leaves_of, tasks);
+        }
+    }
+
+    // An indented outline for reviewing the tree before running it
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.render_into(&mut out);
+        out
+    }
+
+    fn render_into(&self, out: &mut String) {
+        if self.task.id.is_empty() {
+            out.push_str(&format!("{}\n", self.task.name));
+        } else {
+            out.push_str(&format!(
+                "{}[{}] {}",
+                "  ".repeat(self.depth.saturating_sub(1)),
+                self.task.id,
+                self.task.name
+            ));
+            if let Some(tool) = &self.task.tool {
+                out.push_str(&format!(" (tool: {})", tool));
+            }
+            if !self.task.depends_on.is_empty() {
+                out.push_str(&format!(" (after {})", self.task.depends_on.join(", ")));
+            }
+            out.push('\n');
+        }
+        for child in &self.children {
+            child.render_into(out);
+        }
+    }
+}
+
+// Hierarchical planning: the planner breaks the goal into subtasks, then every
+// subtask it marks `complex` into subtasks of its own, until each leaf is a tool call
+// or a single LLM step or `max_depth` is reached. Each request asks for at most
+// `max_breadth` subtasks, and any beyond that are dropped.
+//
+// The planner is expected to be built with `TEMPLATE_SYSTEM_PROMPT_DECOMPOSER` and
+// `TEMPLATE_USER_PROMPT_DECOMPOSE_JSON`, which ask for the `complex` flag.
+pub struct Decomposer {
+    planner: LlmAgent,
+    max_depth: usize,
+    max_breadth: usize,
+}
+
+impl Decomposer {
+    pub fn new(planner: LlmAgent) -> Self {
+        Self {
+            planner,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_breadth: DEFAULT_MAX_BREADTH,
+        }
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth.max(1);
+        self
+    }
+
+    pub fn with_max_breadth(mut self, max_breadth: usize) -> Self {
+        self.max_breadth = max_breadth.max(1);
+        self
+    }
+
+    // Only a failure to split the goal itself is an error; a subtask the planner can't
+    // split stays a leaf
+    pub async fn decompose(&self, goal: &str) -> Result<PlanNode, DecomposeError> {
+        let root = Task {
+            id: String::new(),
+            name: goal.to_string(),
+            description: goal.to_string(),
+            tool: None,
+            arguments: None,
+            depends_on: Vec::new(),
+            expected_output: None,
+            acceptance_criteria: Vec::new(),
+            complex: true,
+        };
+        let children = self.subtasks(goal, &[], &root).await?;
+        let mut node = PlanNode {
+            task: root,
+            depth: 0,
+            children: Vec::new(),
+        };
+        for child in children {
+            let child = self.expand(goal, vec![], child, 1).await;
+            node.children.push(child);
+        }
+        Ok(node)
+    }
+
+    fn expand<'a>(
+        &'a self,
+        goal: &'a str,
+        mut ancestors: Vec<String>,
+        task: Task,
+        depth: usize,
+    ) -> BoxFuture<'a, PlanNode> {
+        Box::pin(async move {
+            let mut node = PlanNode {
+                task,
+                depth,
+                children: Vec::new(),
+            };
+            if !node.task.complex || node.task.tool.is_some() || depth >= self.max_depth {
+                return node;
+            }
+
+            let children = match self.subtasks(goal, &ancestors, &node.task).await {
+                Ok(children) if children.len() > 1 => children,
+                Ok(_) => return node,
+                Err(e) => {
+                    log::warn!("decompose: keeping {} as a leaf: {}", node.task.id, e);
+                    return node;
+                }
+            };
+            ancestors.push(node.task.name.clone());
+            for child in children {
+                let child = self.expand(goal, ancestors.clone(), child, depth + 1).await;
+                node.children.push(child);
+            }
+            node
+        })
+    }
+
+    // Asks for `parent`'s direct subtasks and renumbers them by position under its id.
+    // A kept subtask that needs one cut at the breadth limit fails the whole list
+    // rather than running without its input.
+    async fn subtasks(
+        &self,
+        goal: &str,
+        ancestors: &[String],
+        parent: &Task,
+    ) -> Result<Vec<Task>, DecomposeError> {
+        let prompt = if parent.id.is_empty() {
+            format!(
+                "Break this goal down into at most {} subtasks: {}",
+                self.max_breadth, goal
+            )
+        } else {
+            let mut path = ancestors.to_vec();
+            path.push(parent.name.clone());
+            format!(
+                "Overall goal: {}\nTask path: {}\n\n\
+                 Break this task down into at most {} subtasks: {}\n{}",
+                goal,
+                path.join(" > "),
+                self.max_breadth,
+                parent.name,
+                parent.description
+            )
+        };
+
+        let response = self.planner.default_method(&prompt).await?;
+        let Content::Structured(StructuredText::Tasks(mut tasks)) = response.content else {
+            return Err(DecomposeError::NotATaskList(
+                response.content.content_to_string(),
+            ));
+        };
+        let errors = {
+            let registry = STORE.lock().unwrap();
+            validate_plan(&tasks, |tool| registry.contains_key(tool))
+        };
+        if !errors.is_empty() {
+            return Err(DecomposeError::InvalidPlan(errors));
+        }
+
+        if tasks.len() > self.max_breadth {
+            log::info!(
+                "decompose: dropping {} subtasks of {:?} past the breadth limit",
+                tasks.len() - self.max_breadth,
+                parent.name
+            );
+            tasks.truncate(self.max_breadth);
+        }
+        // Ids are unique once validated, so this maps each one to its position
+        let position: HashMap<String, usize> = tasks
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (t.id.clone(), i))
+            .collect();
+        for task in &tasks {
+            if let Some(dependency) = task.depends_on.iter().find(|d| !position.contains_key(*d)) {
+                return Err(DecomposeError::DroppedDependency {
+                    task: task.id.clone(),
+                    dependency: dependency.clone(),
+                });
+            }
+        }
+
+        let prefix = if parent.id.is_empty() {
+            String::new()
+        } else {
+            format!("{}.", parent.id)
+        };
+        let renumber = |i: usize| format!("{}{}", prefix, i + 1);
+        for (i, task) in tasks.iter_mut().enumerate() {
+            task.id = renumber(i);
+            task.depends_on = task
+                .depends_on
+                .iter()
+                .map(|d| renumber(position[d]))
+                .collect();
+        }
+        Ok(tasks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llama::mock::MockLlm;
+    use crate::{TEMPLATE_SYSTEM_PROMPT_DECOMPOSER, TEMPLATE_USER_PROMPT_DECOMPOSE_JSON};
+    use std::sync::Arc;
+
+    fn decomposer(mock: MockLlm) -> Decomposer {
+        let planner = LlmAgent::build(
+            TEMPLATE_SYSTEM_PROMPT_DECOMPOSER.to_string(),
+            Some(TEMPLATE_USER_PROMPT_DECOMPOSE_JSON.clone()),
+            None,
+            None,
+            "planner".to_string(),
+        )
+        .unwrap()
+        .with_provider(Arc::new(mock));
+        Decomposer::new(planner)
+    }
+
+    #[tokio::test]
+    async fn subtasks_are_renumbered_by_position() {
+        let mock = MockLlm::new().with_response(
+            r#"[
+              {"id": "fetch", "name": "Fetch", "description": "Fetch the data"},
+              {"id": "sum", "name": "Sum", "description": "Add it up", "depends_on": ["fetch"]}
+            ]"#,
+        );
+        let tree = decomposer(mock).decompose("Total the sales").await.unwrap();
+
+        let ids: Vec<_> = tree.children.iter().map(|c| c.task.id.as_str()).collect();
+        assert_eq!(ids, ["1", "2"]);
+        assert_eq!(tree.children[1].task.depends_on, ["1"]);
+    }
+
+    #[tokio::test]
+    async fn dependency_on_a_dropped_subtask_is_rejected() {
+        let mock = MockLlm::new().with_response(
+            r#"[
+              {"id": "1", "name": "Report", "description": "Write it up", "depends_on": ["3"]},
+              {"id": "2", "name": "Check", "description": "Check the inputs"},
+              {"id": "3", "name": "Fetch", "description": "Fetch the data"}
+            ]"#,
+        );
+        let err = decomposer(mock)
+            .with_max_breadth(2)
+            .decompose("Report on sales")
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            DecomposeError::DroppedDependency { ref task, ref dependency }
+                if task == "1" && dependency == "3"
+        ));
+    }
+
+    #[tokio::test]
+    async fn subtasks_within_the_breadth_limit_are_kept() {
+        let mock = MockLlm::new().with_response(
+            r#"[
+              {"id": "1", "name": "Fetch", "description": "Fetch the data"},
+              {"id": "2", "name": "Sum", "description": "Add it up", "depends_on": ["1"]},
+              {"id": "3", "name": "Chart", "description": "Draw a chart"}
+            ]"#,
+        );
+        let tree = decomposer(mock)
+            .with_max_breadth(2)
+            .decompose("Total the sales")
+            .await
+            .unwrap();
+
+        assert_eq!(tree.children.len(), 2);
+        assert_eq!(tree.flatten()[1].depends_on, ["1"]);
+    }
+}
@@ -1,15 +1,30 @@
 use crate::agent_runtime::{agent::AgentActor, AgentId, TopicId};
 use crate::llama::{
-    chat_inner_async_wrapper,
-    llama_utils::{extract_json_from_xml_like, extract_tool_call_json, parse_planning_tasks},
-    Content, LlamaResponseError, LlamaResponseMessage, StructuredText, Task, ToolCall,
+    cache::{CachedProvider, ResponseCache},
+    cassette::{Cassette, CassetteMode, CassetteProvider},
+    context::{fit_to_context, ContextError, TrimStrategy},
+    llama_utils::{parse_planning_tasks, tools_from_meta},
+    plan::{self, validate_plan},
+    provider::{provider_for, ChatRequest, LlmProvider},
+    retry::RetryPolicy,
+    structured::{tasks_from_value, OutputSchema},
+    tokens::Tokenizer,
+    tool_call_parser::extract_tool_calls,
+    ChatInnerError, Content, LlamaResponseError, LlamaResponseMessage, StructuredText, Task,
+    ToolCall,
 };
+use crate::use_tool::Tool;
 use crate::{
     FormatterFn, LlmConfig, STORE, TEMPLATE_SYSTEM_PROMPT_PLANNER, TEMPLATE_SYSTEM_PROMPT_TOOL_USE,
     TEMPLATE_USER_PROMPT_TASK_JSON, TOGETHER_CONFIG,
 };
-use async_openai::types::CompletionUsage;
-use async_openai::types::Role;
+use async_openai::types::{
+    ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessage,
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
+    ChatCompletionRequestToolMessage, ChatCompletionRequestUserMessage, ChatCompletionTool,
+    ChatCompletionToolChoiceOption, ChatCompletionToolType, CompletionUsage, FunctionCall, Role,
+};
+use futures::StreamExt;
 use log::debug;
 use ractor::ActorRef;
 use serde::{Deserialize, Serialize};
@@ -25,11 +40,18 @@ use tokio::io::{stdin, AsyncBufReadExt, BufReader};
 use tokio::time::{timeout, Duration};
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Message {
     pub content: Content,
     pub name: Option<String>,
     pub role: Role,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    // The model behind an agent's reply, see `LlamaResponseMessage::model`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
 }
 
 impl Default for Message {
@@ -38,6 +60,8 @@ impl Default for Message {
             content: Content::Text("placeholder".to_string()),
             name: None,
             role: Role::User,
+            tool_call_id: None,
+            model: None,
         }
     }
 }
@@ -48,6 +72,64 @@ impl Message {
             content,
             name,
             role,
+            tool_call_id: None,
+            model: None,
+        }
+    }
+
+    pub fn tool_result(tool_call_id: Option<String>, name: String, output: String) -> Self {
+        Message {
+            content: Content::Text(output),
+            name: Some(name),
+            role: Role::Tool,
+            tool_call_id,
+            model: None,
+        }
+    }
+
+    pub fn to_request_message(&self) -> ChatCompletionRequestMessage {
+        let text = self.content.content_to_string();
+        match self.role {
+            Role::System => ChatCompletionRequestSystemMessage {
+                content: text,
+                name: self.name.clone(),
+            }
+            .into(),
+            Role::Assistant => match &self.content {
+                Content::Structured(StructuredText::ToolCall(tool_call)) => {
+                    ChatCompletionRequestAssistantMessage {
+                        content: None,
+                        name: self.name.clone(),
+                        tool_calls: Some(vec![ChatCompletionMessageToolCall {
+                            id: tool_call.id.clone().unwrap_or_default(),
+                            r#type: ChatCompletionToolType::Function,
+                            function: FunctionCall {
+                                name: tool_call.name.clone(),
+                                arguments: tool_call.arguments_json(),
+                            },
+                        }]),
+                        function_call: None,
+                    }
+                    .into()
+                }
+                _ => ChatCompletionRequestAssistantMessage {
+                    content: Some(text),
+                    name: self.name.clone(),
+                    tool_calls: None,
+                    function_call: None,
+                }
+                .into(),
+            },
+            Role::Tool | Role::Function => ChatCompletionRequestToolMessage {
+                content: text,
+                tool_call_id: self.tool_call_id.clone().unwrap_or_default(),
+            }
+            .into(),
+            Role::User => ChatCompletionRequestUserMessage {
+                content: text.into(),
+                name: self.name.clone(),
+            }
+            .into(),
         }
     }
 }
@@ -67,7 +149,7 @@ pub enum DefaultMethodError {
     IoError(#[from] std::io::Error),
 
     #[error("LLM API error: {0}")]
-    LlmApiError(String),
+    LlmApiError(#[from] ChatInnerError),
 
     #[error("Tool execution error: {0}")]
     ToolExecutionError(String),
@@ -77,8 +159,102 @@ pub enum DefaultMethodError {
 
     #[error("Tool not found: {0}")]
     ToolNotFound(String),
+
+    #[error("Tool {name} timed out after {timeout:?}")]
+    ToolTimeout { name: String, timeout: Duration },
+
+    #[error("Context window exceeded: {0}")]
+    ContextOverflow(#[from] ContextError),
+
+    #[error("Agent loop stopped before a final answer: {0}")]
+    LoopLimit(String),
+
+    #[error("Output failed schema validation after {attempts} attempts: {errors}")]
+    SchemaViolation { attempts: u32, errors: String },
 }
 
+impl DefaultMethodError {
+    // Failures another model might not have: the API gave up (after retries, timeouts
+    // included), the output could not be parsed, or the prompt did not fit. Tool
+    // failures and loop limits would recur with any model.
+    pub fn warrants_fallback(&self) -> bool {
+        matches!(
+            self,
+            DefaultMethodError::LlmApiError(_)
+                | DefaultMethodError::ParsingError(_)
+                | DefaultMethodError::SchemaViolation { .. }
+                | DefaultMethodError::ContextOverflow(_)
+        )
+    }
+}
+
+// One step of a tool loop, reported as it happens
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TraceStep {
+    Thought {
+        iteration: usize,
+        text: String,
+    },
+    ToolCall {
+        iteration: usize,
+        call: ToolCall,
+    },
+    Observation {
+        iteration: usize,
+        name: String,
+        output: String,
+        is_error: bool,
+    },
+    FinalAnswer {
+        iteration: usize,
+        text: String,
+    },
+    Stopped {
+        iteration: usize,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReactConfig {
+    pub max_iterations: usize,
+    // Budget across every LLM call of one loop, prompt tokens included
+    pub max_total_tokens: Option<u32>,
+}
+
+impl Default for ReactConfig {
+    fn default() -> Self {
+        Self {
+            max_iterations: 6,
+            max_total_tokens: None,
+        }
+    }
+}
+
+impl ReactConfig {
+    pub fn new(max_iterations: usize) -> Self {
+        Self {
+            max_iterations,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_token_budget(mut self, max_total_tokens: u32) -> Self {
+        self.max_total_tokens = Some(max_total_tokens);
+        self
+    }
+}
+
+const SUMMARY_PROMPT: &str = "Summarise the following conversation excerpt in a few sentences. \
+Keep every fact, decision and tool result that later turns may rely on.";
+const SUMMARY_MAX_TOKENS: u16 = 400;
+const PLAN_MAX_CORRECTIONS: u32 = 2;
+
+const REACT_INSTRUCTIONS: &str =
+    "Call tools as many times as you need; their results will be sent back to you. \
+Once you have enough information, reply with the final answer and no tool call.";
+
 #[derive(Clone)]
 pub struct LlmAgent {
     pub system_prompt: String,
@@ -86,7 +262,42 @@ pub struct LlmAgent {
     pub llm_config: Option<LlmConfig>,
     pub tools_map_meta: Option<Value>,
     pub description: String,
+    pub stream_topic: Option<TopicId>,
     tool_names: Vec<String>,
+    provider: Option<Arc<dyn LlmProvider>>,
+    retry_policy: RetryPolicy,
+    output_schema: Option<OutputSchema>,
+    tool_timeout: Duration,
+    react: Option<ReactConfig>,
+    trim_strategy: TrimStrategy,
+    response_cache: Option<Arc<ResponseCache>>,
+    cassette: Option<Arc<Cassette>>,
+    fallback_configs: Vec<LlmConfig>,
+}
+
+const DEFAULT_TOOL_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Tools are looked up in the global registry, not just this agent's own
+fn plan_errors(tasks: &[Task]) -> Vec<String> {
+    let registry = STORE.lock().unwrap();
+    validate_plan(tasks, |tool| registry.contains_key(tool))
+        .iter()
+        .map(|e| e.to_string())
+        .collect()
+}
+
+fn answered_by(mut response: LlamaResponseMessage, config: &LlmConfig) -> LlamaResponseMessage {
+    response.model = Some(config.model.clone());
+    response
+}
+
+// Clones the tool out of the registry so the lock is not held while it runs
+fn lookup_tool(name: &str) -> StdResult<Tool, DefaultMethodError> {
+    STORE.lock().unwrap().get(name).cloned().ok_or_else(|| {
+        let error_msg = format!("Tool {} not found", name);
+        eprintln!("{}", error_msg);
+        DefaultMethodError::ToolNotFound(error_msg)
+    })
 }
 
 impl LlmAgent {
@@ -137,13 +348,868 @@ impl LlmAgent {
             llm_config,
             description,
             tools_map_meta,
+            stream_topic: None,
             tool_names,
+            provider: None,
+            retry_policy: RetryPolicy::default(),
+            output_schema: None,
+            tool_timeout: DEFAULT_TOOL_TIMEOUT,
+            react: None,
+            trim_strategy: TrimStrategy::default(),
+            response_cache: None,
+            cassette: None,
+            fallback_configs: Vec::new(),
+        })
+    }
+
+    pub fn with_stream_topic(mut self, topic: TopicId) -> Self {
+        self.stream_topic = Some(topic);
+        self
+    }
+
+    // Only free-text answers are streamed; tool calls, plans and schema-checked output
+    // need the full body to parse
+    pub fn can_stream(&self) -> bool {
+        self.stream_topic.is_some()
+            && self.tool_names.is_empty()
+            && !self.is_planner()
+            && self.output_schema.is_none()
+            && self.react.is_none()
+    }
+
+    fn is_planner(&self) -> bool {
+        self.user_prompt_formatter.is_some() && self.description.to_lowercase().contains("plan")
+    }
+
+    // The primary config followed by the fallbacks, in the order they are tried
+    fn model_chain(&self) -> Vec<&LlmConfig> {
+        std::iter::once(self.llm_config.as_ref().unwrap_or(&TOGETHER_CONFIG))
+            .chain(&self.fallback_configs)
+            .collect()
+    }
+
+    // An explicitly injected provider wins; otherwise the config decides which API to speak
+    fn provider(&self, config: &LlmConfig) -> Arc<dyn LlmProvider> {
+        let provider = self
+            .provider
+            .clone()
+            .unwrap_or_else(|| provider_for(config));
+        let provider: Arc<dyn LlmProvider> = match &self.response_cache {
+            Some(cache) => Arc::new(CachedProvider::new(provider, cache.clone())),
+            None => provider,
+        };
+        // Outermost, so a recording also captures exchanges the cache answered
+        match &self.cassette {
+            Some(cassette) => Arc::new(CassetteProvider::new(provider, cassette.clone())),
+            None => provider,
+        }
+    }
+
+    fn prompt_messages(&self, user_prompt: &str) -> Vec<Message> {
+        vec![
+            Message::new(
+                Content::Text(self.system_prompt.clone()),
+                None,
+                Role::System,
+            ),
+            Message::new(Content::Text(user_prompt.to_string()), None, Role::User),
+        ]
+    }
+
+    fn build_user_prompt(&self, input: &str) -> String {
+        match &self.user_prompt_formatter {
+            None => format!("here is your task: {}", input),
+            Some(f) => {
+                let formatter = f.lock().unwrap();
+                formatter(&[
+                    &input,
+                    &self
+                        .tools_map_meta
+                        .clone()
+                        .unwrap_or(Value::String(String::new()))
+                        .to_string(),
+                ])
+            }
+        }
+    }
+
+    // Trims the conversation to `context_size` before a call, counting the tool schemas
+    // sent alongside and reserving room for the reply. Returns the trimmed messages and
+    // the reply budget, which shrinks when the untrimmable prompt leaves less room.
+    async fn fit_context(
+        &self,
+        provider: &Arc<dyn LlmProvider>,
+        config: &LlmConfig,
+        messages: Vec<Message>,
+        tools: &[ChatCompletionTool],
+        max_token: u16,
+    ) -> StdResult<(Vec<Message>, u16), DefaultMethodError> {
+        let tokenizer = Tokenizer::for_config(config);
+        let tool_tokens = if tools.is_empty() {
+            0
+        } else {
+            serde_json::to_string(tools).map_or(0, |schemas| tokenizer.count(&schemas))
+        };
+
+        let fitted = fit_to_context(
+            messages,
+            &tokenizer,
+            config.context_size,
+            tool_tokens,
+            max_token,
+            &self.trim_strategy,
+        )?;
+        if fitted.dropped.is_empty() {
+            return Ok((fitted.messages, fitted.max_token));
+        }
+        log::debug!(
+            "context: dropped {} messages to fit {} tokens",
+            fitted.dropped.len(),
+            config.context_size
+        );
+        if self.trim_strategy != TrimStrategy::SummarizeOverflow {
+            return Ok((fitted.messages, fitted.max_token));
+        }
+
+        let summary = self.summarize(provider, config, &fitted.dropped).await?;
+        let summary = format!("Summary of the earlier conversation:\n{}", summary);
+        let mut messages = fitted.messages;
+        match messages.first_mut() {
+            Some(first) if first.role == Role::System => {
+                first.content = Content::Text(format!(
+                    "{}\n\n{}",
+                    first.content.content_to_string(),
+                    summary
+                ));
+            }
+            _ => messages.insert(0, Message::new(Content::Text(summary), None, Role::System)),
+        }
+
+        // The summary takes room too; whatever still doesn't fit is dropped outright
+        let fitted = fit_to_context(
+            messages,
+            &tokenizer,
+            config.context_size,
+            tool_tokens,
+            max_token,
+            &TrimStrategy::DropOldest,
+        )?;
+        Ok((fitted.messages, fitted.max_token))
+    }
+
+    async fn summarize(
+        &self,
+        provider: &Arc<dyn LlmProvider>,
+        config: &LlmConfig,
+        dropped: &[Message],
+    ) -> StdResult<String, DefaultMethodError> {
+        let transcript = dropped
+            .iter()
+            .map(|m| format!("{:?}: {}", m.role, m.content.content_to_string()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        // The excerpt must fit in one request itself; keep its most recent part
+        let max_chars = config
+            .context_size
+            .saturating_sub(SUMMARY_MAX_TOKENS as usize * 2)
+            * 3;
+        let skip = transcript.chars().count().saturating_sub(max_chars);
+        let transcript = transcript.chars().skip(skip).collect::<String>();
+
+        let messages = vec![
+            Message::new(
+                Content::Text(SUMMARY_PROMPT.to_string()),
+                None,
+                Role::System,
+            ),
+            Message::new(Content::Text(transcript), None, Role::User),
+        ];
+        let response = self
+            .retry_policy
+            .run(|| provider.chat(config, ChatRequest::new(&messages, SUMMARY_MAX_TOKENS)))
+            .await?;
+        Ok(response.content_to_string())
+    }
+
+    // Falls through to the next model only while nothing has been streamed; deltas
+    // already forwarded can't be taken back
+    pub async fn stream_method<F>(
+        &self,
+        input: &str,
+        mut on_delta: F,
+    ) -> StdResult<LlamaResponseMessage, DefaultMethodError>
+    where
+        F: FnMut(&str) + Send,
+    {
+        if !self.can_stream() {
+            return self.default_method(input).await;
+        }
+
+        let mut last_error = None;
+        for config in self.model_chain() {
+            let mut streamed = false;
+            let result = self
+                .stream_with(input, config, |delta| {
+                    streamed = true;
+                    on_delta(delta)
+                })
+                .await;
+            match result {
+                Ok(response) => return Ok(answered_by(response, config)),
+                Err(e) if e.warrants_fallback() && !streamed => {
+                    log::warn!("Model {} failed: {}", config.model, e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.expect("model chain is never empty"))
+    }
+
+    async fn stream_with<F>(
+        &self,
+        input: &str,
+        config: &LlmConfig,
+        mut on_delta: F,
+    ) -> StdResult<LlamaResponseMessage, DefaultMethodError>
+    where
+        F: FnMut(&str) + Send,
+    {
+        let user_prompt = self.build_user_prompt(input);
+        let max_token = config.sampling.max_tokens;
+
+        let provider = self.provider(config);
+        let (messages, max_token) = self
+            .fit_context(
+                &provider,
+                config,
+                self.prompt_messages(&user_prompt),
+                &[],
+                max_token,
+            )
+            .await?;
+
+        // Only opening the stream is retried; deltas already forwarded can't be taken back
+        let mut stream = self
+            .retry_policy
+            .run(|| provider.chat_stream(config, ChatRequest::new(&messages, max_token)))
+            .await?;
+
+        let mut assembled = String::new();
+        let mut usage = CompletionUsage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens: 0,
+        };
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            if let Some(chunk_usage) = chunk.usage {
+                usage = chunk_usage;
+            }
+            for choice in chunk.choices {
+                if let Some(delta) = choice.delta.content.filter(|d| !d.is_empty()) {
+                    on_delta(&delta);
+                    assembled.push_str(&delta);
+                }
+            }
+        }
+
+        Ok(LlamaResponseMessage {
+            content: Content::Text(assembled),
+            role: Role::Assistant,
+            usage,
+            tool_calls: Vec::new(),
+            tool_results: Vec::new(),
+            model: None,
         })
     }
 
+    // Models to try in order when the primary config fails, see
+    // `DefaultMethodError::warrants_fallback`; the response's `model` names the one
+    // that answered
+    pub fn with_fallbacks(mut self, configs: impl IntoIterator<Item = LlmConfig>) -> Self {
+        self.fallback_configs.extend(configs);
+        self
+    }
+
+    pub fn with_provider(mut self, provider: Arc<dyn LlmProvider>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    // Turns the agent into a tool loop: see `react_method`
+    pub fn with_react(mut self, react: ReactConfig) -> Self {
+        self.react = Some(react);
+        self
+    }
+
+    pub fn is_react(&self) -> bool {
+        self.react.is_some()
+    }
+
+    // Several agents may share one cache; its hit/miss counters are then shared too
+    pub fn with_response_cache(mut self, cache: Arc<ResponseCache>) -> Self {
+        self.response_cache = Some(cache);
+        self
+    }
+
+    // Record to or replay from a fixture file instead of (or as well as) the network
+    pub fn with_cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    pub fn with_trim_strategy(mut self, trim_strategy: TrimStrategy) -> Self {
+        self.trim_strategy = trim_strategy;
+        self
+    }
+
+    pub fn with_tool_timeout(mut self, tool_timeout: Duration) -> Self {
+        self.tool_timeout = tool_timeout;
+        self
+    }
+
+    // Planners given `OutputSchema::task_list()` get their tasks from the validated JSON;
+    // plans failing `validate_plan` are corrected like schema violations.
+    pub fn with_output_schema(mut self, output_schema: OutputSchema) -> Self {
+        self.output_schema = Some(output_schema);
+        self
+    }
+
+    async fn structured_output(
+        &self,
+        user_prompt: &str,
+        schema: &OutputSchema,
+        config: &LlmConfig,
+        max_token: u16,
+    ) -> StdResult<LlamaResponseMessage, DefaultMethodError> {
+        let provider = self.provider(config);
+        let mut messages = vec![
+            Message::new(
+                Content::Text(format!(
+                    "{}\n\n{}",
+                    self.system_prompt,
+                    schema.instructions()
+                )),
+                None,
+                Role::System,
+            ),
+            Message::new(Content::Text(user_prompt.to_string()), None, Role::User),
+        ];
+        let mut usage = CompletionUsage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens: 0,
+        };
+        let mut errors = Vec::new();
+
+        for attempt in 1..=schema.max_corrections + 1 {
+            let (fitted, max_token) = self
+                .fit_context(&provider, config, messages, &[], max_token)
+                .await?;
+            messages = fitted;
+            let request = ChatRequest::new(&messages, max_token).with_response_format(schema);
+            let response = self
+                .retry_policy
+                .run(|| provider.chat(config, request))
+                .await?;
+            usage.prompt_tokens += response.usage.prompt_tokens;
+            usage.completion_tokens += response.usage.completion_tokens;
+            usage.total_tokens += response.usage.total_tokens;
+
+            let reply = response.content_to_string();
+            let parsed = schema.parse(&reply).and_then(|value| {
+                if self.is_planner() {
+                    let tasks = tasks_from_value(&value).map_err(|e| vec![e.to_string()])?;
+                    let errors = plan_errors(&tasks);
+                    if errors.is_empty() {
+                        Ok(Content::Structured(StructuredText::Tasks(tasks)))
+                    } else {
+                        Err(errors)
+                    }
+                } else {
+                    Ok(Content::Text(value.to_string()))
+                }
+            });
+
+            match parsed {
+                Ok(content) => {
+                    return Ok(LlamaResponseMessage {
+                        content,
+                        role: Role::Assistant,
+                        usage,
+                        tool_calls: Vec::new(),
+                        tool_results: Vec::new(),
+                        model: None,
+                    })
+                }
+                Err(errs) => {
+                    log::warn!(
+                        "schema validation failed on attempt {}: {:?}",
+                        attempt,
+                        errs
+                    );
+                    messages.push(Message::new(Content::Text(reply), None, Role::Assistant));
+                    messages.push(Message::new(
+                        Content::Text(OutputSchema::correction_prompt(&errs)),
+                        None,
+                        Role::User,
+                    ));
+                    errors = errs;
+                }
+            }
+        }
+
+        Err(DefaultMethodError::SchemaViolation {
+            attempts: schema.max_corrections + 1,
+            errors: errors.join("; "),
+        })
+    }
+
+    // Calls from one turn are independent, so they run side by side on the blocking
+    // pool, each under `tool_timeout`; results come back in the order the model asked.
+    // Every call gets a result: a failed one carries the error as its content, so the
+    // calls that did run (side effects included) are never thrown away or repeated.
+    pub async fn execute_tool_calls(&self, tool_calls: &[ToolCall]) -> Vec<Message> {
+        let runs = tool_calls.iter().map(|tool_call| async move {
+            self.run_tool_call(tool_call).await.unwrap_or_else(|e| {
+                Message::tool_result(tool_call.id.clone(), tool_call.name.clone(), e.to_string())
+            })
+        });
+        futures::future::join_all(runs).await
+    }
+
+    // A timed-out call keeps its thread until the tool returns, but nobody waits on it
+    // With a cassette attached, runs are recorded, or in replay served from the tape
+    // without running the tool
+    pub async fn run_tool_call(
+        &self,
+        tool_call: &ToolCall,
+    ) -> StdResult<Message, DefaultMethodError> {
+        let output = match &self.cassette {
+            Some(cassette) if cassette.mode() == CassetteMode::Replay => cassette
+                .play_tool(tool_call)
+                .ok_or_else(|| {
+                    DefaultMethodError::ToolExecutionError(format!(
+                        "No recorded run of tool {} in cassette {}",
+                        tool_call.name,
+                        cassette.path().display()
+                    ))
+                })?
+                .map_err(DefaultMethodError::ToolExecutionError)?,
+            Some(cassette) => {
+                let result = self.invoke_tool(tool_call).await;
+                let recorded = result.as_deref().map_err(|e| match e {
+                    DefaultMethodError::ToolExecutionError(message) => message.clone(),
+                    e => e.to_string(),
+                });
+                cassette.record_tool(tool_call, recorded);
+                result?
+            }
+            None => self.invoke_tool(tool_call).await?,
+        };
+
+        Ok(Message::tool_result(
+            tool_call.id.clone(),
+            tool_call.name.clone(),
+            output,
+        ))
+    }
+
+    async fn invoke_tool(&self, tool_call: &ToolCall) -> StdResult<String, DefaultMethodError> {
+        let tool = lookup_tool(&tool_call.name)?;
+        let arguments = tool_call.arguments_json();
+        let task = tokio::task::spawn_blocking(move || tool.run(arguments));
+
+        let output = match timeout(self.tool_timeout, task).await {
+            Ok(Ok(Ok(output))) => output,
+            Ok(Ok(Err(e))) => {
+                let error_msg = format!("Error executing tool {}: {}", tool_call.name, e);
+                eprintln!("{}", error_msg);
+                return Err(DefaultMethodError::ToolExecutionError(error_msg));
+            }
+            Ok(Err(join_error)) => {
+                let error_msg = format!("Tool {} panicked: {}", tool_call.name, join_error);
+                eprintln!("{}", error_msg);
+                return Err(DefaultMethodError::ToolExecutionError(error_msg));
+            }
+            Err(_) => {
+                eprintln!(
+                    "Tool {} timed out after {:?}",
+                    tool_call.name, self.tool_timeout
+                );
+                return Err(DefaultMethodError::ToolTimeout {
+                    name: tool_call.name.clone(),
+                    timeout: self.tool_timeout,
+                });
+            }
+        };
+        println!("function_call result: {}", output);
+        Ok(output)
+    }
+
+    // One LLM turn of the tool loop. Falls back to the prompt-based <tool_call> format
+    // for good once the provider turns out to have no native function calling.
+    async fn react_turn(
+        &self,
+        provider: &Arc<dyn LlmProvider>,
+        config: &LlmConfig,
+        messages: &[Message],
+        tools: &[ChatCompletionTool],
+        native: &mut bool,
+        max_token: u16,
+    ) -> StdResult<(LlamaResponseMessage, Vec<ToolCall>), DefaultMethodError> {
+        if *native {
+            let request = ChatRequest::new(messages, max_token)
+                .with_tools(tools, Some(&ChatCompletionToolChoiceOption::Auto));
+            match self
+                .retry_policy
+                .run(|| provider.chat(config, request))
+                .await
+            {
+                Ok(response) => {
+                    let tool_calls = response.tool_calls.clone();
+                    return Ok((response, tool_calls));
+                }
+                Err(ChatInnerError::NativeToolsUnsupported) => *native = false,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let request = ChatRequest::new(messages, max_token);
+        let response = self
+            .retry_policy
+            .run(|| provider.chat(config, request))
+            .await?;
+        let tool_calls = extract_tool_calls(&response.content_to_string());
+        Ok((response, tool_calls))
+    }
+
+    // ReAct-style loop: the model calls tools, sees their results and goes again until it
+    // answers without a tool call. Tool failures are fed back as observations so the
+    // model can correct itself; running out of iterations or tokens is an error.
+    pub async fn react_method<F>(
+        &self,
+        input: &str,
+        mut on_step: F,
+    ) -> StdResult<LlamaResponseMessage, DefaultMethodError>
+    where
+        F: FnMut(&TraceStep) + Send,
+    {
+        let mut last_error = None;
+        for config in self.model_chain() {
+            match self.react_with(input, config, &mut on_step).await {
+                Ok(response) => return Ok(answered_by(response, config)),
+                Err(e) if e.warrants_fallback() => {
+                    log::warn!("Model {} failed: {}", config.model, e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.expect("model chain is never empty"))
+    }
+
+    async fn react_with<F>(
+        &self,
+        input: &str,
+        config: &LlmConfig,
+        on_step: &mut F,
+    ) -> StdResult<LlamaResponseMessage, DefaultMethodError>
+    where
+        F: FnMut(&TraceStep) + Send,
+    {
+        let react = self.react.clone().unwrap_or_default();
+        let max_token = config.sampling.max_tokens;
+        let provider = self.provider(config);
+
+        let tools = tools_from_meta(self.tools_map_meta.as_ref().unwrap_or(&Value::Null))
+            .map_err(|e| DefaultMethodError::ParsingError(e.to_string()))?;
+        let mut native = config.native_tool_calls && !tools.is_empty();
+
+        let user_prompt = self.build_user_prompt(input);
+        let mut messages = vec![
+            Message::new(
+                Content::Text(format!("{}\n\n{}", self.system_prompt, REACT_INSTRUCTIONS)),
+                None,
+                Role::System,
+            ),
+            Message::new(Content::Text(user_prompt), None, Role::User),
+        ];
+        let mut usage = CompletionUsage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens: 0,
+        };
+        let mut all_calls = Vec::new();
+        let mut all_results = Vec::new();
+
+        for iteration in 1..=react.max_iterations {
+            let sent_tools = if native { tools.as_slice() } else { &[] };
+            let (fitted, turn_max_token) = self
+                .fit_context(&provider, config, messages, sent_tools, max_token)
+                .await?;
+            messages = fitted;
+            let (response, tool_calls) = self
+                .react_turn(
+                    &provider,
+                    config,
+                    &messages,
+                    &tools,
+                    &mut native,
+                    turn_max_token,
+                )
+                .await?;
+            usage.prompt_tokens += response.usage.prompt_tokens;
+            usage.completion_tokens += response.usage.completion_tokens;
+            usage.total_tokens += response.usage.total_tokens;
+
+            let reply = response.content_to_string();
+            if tool_calls.is_empty() {
+                on_step(&TraceStep::FinalAnswer {
+                    iteration,
+                    text: reply.clone(),
+                });
+                return Ok(LlamaResponseMessage {
+                    content: Content::Text(reply),
+                    role: Role::Assistant,
+                    usage,
+                    tool_calls: all_calls,
+                    tool_results: all_results,
+                    model: None,
+                });
+            }
+
+            if !reply.trim().is_empty() {
+                on_step(&TraceStep::Thought {
+                    iteration,
+                    text: reply.clone(),
+                });
+            }
+            for call in &tool_calls {
+                on_step(&TraceStep::ToolCall {
+                    iteration,
+                    call: call.clone(),
+                });
+            }
+
+            let outcomes =
+                futures::future::join_all(tool_calls.iter().map(|call| self.run_tool_call(call)))
+                    .await;
+
+            let mut responses = Vec::new();
+            for (call, outcome) in tool_calls.iter().zip(outcomes) {
+                let (output, is_error) = match outcome {
+                    Ok(message) => (message.content.content_to_string(), false),
+                    Err(e) => (e.to_string(), true),
+                };
+                on_step(&TraceStep::Observation {
+                    iteration,
+                    name: call.name.clone(),
+                    output: output.clone(),
+                    is_error,
+                });
+                all_results.push(Message::tool_result(
+                    call.id.clone(),
+                    call.name.clone(),
+                    output.clone(),
+                ));
+
+                if native {
+                    messages.push(Message::new(
+                        Content::Structured(StructuredText::ToolCall(call.clone())),
+                        None,
+                        Role::Assistant,
+                    ));
+                    messages.push(Message::tool_result(
+                        call.id.clone(),
+                        call.name.clone(),
+                        output,
+                    ));
+                } else {
+                    responses.push(format!(
+                        "<tool_response>\n{}\n</tool_response>",
+                        json!({ "name": call.name, "content": output })
+                    ));
+                }
+            }
+            if !native {
+                messages.push(Message::new(Content::Text(reply), None, Role::Assistant));
+                messages.push(Message::new(
+                    Content::Text(responses.join("\n")),
+                    None,
+                    Role::User,
+                ));
+            }
+            all_calls.extend(tool_calls);
+
+            if let Some(budget) = react.max_total_tokens.filter(|b| usage.total_tokens >= *b) {
+                let reason = format!("token budget of {} used up", budget);
+                on_step(&TraceStep::Stopped {
+                    iteration,
+                    reason: reason.clone(),
+                });
+                return Err(DefaultMethodError::LoopLimit(reason));
+            }
+        }
+
+        let reason = format!("no final answer after {} iterations", react.max_iterations);
+        on_step(&TraceStep::Stopped {
+            iteration: react.max_iterations,
+            reason: reason.clone(),
+        });
+        Err(DefaultMethodError::LoopLimit(reason))
+    }
+
+    // Returns Ok(None) when the provider has no native function calling, so the caller
+    // can fall back to the prompt-based <tool_call> format.
+    async fn native_tool_call(
+        &self,
+        user_prompt: &str,
+        config: &LlmConfig,
+        max_token: u16,
+    ) -> StdResult<Option<LlamaResponseMessage>, DefaultMethodError> {
+        let tools = tools_from_meta(self.tools_map_meta.as_ref().unwrap_or(&Value::Null))
+            .map_err(|e| DefaultMethodError::ParsingError(e.to_string()))?;
+        let provider = self.provider(config);
+        let (messages, max_token) = self
+            .fit_context(
+                &provider,
+                config,
+                self.prompt_messages(user_prompt),
+                &tools,
+                max_token,
+            )
+            .await?;
+        let request = ChatRequest::new(&messages, max_token)
+            .with_tools(&tools, Some(&ChatCompletionToolChoiceOption::Auto));
+
+        let response = match self
+            .retry_policy
+            .run(|| provider.chat(config, request))
+            .await
+        {
+            Ok(res) => res,
+            Err(ChatInnerError::NativeToolsUnsupported) => return Ok(None),
+            Err(e) => return Err(DefaultMethodError::LlmApiError(e)),
+        };
+
+        if response.tool_calls.is_empty() {
+            return Ok(Some(response));
+        }
+
+        let usage = response.usage.clone();
+        let results = self.execute_tool_calls(&response.tool_calls).await;
+        let output = results
+            .iter()
+            .map(|m| m.content.content_to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Ok(Some(LlamaResponseMessage {
+            content: Content::Text(output),
+            role: Role::Tool,
+            usage,
+            tool_calls: response.tool_calls,
+            tool_results: results,
+            model: None,
+        }))
+    }
+
+    // A reply that doesn't parse as a task list or fails `validate_plan` goes back to
+    // the planner with the problems, like schema violations in `structured_output`
+    async fn plan_with_corrections(
+        &self,
+        user_prompt: &str,
+        config: &LlmConfig,
+        max_token: u16,
+    ) -> StdResult<LlamaResponseMessage, DefaultMethodError> {
+        let provider = self.provider(config);
+        let mut messages = self.prompt_messages(user_prompt);
+        let mut usage = CompletionUsage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens: 0,
+        };
+        let mut errors = Vec::new();
+
+        for attempt in 1..=PLAN_MAX_CORRECTIONS + 1 {
+            let (fitted, max_token) = self
+                .fit_context(&provider, config, messages, &[], max_token)
+                .await?;
+            messages = fitted;
+            let request = ChatRequest::new(&messages, max_token);
+            let response = self
+                .retry_policy
+                .run(|| provider.chat(config, request))
+                .await?;
+            usage.prompt_tokens += response.usage.prompt_tokens;
+            usage.completion_tokens += response.usage.completion_tokens;
+            usage.total_tokens += response.usage.total_tokens;
+
+            let reply = response.content_to_string();
+            let problems = match parse_planning_tasks(&reply) {
+                Ok(tasks) => {
+                    let problems = plan_errors(&tasks);
+                    if problems.is_empty() {
+                        return Ok(LlamaResponseMessage {
+                            content: Content::Structured(StructuredText::Tasks(tasks)),
+                            role: Role::Assistant,
+                            usage,
+                            tool_calls: Vec::new(),
+                            tool_results: Vec::new(),
+                            model: None,
+                        });
+                    }
+                    problems
+                }
+                Err(e) => vec![format!("the reply is not a JSON task list: {}", e)],
+            };
+            log::warn!("plan rejected on attempt {}: {:?}", attempt, problems);
+            messages.push(Message::new(Content::Text(reply), None, Role::Assistant));
+            messages.push(Message::new(
+                Content::Text(plan::correction_prompt(&problems)),
+                None,
+                Role::User,
+            ));
+            errors = problems;
+        }
+
+        Err(DefaultMethodError::ParsingError(format!(
+            "No valid plan after {} attempts: {}",
+            PLAN_MAX_CORRECTIONS + 1,
+            errors.join("; ")
+        )))
+    }
+
     pub async fn default_method(
         &self,
         input: &str,
+    ) -> StdResult<LlamaResponseMessage, DefaultMethodError> {
+        let mut last_error = None;
+        for config in self.model_chain() {
+            match self.default_with(input, config).await {
+                Ok(response) => return Ok(answered_by(response, config)),
+                Err(e) if e.warrants_fallback() => {
+                    log::warn!("Model {} failed: {}", config.model, e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.expect("model chain is never empty"))
+    }
+
+    async fn default_with(
+        &self,
+        input: &str,
+        config: &LlmConfig,
     ) -> StdResult<LlamaResponseMessage, DefaultMethodError> {
         enum TaskOutput {
             text,
@@ -166,10 +1232,7 @@ impl LlmAgent {
             .map(|name| name == "get_user_feedback")
             .unwrap_or(false)
         {
-            let store = STORE.lock().unwrap();
-            let tool = store
-                .get("get_user_feedback")
-                .expect("get_user_feedback not found in store");
+            let tool = lookup_tool("get_user_feedback")?;
             let output = tool
                 .run(String::new())
                 .map_err(|e| DefaultMethodError::ToolExecutionError(e.to_string()))?;
@@ -178,12 +1241,13 @@ impl LlmAgent {
                 content: Content::Text(output.to_string()),
                 role: Role::Assistant,
                 usage: default_usage,
+                tool_calls: Vec::new(),
+                tool_results: Vec::new(),
+                model: None,
             });
         }
 
-        let task_type = if self.user_prompt_formatter.is_some()
-            && self.description.to_lowercase().contains("plan")
-        {
+        let task_type = if self.is_planner() {
             TaskOutput::tasks
         } else if !tool_names.is_empty() {
             TaskOutput::tool_call
@@ -191,89 +1255,535 @@ impl LlmAgent {
             TaskOutput::text
         };
 
-        let user_prompt = match &self.user_prompt_formatter {
-            None => format!("here is your task: {}", input),
-            Some(f) => {
-                let formatter = f.lock().unwrap();
-                formatter(&[
-                    &input,
-                    &self
-                        .tools_map_meta
-                        .clone()
-                        .unwrap_or(Value::String(String::new()))
-                        .to_string(),
-                ])
-            }
-        };
+        let user_prompt = self.build_user_prompt(input);
+        let max_token = config.sampling.max_tokens;
 
-        let max_token = 1000u16;
-        let config = self.llm_config.as_ref().unwrap_or(&TOGETHER_CONFIG);
+        if let Some(schema) = self
+            .output_schema
+            .as_ref()
+            .filter(|_| !matches!(task_type, TaskOutput::tool_call))
+        {
+            return self
+                .structured_output(&user_prompt, schema, config, max_token)
+                .await;
+        }
+
+        if matches!(task_type, TaskOutput::tasks) {
+            return self
+                .plan_with_corrections(&user_prompt, config, max_token)
+                .await;
+        }
+        if matches!(task_type, TaskOutput::tool_call) && config.native_tool_calls {
+            if let Some(response) = self
+                .native_tool_call(&user_prompt, config, max_token)
+                .await?
+            {
+                return Ok(response);
+            }
+        }
+        let provider = self.provider(config);
+        let (messages, max_token) = self
+            .fit_context(
+                &provider,
+                config,
+                self.prompt_messages(&user_prompt),
+                &[],
+                max_token,
+            )
+            .await?;
         let attempt = AtomicUsize::new(0);
 
         let result = tryhard::retry_fn(|| async {
             let count = attempt.fetch_add(1, Ordering::Relaxed) + 1;
             println!("Attempt number: {}", count);
 
-            let (resp, usage) =
-                chat_inner_async_wrapper(config, &self.system_prompt, &user_prompt, max_token)
-                    .await
-                    .map_err(|e| DefaultMethodError::LlmApiError(e.to_string()))?;
-
-            let content = match task_type {
-                TaskOutput::text => Content::Text(resp.clone()),
-                TaskOutput::tasks => {
-                    let tasks = parse_planning_tasks(&resp).map_err(|e| {
-                        DefaultMethodError::ParsingError(format!(
-                            "Failed to parse planning tasks: {:?}",
-                            e
-                        ))
-                    })?;
-                    Content::Structured(StructuredText::Tasks(tasks))
-                }
+            let response = provider
+                .chat(config, ChatRequest::new(&messages, max_token))
+                .await?;
+            let resp = response.content_to_string();
+            let usage = response.usage;
+
+            let (content, tool_calls, tool_results) = match task_type {
+                TaskOutput::text => (Content::Text(resp.clone()), Vec::new(), Vec::new()),
+                TaskOutput::tasks => unreachable!("plans go through plan_with_corrections"),
                 TaskOutput::tool_call => {
-                    let json_str = extract_json_from_xml_like(&resp).map_err(|e| {
-                        DefaultMethodError::ParsingError(format!("JSON extraction error: {}", e))
-                    })?;
-                    let tool_call = extract_tool_call_json(&json_str).map_err(|e| {
-                        DefaultMethodError::ParsingError(format!("Tool call parse error: {}", e))
-                    })?;
-
-                    let func_name = tool_call.name.clone();
-                    let args_value = tool_call.arguments.unwrap_or_else(String::new);
-                    let binding = STORE.lock().unwrap();
-
-                    if let Some(tool) = binding.get(&func_name) {
-                        match tool.run(args_value) {
-                            Ok(tool_output) => {
-                                println!("function_call result: {}", tool_output);
-                                Content::Text(tool_output)
-                            }
-                            Err(e) => {
-                                let error_msg =
-                                    format!("Error executing tool {}: {}", func_name, e);
-                                eprintln!("{}", error_msg);
-                                return Err(DefaultMethodError::ToolExecutionError(error_msg));
-                            }
-                        }
-                    } else {
-                        let error_msg = format!("Tool {} not found", func_name);
-                        eprintln!("{}", error_msg);
-                        return Err(DefaultMethodError::ToolNotFound(error_msg));
+                    let tool_calls = extract_tool_calls(&resp);
+                    if tool_calls.is_empty() {
+                        return Err(DefaultMethodError::ParsingError(format!(
+                            "No tool call found in response: {}",
+                            resp
+                        )));
                     }
+
+                    let results = self.execute_tool_calls(&tool_calls).await;
+                    let output = results
+                        .iter()
+                        .map(|m| m.content.content_to_string())
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    (Content::Text(output), tool_calls, results)
                 }
             };
 
-            Ok((resp, usage, content))
+            Ok::<_, DefaultMethodError>((resp, usage, content, tool_calls, tool_results))
+        })
+        .retries(self.retry_policy.max_retries)
+        .custom_backoff(|attempt, error: &DefaultMethodError| match error {
+            DefaultMethodError::LlmApiError(e) => self.retry_policy.decide(attempt, e),
+            // Tools may already have run and had side effects; asking again would rerun them
+            DefaultMethodError::ToolExecutionError(_)
+            | DefaultMethodError::ToolTimeout { .. }
+            | DefaultMethodError::ToolNotFound(_) => tryhard::RetryPolicy::Break,
+            // Malformed model output: just ask again
+            _ => tryhard::RetryPolicy::Delay(Duration::ZERO),
         })
-        .retries(2)
         .await?;
 
-        let (_, usage, content) = result;
+        let (_, usage, content, tool_calls, tool_results) = result;
 
         Ok(LlamaResponseMessage {
             content,
             role: Role::Assistant,
             usage,
+            tool_calls,
+            tool_results,
+            model: None,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llama::mock::MockLlm;
+    use crate::{SamplingParams, GET_WEATHER_TOOL_DEF_OBJ, TEMPLATE_USER_PROMPT_TOOL_USE};
+
+    const WEATHER_CALL: &str = r#"<tool_call>
+{"name": "get_current_weather", "arguments": {"location": "New York", "unit": "celsius"}}
+</tool_call>"#;
+
+    fn agent(mock: &Arc<MockLlm>) -> LlmAgent {
+        LlmAgent::build(
+            "You are a helpful assistant.".to_string(),
+            None,
+            None,
+            None,
+            "assistant".to_string(),
+        )
+        .unwrap()
+        .with_provider(mock.clone())
+    }
+
+    fn weather_agent(mock: &Arc<MockLlm>) -> LlmAgent {
+        let tools: Value = serde_json::from_str(GET_WEATHER_TOOL_DEF_OBJ).unwrap();
+        LlmAgent::build(
+            String::new(),
+            Some(TEMPLATE_USER_PROMPT_TOOL_USE.clone()),
+            None,
+            Some(json!([tools])),
+            "weather agent".to_string(),
+        )
+        .unwrap()
+        .with_provider(mock.clone())
+    }
+
+    #[tokio::test]
+    async fn default_method_returns_the_model_text() {
+        let mock = Arc::new(MockLlm::new().with_response("Paris"));
+        let response = agent(&mock)
+            .default_method("What is the capital of France?")
+            .await
+            .unwrap();
+
+        assert_eq!(response.content_to_string(), "Paris");
+        assert_eq!(
+            response.model.as_deref(),
+            Some(TOGETHER_CONFIG.model.as_str())
+        );
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].system_prompt, "You are a helpful assistant.");
+        assert!(calls[0].input.contains("What is the capital of France?"));
+    }
+
+    #[tokio::test]
+    async fn tool_call_runs_the_tool() {
+        let mock = Arc::new(MockLlm::new().with_response(WEATHER_CALL));
+        let response = weather_agent(&mock)
+            .default_method("weather in New York")
+            .await
+            .unwrap();
+
+        assert_eq!(response.tool_calls.len(), 1);
+        assert_eq!(response.tool_calls[0].name, "get_current_weather");
+        assert_eq!(
+            response.content_to_string(),
+            "Weather for New York in 25 celsius "
+        );
+        assert!(mock.calls()[0].input.contains("get_current_weather"));
+    }
+
+    // Answers every request with two native tool calls, as an OpenAI-style backend would,
+    // and keeps the user prompts it was sent
+    #[derive(Default)]
+    struct NativeWeather {
+        inputs: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl LlmProvider for NativeWeather {
+        fn chat<'a>(
+            &'a self,
+            _llm_config: &'a LlmConfig,
+            request: ChatRequest<'a>,
+        ) -> futures::future::BoxFuture<'a, StdResult<LlamaResponseMessage, ChatInnerError>>
+        {
+            self.inputs.lock().unwrap().push(request.last_user_input());
+            let call = |id: &str, location: &str| ToolCall {
+                id: Some(id.to_string()),
+                name: "get_current_weather".to_string(),
+                arguments: Some(json!({ "location": location, "unit": "celsius" })),
+            };
+            let mut response = crate::llama::provider::text_response(
+                String::new(),
+                crate::llama::provider::usage_from_counts(0, 0),
+            );
+            response.tool_calls = vec![call("call_1", "New York"), call("call_2", "Paris")];
+            Box::pin(async move { Ok(response) })
+        }
+    }
+
+    #[tokio::test]
+    async fn native_tool_call_returns_a_tool_message_per_call() {
+        let tools: Value = serde_json::from_str(GET_WEATHER_TOOL_DEF_OBJ).unwrap();
+        let config = TOGETHER_CONFIG.clone().with_native_tool_calls(true);
+        let response = LlmAgent::build(
+            String::new(),
+            None,
+            Some(config),
+            Some(json!([tools])),
+            "weather agent".to_string(),
+        )
+        .unwrap()
+        .with_provider(Arc::new(NativeWeather::default()))
+        .default_method("weather in New York and Paris")
+        .await
+        .unwrap();
+
+        assert_eq!(response.tool_results.len(), 2);
+        for (result, id) in response.tool_results.iter().zip(["call_1", "call_2"]) {
+            assert_eq!(result.role, Role::Tool);
+            assert_eq!(result.tool_call_id.as_deref(), Some(id));
+        }
+        assert_eq!(
+            response.tool_results[0].content.content_to_string(),
+            "Weather for New York in 25 celsius "
+        );
+        // The failed call still answers its id, with the error as content
+        assert!(response.tool_results[1]
+            .content
+            .content_to_string()
+            .contains("Weather for Paris in celsius"));
+    }
+
+    #[tokio::test]
+    async fn native_tool_call_formats_the_prompt() {
+        let tools: Value = serde_json::from_str(GET_WEATHER_TOOL_DEF_OBJ).unwrap();
+        let config = TOGETHER_CONFIG.clone().with_native_tool_calls(true);
+        let provider = Arc::new(NativeWeather::default());
+        let agent = LlmAgent::build(
+            String::new(),
+            Some(TEMPLATE_USER_PROMPT_TOOL_USE.clone()),
+            Some(config),
+            Some(json!([tools])),
+            "weather agent".to_string(),
+        )
+        .unwrap()
+        .with_provider(provider.clone());
+
+        let input = "weather in New York and Paris";
+        agent.default_method(input).await.unwrap();
+
+        // The same prompt the scraping path would send
+        let inputs = provider.inputs.lock().unwrap().clone();
+        assert_eq!(inputs, [agent.build_user_prompt(input)]);
+        assert_ne!(inputs[0], input);
+    }
+
+    #[tokio::test]
+    async fn tool_call_asks_again_when_the_reply_has_no_call() {
+        let mock = Arc::new(
+            MockLlm::new()
+                .with_response("I would call the weather tool.")
+                .with_response(WEATHER_CALL),
+        );
+        let response = weather_agent(&mock)
+            .default_method("weather in New York")
+            .await
+            .unwrap();
+
+        assert_eq!(mock.call_count(), 2);
+        assert_eq!(response.tool_calls.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_failed_tool_call_keeps_the_other_results_and_is_not_retried() {
+        let reply = r#"<tool_call>
+{"name": "get_current_weather", "arguments": {"location": "New York", "unit": "celsius"}}
+</tool_call>
+<tool_call>
+{"name": "get_current_weather", "arguments": {"location": "Paris", "unit": "celsius"}}
+</tool_call>"#;
+        let mock = Arc::new(MockLlm::new().with_fallback(reply));
+        let response = weather_agent(&mock)
+            .default_method("weather in New York and Paris")
+            .await
+            .unwrap();
+
+        assert_eq!(mock.call_count(), 1);
+        assert_eq!(response.tool_calls.len(), 2);
+        let output = response.content_to_string();
+        assert!(
+            output.contains("Weather for New York in 25 celsius"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("Error executing tool get_current_weather: Weather for Paris"),
+            "{}",
+            output
+        );
+    }
+
+    #[tokio::test]
+    async fn execute_tool_calls_returns_a_message_per_call() {
+        let mock = Arc::new(MockLlm::new());
+        let calls = [
+            ToolCall {
+                id: Some("call_1".to_string()),
+                name: "get_current_weather".to_string(),
+                arguments: Some(json!({"location": "New York", "unit": "celsius"})),
+            },
+            ToolCall {
+                id: Some("call_2".to_string()),
+                name: "no_such_tool".to_string(),
+                arguments: None,
+            },
+        ];
+        let results = weather_agent(&mock).execute_tool_calls(&calls).await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(
+            results[0].content.content_to_string(),
+            "Weather for New York in 25 celsius "
+        );
+        assert_eq!(results[1].tool_call_id.as_deref(), Some("call_2"));
+        assert!(results[1]
+            .content
+            .content_to_string()
+            .contains("Tool no_such_tool not found"));
+    }
+
+    #[tokio::test]
+    async fn react_feeds_tool_results_back_until_an_answer() {
+        let mock = Arc::new(
+            MockLlm::new()
+                .with_response(WEATHER_CALL)
+                .with_response("It is 25 degrees in New York."),
+        );
+        let mut steps = Vec::new();
+        let response = weather_agent(&mock)
+            .with_react(ReactConfig::new(3))
+            .react_method("weather in New York", |step| steps.push(step.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.content_to_string(),
+            "It is 25 degrees in New York."
+        );
+        assert_eq!(response.tool_calls.len(), 1);
+        assert!(matches!(steps[0], TraceStep::Thought { iteration: 1, .. }));
+        assert!(matches!(steps[1], TraceStep::ToolCall { iteration: 1, .. }));
+        assert!(matches!(
+            &steps[2],
+            TraceStep::Observation { iteration: 1, is_error: false, output, .. }
+                if output == "Weather for New York in 25 celsius "
+        ));
+        assert!(matches!(
+            steps[3],
+            TraceStep::FinalAnswer { iteration: 2, .. }
+        ));
+
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 2);
+        assert!(calls[1].input.contains("<tool_response>"));
+        assert!(calls[1]
+            .input
+            .contains("Weather for New York in 25 celsius"));
+    }
+
+    #[tokio::test]
+    async fn react_stops_at_the_iteration_limit() {
+        let mock = Arc::new(MockLlm::new().with_fallback(WEATHER_CALL));
+        let mut steps = Vec::new();
+        let result = weather_agent(&mock)
+            .with_react(ReactConfig::new(2))
+            .react_method("weather in New York", |step| steps.push(step.clone()))
+            .await;
+
+        assert!(matches!(result, Err(DefaultMethodError::LoopLimit(_))));
+        assert_eq!(mock.call_count(), 2);
+        assert!(matches!(
+            steps.last(),
+            Some(TraceStep::Stopped { iteration: 2, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn planner_corrects_an_invalid_plan() {
+        let cyclic = r#"{"tasks": [
+            {"id": "t1", "name": "Design", "description": "d", "tool": null, "depends_on": ["t2"]},
+            {"id": "t2", "name": "Build", "description": "b", "tool": null, "depends_on": ["t1"]}
+        ]}"#;
+        let valid = r#"{"tasks": [
+            {"id": "t1", "name": "Design", "description": "d", "tool": null},
+            {"id": "t2", "name": "Build", "description": "b", "tool": null, "depends_on": ["t1"]}
+        ]}"#;
+        let mock = Arc::new(MockLlm::new().with_responses([cyclic, valid]));
+        let planner = LlmAgent::build(
+            TEMPLATE_SYSTEM_PROMPT_PLANNER.to_string(),
+            Some(TEMPLATE_USER_PROMPT_TASK_JSON.clone()),
+            None,
+            None,
+            "planner agent".to_string(),
+        )
+        .unwrap()
+        .with_provider(mock.clone());
+
+        let response = planner.default_method("build an amplifier").await.unwrap();
+        let Content::Structured(StructuredText::Tasks(tasks)) = response.content else {
+            panic!("expected a task list, got {:?}", response.content);
+        };
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[1].depends_on, vec!["t1".to_string()]);
+        assert_eq!(mock.call_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn structured_output_corrects_a_schema_violation() {
+        let schema = OutputSchema::new(
+            "city",
+            json!({
+                "type": "object",
+                "properties": {"city": {"type": "string"}},
+                "required": ["city"]
+            }),
+        )
+        .unwrap();
+        let mock = Arc::new(
+            MockLlm::new().with_responses([r#"{"town": "Paris"}"#, r#"{"city": "Paris"}"#]),
+        );
+        let response = agent(&mock)
+            .with_output_schema(schema.clone())
+            .default_method("Where is the Louvre?")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<Value>(&response.content_to_string()).unwrap(),
+            json!({"city": "Paris"})
+        );
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 2);
+        assert!(calls[0].system_prompt.contains(&schema.instructions()));
+        assert!(calls[0].input.contains("Where is the Louvre?"));
+        assert!(calls[1]
+            .input
+            .starts_with("Your previous reply did not conform to the required JSON Schema"));
+        assert!(calls[1].input.contains("city"));
+    }
+
+    #[tokio::test]
+    async fn fallback_model_answers_when_the_primary_fails() {
+        // Unscripted on the first call, so the primary model's request fails
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let mock = Arc::new(MockLlm::new().with_closure(move |_, _| {
+            let first = calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0;
+            (!first).then(|| "Paris".to_string())
+        }));
+        let response = agent(&mock)
+            .with_retry_policy(RetryPolicy::none())
+            .with_fallbacks([LlmConfig::new("backup-model", "http://localhost:1")])
+            .default_method("What is the capital of France?")
+            .await
+            .unwrap();
+
+        assert_eq!(response.content_to_string(), "Paris");
+        assert_eq!(response.model.as_deref(), Some("backup-model"));
+        let models: Vec<String> = mock.calls().into_iter().map(|c| c.model).collect();
+        assert_eq!(
+            models,
+            [TOGETHER_CONFIG.model.clone(), "backup-model".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn long_history_is_trimmed_before_the_call() {
+        let schema = OutputSchema::new("city", json!({"type": "object", "required": ["city"]}))
+            .unwrap()
+            .with_max_corrections(2);
+        // Two wrong answers of about a thousand tokens each don't fit in the window
+        // together, so the third request has to drop the older one
+        let wrong = |n: usize| format!(r#"{{"town": "{} {}"}}"#, n, "word ".repeat(1000));
+        let mock = Arc::new(MockLlm::new().with_responses([
+            wrong(1),
+            wrong(2),
+            r#"{"city": "Paris"}"#.to_string(),
+        ]));
+        let config = TOGETHER_CONFIG
+            .clone()
+            .with_context_size(2000)
+            .with_sampling(SamplingParams {
+                max_tokens: 10,
+                ..SamplingParams::default()
+            });
+        let agent = LlmAgent::build(
+            "You are a helpful assistant.".to_string(),
+            None,
+            Some(config),
+            None,
+            "assistant".to_string(),
+        )
+        .unwrap()
+        .with_provider(mock.clone())
+        .with_output_schema(schema);
+
+        agent.default_method("Where is the Louvre?").await.unwrap();
+
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[1].messages.len(), 4);
+        let sent = &calls[2].messages;
+        let texts: Vec<String> = sent.iter().map(|m| m.content.content_to_string()).collect();
+        assert!(sent.len() < 6, "nothing was trimmed: {:?}", texts);
+        assert_eq!(sent[0].role, Role::System);
+        assert!(texts.iter().all(|t| !t.contains(r#""town": "1 "#)));
+        assert!(texts.iter().any(|t| t.contains(r#""town": "2 "#)));
+        assert_eq!(calls[2].input, calls[1].input);
+        assert!(calls[2]
+            .input
+            .starts_with("Your previous reply did not conform"));
+    }
+
+    #[tokio::test]
+    async fn unscripted_input_is_an_error() {
+        let mock = Arc::new(MockLlm::new());
+        let result = agent(&mock)
+            .with_retry_policy(RetryPolicy::none())
+            .default_method("hello")
+            .await;
+
+        assert!(matches!(result, Err(DefaultMethodError::LlmApiError(_))));
+    }
+}
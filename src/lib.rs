@@ -1,9 +1,16 @@
 #![allow(warnings, deprecated)]
 
 pub mod agent_runtime;
+pub mod decomposer;
 pub mod immutable_agent;
 pub mod llama;
+pub mod llm_config;
+pub mod plan_executor;
 pub mod use_tool;
+
+pub use llama::chat_template::ChatTemplate;
+pub use llm_config::{LlmConfig, LlmConfigError, ProviderKind, SamplingParams};
+
 use crate::use_tool::{Tool, TypeConverter};
 use ctor::ctor;
 use lazy_static::lazy_static;
@@ -33,7 +40,7 @@ pub static ref TEMPLATE_SYSTEM_PROMPT_TOOL_USE: &'static str = "You are an AI as
 pub static ref TEMPLATE_USER_PROMPT_TOOL_USE: Arc<Mutex<FormatterFn>> =
     Arc::new(Mutex::new(Box::new(|args: &[&str]| {
         format!(
-            "<|im_start|>user Task: {}\n\n\
+            "Task: {}\n\n\
             AVAILABLE TOOLS:\n{}\n\n\
             RESPONSE FORMAT INSTRUCTIONS:\n\
             Use the following pydantic model json schema for each tool call you make:\n\
@@ -49,7 +56,7 @@ pub static ref TEMPLATE_USER_PROMPT_TOOL_USE: Arc<Mutex<FormatterFn>> =
             Return NOTHING but your tool call ONLY, within <tool_call></tool_call> XML tags as follows:\n\
             <tool_call>\n\
             {{\"arguments\": <args-dict>, \"name\": <function-name>}}\n\
-            </tool_call><|im_end|>",
+            </tool_call>",
             args[0], args[1]
         )
     })));
@@ -80,31 +87,76 @@ pub static ref TEMPLATE_USER_PROMPT_TOOL_USE: Arc<Mutex<FormatterFn>> =
         {{
           \"tasks\": [
             {{
+              \"id\": \"[Unique short id, e.g. t1]\",
               \"name\": \"[Short descriptive name]\",
               \"description\": \"[Detailed explanation]\",
               \"tool\": \"[tool_name or null if no specific tool]\",
+              \"arguments\": {{ \"[argument name]\": \"[value]\" }},
+              \"depends_on\": [\"[ids of tasks that must finish first]\"],
+              \"expected_output\": \"[What the task produces]\",
+              \"acceptance_criteria\": [\"[How to tell the task succeeded]\"]
             }},
             ...
           ]
         }}
 
         IMPORTANT NOTES:
-        - Tasks should be arranged in sequential order by default
-        - Include dependencies only when tasks can be executed in parallel
-        - The \"tool\" field should reference an available tool when applicable, or null
+        - List tasks in execution order; \"depends_on\" names the tasks whose results a task needs
+        - Leave \"depends_on\" empty for tasks that can start right away, so they can run in parallel
+        - Dependencies must not form a cycle
+        - The \"tool\" field should reference an available tool when applicable, or null; \"arguments\" are that tool's arguments, or null
         - Each task MUST include all fields shown above
         - Keep the breakdown flat (one level only, no nested subtasks)",
                 args[0], args[1]
             )
         })));
-}
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
-pub struct LlmConfig {
-    pub model: &'static str,
-    pub base_url: &'static str,
-    pub context_size: usize,
-    pub api_key_str: &'static str,
+    pub static ref TEMPLATE_SYSTEM_PROMPT_DECOMPOSER:  &'static str  = "You are a precise project planning assistant that decomposes work step by step into a tree of tasks.
+
+        PLANNING RULES:
+        - You are asked for one level at a time: list only the direct subtasks of the task you are given
+        - Mark a subtask as complex when it still needs several tool calls or LLM steps; it will be broken down in a later request
+        - A subtask that maps to a single tool call or a single LLM answer is not complex
+        - Map subtasks to available tool capabilities when possible
+        - Ensure each subtask is concrete and actionable
+
+        RESPONSE BEHAVIOR:
+        - Stay within the scope of the task you are given; the rest of the goal is handled elsewhere
+        - Always return valid JSON in the exact format requested";
+
+    pub static ref TEMPLATE_USER_PROMPT_DECOMPOSE_JSON: Arc<Mutex<FormatterFn>> =
+        Arc::new(Mutex::new(Box::new(|args: &[&str]| {
+            format!(
+                "{}
+
+        Available tools: {}
+
+        Return your response as valid JSON with this structure:
+        {{
+          \"tasks\": [
+            {{
+              \"id\": \"[Unique short id, e.g. t1]\",
+              \"name\": \"[Short descriptive name]\",
+              \"description\": \"[Detailed explanation]\",
+              \"tool\": \"[tool_name or null if no specific tool]\",
+              \"arguments\": {{ \"[argument name]\": \"[value]\" }},
+              \"depends_on\": [\"[ids of sibling tasks that must finish first]\"],
+              \"expected_output\": \"[What the task produces]\",
+              \"complex\": [true if the task needs further breakdown, otherwise false]
+            }},
+            ...
+          ]
+        }}
+
+        IMPORTANT NOTES:
+        - \"depends_on\" may only name ids from this same list
+        - Dependencies must not form a cycle
+        - The \"tool\" field should reference an available tool when applicable, or null; \"arguments\" are that tool's arguments, or null
+        - A task with a tool is never complex
+        - Each task MUST include all fields shown above",
+                args[0], args[1]
+            )
+        })));
 }
 
 // pub const DEEPINFRA_CONFIG: LlmConfig = LlmConfig {
@@ -114,14 +166,16 @@ pub struct LlmConfig {
 //     api_key_str: "DEEPINFRA_API_KEY",
 // };
 
-pub const TOGETHER_CONFIG: LlmConfig = LlmConfig {
-    model: "google/gemma-2-9b-it",
-    // model: "mistralai/Mistral-Small-24B-Instruct-2501",
-    // model: "meta-llama/Llama-3.3-70B-Instruct-Turbo",
-    context_size: 8192,
-    base_url: "https://api.together.xyz/v1/chat/completions",
-    api_key_str: "TOGETHER_API_KEY",
-};
+pub static TOGETHER_CONFIG: Lazy<LlmConfig> = Lazy::new(|| {
+    LlmConfig::new(
+        "google/gemma-2-9b-it",
+        // "mistralai/Mistral-Small-24B-Instruct-2501",
+        // "meta-llama/Llama-3.3-70B-Instruct-Turbo",
+        "https://api.together.xyz/v1/chat/completions",
+    )
+    .with_context_size(8192)
+    .with_api_key_var("TOGETHER_API_KEY")
+});
 
 // const CODELLAMA_CONFIG: LlmConfig = LlmConfig {
 //     model: "codellama/CodeLlama-34b-Instruct-hf",
@@ -0,0 +1,394 @@
+use crate::llama::provider::{single_chunk_response, ChatRequest, LlmProvider};
+use crate::llama::{ChatCompletionStream, ChatInnerError, LlamaResponseMessage};
+use crate::LlmConfig;
+use futures::future::BoxFuture;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+// Tells apart the temp files of writers in the same process
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Error)]
+pub enum CacheError {
+    #[error("Cache I/O failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Cache entry (de)serialization failed: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    // The full key, compared on read so a hash collision is a miss, not a wrong answer
+    key: Value,
+    created_at: u64,
+    response: LlamaResponseMessage,
+}
+
+// Content-addressed store of chat completions, one JSON file per request under `dir`.
+// Entries older than `ttl` count as misses and are removed; past `max_bytes` the
+// least recently written entries go first.
+#[derive(Debug)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Option<Duration>,
+    max_bytes: Option<u64>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, CacheError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            ttl: None,
+            max_bytes: None,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    // Everything that can change the completion: which server and model, the whole
+    // conversation, sampling, tools and the requested output schema
+    pub fn key(llm_config: &LlmConfig, request: &ChatRequest) -> Value {
+        json!({
+            "provider": llm_config.provider,
+            "base_url": llm_config.base_url,
+            "model": llm_config.model,
+            "chat_template": llm_config.chat_template(),
+            "sampling": llm_config.sampling,
+            "max_token": request.max_token,
+            "messages": request.messages,
+            "tools": request.tools,
+            "tool_choice": request.tool_choice,
+            "response_format": request.response_format.map(|schema| json!({
+                "name": schema.name,
+                "schema": schema.schema,
+                "strict": schema.strict,
+            })),
+        })
+    }
+
+    pub fn get(&self, key: &Value) -> Option<LlamaResponseMessage> {
+        let path = self.entry_path(key);
+        let entry = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<CacheEntry>(&bytes).ok())
+            .filter(|entry| entry.key == *key);
+
+        match entry {
+            Some(entry) if !self.is_expired(entry.created_at) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.response)
+            }
+            Some(_) => {
+                let _ = std::fs::remove_file(&path);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn put(&self, key: Value, response: &LlamaResponseMessage) -> Result<(), CacheError> {
+        let path = self.entry_path(&key);
+        let entry = CacheEntry {
+            key,
+            created_at: now_secs(),
+            response: response.clone(),
+        };
+
+        // Write-then-rename so a concurrent reader never sees half an entry
+        let tmp = path.with_extension(format!(
+            "tmp{}-{}",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&tmp, serde_json::to_vec(&entry)?)?;
+        std::fs::rename(&tmp, &path)?;
+
+        if let Some(max_bytes) = self.max_bytes {
+            self.evict(max_bytes)?;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&self) -> Result<(), CacheError> {
+        for (path, _, _) in self.entries()? {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn evict(&self, max_bytes: u64) -> Result<(), CacheError> {
+        let mut entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        if total <= max_bytes {
+            return Ok(());
+        }
+
+        entries.sort_by_key(|(_, _, modified)| *modified);
+        for (path, size, _) in entries {
+            if total <= max_bytes {
+                break;
+            }
+            std::fs::remove_file(&path)?;
+            total -= size;
+        }
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<(PathBuf, u64, SystemTime)>, CacheError> {
+        let mut entries = Vec::new();
+        for dir_entry in std::fs::read_dir(&self.dir)? {
+            let dir_entry = dir_entry?;
+            let path = dir_entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let metadata = dir_entry.metadata()?;
+            entries.push((path, metadata.len(), metadata.modified()?));
+        }
+        Ok(entries)
+    }
+
+    fn is_expired(&self, created_at: u64) -> bool {
+        self.ttl.map_or(false, |ttl| {
+            now_secs().saturating_sub(created_at) > ttl.as_secs()
+        })
+    }
+
+    fn entry_path(&self, key: &Value) -> PathBuf {
+        self.dir.join(format!(
+            "{:032x}.json",
+            fnv1a_128(key.to_string().as_bytes())
+        ))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// Stable across builds and platforms, unlike `std::hash`
+fn fnv1a_128(bytes: &[u8]) -> u128 {
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u128::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+// Serves repeated requests from a `ResponseCache` in front of any provider. Errors are
+// never cached, and a failing cache write only costs the next call a miss.
+pub struct CachedProvider {
+    inner: Arc<dyn LlmProvider>,
+    cache: Arc<ResponseCache>,
+}
+
+impl CachedProvider {
+    pub fn new(inner: Arc<dyn LlmProvider>, cache: Arc<ResponseCache>) -> Self {
+        Self { inner, cache }
+    }
+}
+
+impl LlmProvider for CachedProvider {
+    fn chat<'a>(
+        &'a self,
+        llm_config: &'a LlmConfig,
+        request: ChatRequest<'a>,
+    ) -> BoxFuture<'a, Result<LlamaResponseMessage, ChatInnerError>> {
+        Box::pin(async move {
+            let key = ResponseCache::key(llm_config, &request);
+            if let Some(response) = self.cache.get(&key) {
+                return Ok(response);
+            }
+
+            let response = self.inner.chat(llm_config, request).await?;
+            if let Err(e) = self.cache.put(key, &response) {
+                log::warn!("Failed to cache LLM response: {}", e);
+            }
+            Ok(response)
+        })
+    }
+
+    // A hit replays as a single chunk; a miss streams live and is not cached, since
+    // a stream can be abandoned half way
+    fn chat_stream<'a>(
+        &'a self,
+        llm_config: &'a LlmConfig,
+        request: ChatRequest<'a>,
+    ) -> BoxFuture<'a, Result<ChatCompletionStream, ChatInnerError>> {
+        Box::pin(async move {
+            let key = ResponseCache::key(llm_config, &request);
+            match self.cache.get(&key) {
+                Some(response) => {
+                    let chunk = single_chunk_response(
+                        llm_config,
+                        response.content.content_to_string(),
+                        response.usage,
+                    );
+                    Ok(stream::once(async move { Ok(chunk) }).boxed())
+                }
+                None => self.inner.chat_stream(llm_config, request).await,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::immutable_agent::Message;
+    use crate::llama::provider::{text_response, usage_from_counts};
+    use crate::llama::Content;
+    use async_openai::types::Role;
+
+    fn cache() -> ResponseCache {
+        let dir = std::env::temp_dir().join(format!("autogen-cache-{}", uuid::Uuid::new_v4()));
+        ResponseCache::new(dir).unwrap()
+    }
+
+    fn key(input: &str) -> Value {
+        let messages = vec![Message::new(
+            Content::Text(input.to_string()),
+            None,
+            Role::User,
+        )];
+        let config = LlmConfig::new("model", "http://localhost");
+        ResponseCache::key(&config, &ChatRequest::new(&messages, 100))
+    }
+
+    fn response(text: &str) -> LlamaResponseMessage {
+        text_response(text.to_string(), usage_from_counts(1, 1))
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let cache = cache();
+        assert_eq!(cache.get(&key("a")), None);
+        cache.put(key("a"), &response("A")).unwrap();
+        assert_eq!(cache.get(&key("a")), Some(response("A")));
+        assert_eq!(cache.get(&key("b")), None);
+
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2 });
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn expired_entries_are_misses_and_removed() {
+        let cache = cache().with_ttl(Duration::from_secs(60));
+        let write = |input: &str, age: u64| {
+            let entry = CacheEntry {
+                key: key(input),
+                created_at: now_secs() - age,
+                response: response(input),
+            };
+            std::fs::write(
+                cache.entry_path(&key(input)),
+                serde_json::to_vec(&entry).unwrap(),
+            )
+            .unwrap();
+        };
+        write("fresh", 10);
+        write("stale", 120);
+
+        assert!(cache.get(&key("fresh")).is_some());
+        assert!(cache.get(&key("stale")).is_none());
+        assert!(!cache.entry_path(&key("stale")).exists());
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1 });
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn oldest_entries_are_evicted_past_max_bytes() {
+        let cache = cache();
+        let now = SystemTime::now();
+        for (i, input) in ["a", "b"].iter().enumerate() {
+            cache.put(key(input), &response(input)).unwrap();
+            // Distinct ages regardless of the file system's timestamp resolution
+            std::fs::File::options()
+                .write(true)
+                .open(cache.entry_path(&key(input)))
+                .unwrap()
+                .set_modified(now - Duration::from_secs(20 - 10 * i as u64))
+                .unwrap();
+        }
+        let entry_size = std::fs::metadata(cache.entry_path(&key("a")))
+            .unwrap()
+            .len();
+
+        // Room for two entries, so the third one pushes out the oldest
+        let cache = cache.with_max_bytes(entry_size * 2 + entry_size / 2);
+        cache.put(key("c"), &response("c")).unwrap();
+
+        assert!(cache.get(&key("a")).is_none());
+        assert!(cache.get(&key("b")).is_some());
+        assert!(cache.get(&key("c")).is_some());
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn concurrent_writers_of_one_key_do_not_clash() {
+        let cache = Arc::new(cache());
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        cache
+                            .put(key("same"), &response(&format!("writer {}", i)))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let text = cache.get(&key("same")).unwrap().content_to_string();
+        assert!(text.starts_with("writer "), "{}", text);
+        assert_eq!(cache.entries().unwrap().len(), 1);
+        std::fs::remove_dir_all(cache.dir()).unwrap();
+    }
+}
@@ -0,0 +1,402 @@
+use crate::llama::cache::ResponseCache;
+use crate::llama::provider::{ChatRequest, LlmProvider};
+use crate::llama::{ChatInnerError, LlamaResponseMessage, ToolCall};
+use crate::LlmConfig;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CassetteError {
+    #[error("Cassette I/O failed for {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Cassette {path} is not valid JSON: {source}")]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CassetteMode {
+    // Call the real provider and write every exchange to the cassette
+    Record,
+    // Serve exchanges from the cassette; a request it does not contain is an error
+    Replay,
+}
+
+impl FromStr for CassetteMode {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(Value::String(s.trim().to_lowercase()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    // Same shape as the response cache key, so fixtures stay readable diffs
+    request: Value,
+    response: LlamaResponseMessage,
+}
+
+// One tool run, with either its output or the error it failed with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ToolRun {
+    name: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    arguments: Option<Value>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Tape {
+    interactions: Vec<Interaction>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_runs: Vec<ToolRun>,
+    #[serde(skip)]
+    played: Vec<bool>,
+    #[serde(skip)]
+    tools_played: Vec<bool>,
+}
+
+// The first unplayed match in recording order, else the last match again
+fn next_match(matching: impl Iterator<Item = usize>, played: &mut [bool]) -> Option<usize> {
+    let matching: Vec<usize> = matching.collect();
+    let index = matching
+        .iter()
+        .copied()
+        .find(|&i| !played[i])
+        .or_else(|| matching.last().copied())?;
+    played[index] = true;
+    Some(index)
+}
+
+// A fixture file of recorded LLM exchanges and tool runs, one JSON document per
+// cassette. Replay hands out matching entries in recording order, so a conversation
+// that sends the same request twice gets both recorded answers back; once they are
+// used up the last one is repeated. Replayed tools are not run at all.
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    tape: Mutex<Tape>,
+}
+
+impl Cassette {
+    // Recording starts from an empty tape and overwrites `path` on the first exchange
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mode: CassetteMode::Record,
+            tape: Mutex::new(Tape::default()),
+        }
+    }
+
+    pub fn replay(path: impl Into<PathBuf>) -> Result<Self, CassetteError> {
+        let path = path.into();
+        let bytes = std::fs::read(&path).map_err(|source| CassetteError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        let mut tape: Tape =
+            serde_json::from_slice(&bytes).map_err(|source| CassetteError::Json {
+                path: path.clone(),
+                source,
+            })?;
+        tape.played = vec![false; tape.interactions.len()];
+        tape.tools_played = vec![false; tape.tool_runs.len()];
+        Ok(Self {
+            path,
+            mode: CassetteMode::Replay,
+            tape: Mutex::new(tape),
+        })
+    }
+
+    pub fn open(path: impl Into<PathBuf>, mode: CassetteMode) -> Result<Self, CassetteError> {
+        match mode {
+            CassetteMode::Record => Ok(Self::record(path)),
+            CassetteMode::Replay => Self::replay(path),
+        }
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.tape.lock().unwrap().interactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn play(&self, request: &Value) -> Option<LlamaResponseMessage> {
+        let mut tape = self.tape.lock().unwrap();
+        let Tape {
+            interactions,
+            played,
+            ..
+        } = &mut *tape;
+        let matching = (0..interactions.len()).filter(|&i| interactions[i].request == *request);
+        let index = next_match(matching, played)?;
+        Some(interactions[index].response.clone())
+    }
+
+    // The recorded outcome of the same tool called with the same arguments: its output,
+    // or the error it failed with
+    pub fn play_tool(&self, call: &ToolCall) -> Option<Result<String, String>> {
+        let mut tape = self.tape.lock().unwrap();
+        let Tape {
+            tool_runs,
+            tools_played,
+            ..
+        } = &mut *tape;
+        let matching = (0..tool_runs.len()).filter(|&i| {
+            tool_runs[i].name == call.name && tool_runs[i].arguments == call.arguments
+        });
+        let run = &tool_runs[next_match(matching, tools_played)?];
+        Some(match &run.error {
+            Some(error) => Err(error.clone()),
+            None => Ok(run.output.clone().unwrap_or_default()),
+        })
+    }
+
+    pub fn record_tool(&self, call: &ToolCall, result: Result<&str, String>) {
+        let mut tape = self.tape.lock().unwrap();
+        let (output, error) = match result {
+            Ok(output) => (Some(output.to_string()), None),
+            Err(error) => (None, Some(error)),
+        };
+        tape.tool_runs.push(ToolRun {
+            name: call.name.clone(),
+            arguments: call.arguments.clone(),
+            output,
+            error,
+        });
+        if let Err(e) = self.save(&tape) {
+            log::warn!("Failed to record tool run: {}", e);
+        }
+    }
+
+    fn append(&self, request: Value, response: &LlamaResponseMessage) -> Result<(), CassetteError> {
+        let mut tape = self.tape.lock().unwrap();
+        tape.interactions.push(Interaction {
+            request,
+            response: response.clone(),
+        });
+        self.save(&tape)
+    }
+
+    // The whole tape is rewritten after each exchange so an aborted run still leaves
+    // a usable fixture behind
+    fn save(&self, tape: &Tape) -> Result<(), CassetteError> {
+        let io_error = |source| CassetteError::Io {
+            path: self.path.clone(),
+            source,
+        };
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        let json = serde_json::to_vec_pretty(tape).map_err(|source| CassetteError::Json {
+            path: self.path.clone(),
+            source,
+        })?;
+        std::fs::write(&self.path, json).map_err(io_error)
+    }
+}
+
+// Puts a `Cassette` in front of a provider. Streaming goes through the trait's
+// single-chunk default, so streamed calls are recorded and replayed like any other.
+pub struct CassetteProvider {
+    inner: Arc<dyn LlmProvider>,
+    cassette: Arc<Cassette>,
+}
+
+impl CassetteProvider {
+    pub fn new(inner: Arc<dyn LlmProvider>, cassette: Arc<Cassette>) -> Self {
+        Self { inner, cassette }
+    }
+}
+
+impl LlmProvider for CassetteProvider {
+    fn chat<'a>(
+        &'a self,
+        llm_config: &'a LlmConfig,
+        request: ChatRequest<'a>,
+    ) -> BoxFuture<'a, Result<LlamaResponseMessage, ChatInnerError>> {
+        Box::pin(async move {
+            let key = ResponseCache::key(llm_config, &request);
+            match self.cassette.mode {
+                CassetteMode::Replay => {
+                    self.cassette
+                        .play(&key)
+                        .ok_or_else(|| ChatInnerError::CassetteMiss {
+                            path: self.cassette.path.display().to_string(),
+                            input: request.last_user_input(),
+                        })
+                }
+                CassetteMode::Record => {
+                    let response = self.inner.chat(llm_config, request).await?;
+                    if let Err(e) = self.cassette.append(key, &response) {
+                        log::warn!("Failed to record LLM exchange: {}", e);
+                    }
+                    Ok(response)
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::immutable_agent::LlmAgent;
+    use crate::llama::mock::MockLlm;
+    use crate::llama::{Content, StructuredText};
+    use crate::{
+        GET_WEATHER_TOOL_DEF_OBJ, TEMPLATE_SYSTEM_PROMPT_PLANNER, TEMPLATE_USER_PROMPT_TASK_JSON,
+        TEMPLATE_USER_PROMPT_TOOL_USE,
+    };
+    use serde_json::json;
+
+    const FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/fixtures/cassettes/plan_and_weather.json"
+    );
+
+    fn weather_call(location: &str) -> String {
+        format!(
+            "<tool_call>\n{}\n</tool_call>",
+            json!({
+                "name": "get_current_weather",
+                "arguments": { "location": location, "unit": "celsius" },
+            })
+        )
+    }
+
+    fn planner(mock: MockLlm, cassette: Arc<Cassette>) -> LlmAgent {
+        LlmAgent::build(
+            TEMPLATE_SYSTEM_PROMPT_PLANNER.to_string(),
+            Some(TEMPLATE_USER_PROMPT_TASK_JSON.clone()),
+            None,
+            None,
+            "planner".to_string(),
+        )
+        .unwrap()
+        .with_provider(Arc::new(mock))
+        .with_cassette(cassette)
+    }
+
+    fn weather_agent(mock: MockLlm, cassette: Arc<Cassette>) -> LlmAgent {
+        let tools: Value = serde_json::from_str(GET_WEATHER_TOOL_DEF_OBJ).unwrap();
+        LlmAgent::build(
+            String::new(),
+            Some(TEMPLATE_USER_PROMPT_TOOL_USE.clone()),
+            None,
+            Some(json!([tools])),
+            "weather agent".to_string(),
+        )
+        .unwrap()
+        .with_provider(Arc::new(mock))
+        .with_cassette(cassette)
+    }
+
+    fn temp_cassette() -> PathBuf {
+        std::env::temp_dir().join(format!("cassette-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn fixture_replays_the_planner() {
+        // Nothing is scripted, so any request that reaches the provider fails
+        let cassette = Arc::new(Cassette::replay(FIXTURE).unwrap());
+        let response = planner(MockLlm::new(), cassette)
+            .default_method("Plan what to wear in Paris today")
+            .await
+            .unwrap();
+
+        let Content::Structured(StructuredText::Tasks(tasks)) = response.content else {
+            panic!("expected a task list, got {:?}", response.content);
+        };
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].tool.as_deref(), Some("get_current_weather"));
+        assert_eq!(tasks[1].depends_on, ["1"]);
+    }
+
+    #[tokio::test]
+    async fn fixture_replays_tool_runs_without_running_the_tool() {
+        // The real tool fails for Paris; the tape says otherwise
+        let cassette = Arc::new(Cassette::replay(FIXTURE).unwrap());
+        let response = weather_agent(MockLlm::new(), cassette)
+            .default_method("What is the weather in Paris?")
+            .await
+            .unwrap();
+
+        assert_eq!(response.content_to_string(), "Sunny, 18 celsius");
+        assert_eq!(response.tool_calls[0].name, "get_current_weather");
+    }
+
+    #[tokio::test]
+    async fn recorded_tool_runs_replay_identically() {
+        let path = temp_cassette();
+        let recorder = Arc::new(Cassette::record(&path));
+        let mock = MockLlm::new()
+            .with_response(weather_call("New York"))
+            .with_response(weather_call("Paris"));
+        let agent = weather_agent(mock, recorder.clone());
+        let sunny = agent.default_method("Weather in New York?").await.unwrap();
+        let failed = agent.default_method("Weather in Paris?").await.unwrap();
+        assert_eq!(recorder.len(), 2);
+
+        let player = Arc::new(Cassette::replay(&path).unwrap());
+        let agent = weather_agent(MockLlm::new(), player);
+        let replayed = agent.default_method("Weather in New York?").await.unwrap();
+        assert_eq!(replayed.content, sunny.content);
+        // A recorded failure comes back with the same message
+        let replayed = agent.default_method("Weather in Paris?").await.unwrap();
+        assert_eq!(replayed.content, failed.content);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unrecorded_tool_run_is_not_replayed() {
+        let path = temp_cassette();
+        let recorder = Arc::new(Cassette::record(&path));
+        weather_agent(
+            MockLlm::new().with_response(weather_call("New York")),
+            recorder,
+        )
+        .default_method("Weather in New York?")
+        .await
+        .unwrap();
+
+        let player = Cassette::replay(&path).unwrap();
+        let call = ToolCall {
+            id: None,
+            name: "get_current_weather".to_string(),
+            arguments: Some(json!({ "location": "Boston", "unit": "celsius" })),
+        };
+        assert!(player.play_tool(&call).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,368 @@
+use crate::immutable_agent::Message;
+use crate::llama::{Content, StructuredText, ToolCall};
+use async_openai::types::Role;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+// How a conversation is flattened into one prompt for raw-completion backends
+// (llama.cpp's `/completion`). Chat endpoints apply the model's own template
+// server-side, so messages sent there carry no markers at all.
+//
+// BOS tokens (`<s>`, `<|begin_of_text|>`, `<bos>`) are left out: the server adds one
+// when it tokenizes the prompt, and a second one degrades output.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatTemplate {
+    // "System: ...\nUser: ...\nAssistant:", for base models without a template
+    #[default]
+    Plain,
+    #[serde(rename = "chatml")]
+    ChatMl,
+    #[serde(alias = "llama-3", alias = "llama_3")]
+    Llama3,
+    Gemma,
+    Mistral,
+    // ChatML framing with the Hermes/Qwen `<tool_call>` and `<tool_response>` tags
+    Hermes,
+}
+
+impl FromStr for ChatTemplate {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(Value::String(s.trim().to_lowercase()))
+    }
+}
+
+impl ChatTemplate {
+    // Best guess from a model id such as "meta-llama/Llama-3.3-70B-Instruct-Turbo"
+    // or a GGUF file name; `Plain` when nothing matches.
+    pub fn for_model(model: &str) -> Self {
+        let model = model.to_lowercase();
+        if model.contains("hermes") || model.contains("qwen") {
+            ChatTemplate::Hermes
+        } else if model.contains("llama-3") || model.contains("llama3") {
+            ChatTemplate::Llama3
+        } else if model.contains("gemma") {
+            ChatTemplate::Gemma
+        } else if model.contains("mistral") || model.contains("mixtral") {
+            ChatTemplate::Mistral
+        } else if model.contains("chatml") || model.contains("yi-") {
+            ChatTemplate::ChatMl
+        } else {
+            ChatTemplate::Plain
+        }
+    }
+
+    // The whole conversation, ending with the opening of the assistant's turn
+    pub fn render(&self, messages: &[Message]) -> String {
+        match self {
+            ChatTemplate::Plain => render_plain(messages),
+            ChatTemplate::ChatMl | ChatTemplate::Hermes => self.render_chatml(messages),
+            ChatTemplate::Llama3 => render_llama3(messages),
+            ChatTemplate::Gemma => render_gemma(messages),
+            ChatTemplate::Mistral => render_mistral(messages),
+        }
+    }
+
+    // Sequences that end the assistant's turn, to pass as the completion's `stop`
+    pub fn stop_sequences(&self) -> Vec<String> {
+        let stops: &[&str] = match self {
+            ChatTemplate::Plain => &["\nUser:", "\nSystem:"],
+            ChatTemplate::ChatMl | ChatTemplate::Hermes => &["<|im_end|>", "<|im_start|>"],
+            ChatTemplate::Llama3 => &["<|eot_id|>", "<|eom_id|>"],
+            ChatTemplate::Gemma => &["<end_of_turn>"],
+            ChatTemplate::Mistral => &["</s>", "[INST]"],
+        };
+        stops.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn render_chatml(&self, messages: &[Message]) -> String {
+        let mut prompt = String::new();
+        for message in messages {
+            let (role, text) = match (message.role.clone(), &message.content) {
+                (Role::Assistant, Content::Structured(StructuredText::ToolCall(call))) => (
+                    "assistant",
+                    format!(
+                        "<tool_call>\n{}\n</tool_call>",
+                        call_json(call, "arguments")
+                    ),
+                ),
+                (Role::Tool | Role::Function, content) if *self == ChatTemplate::Hermes => (
+                    "tool",
+                    format!(
+                        "<tool_response>\n{}\n</tool_response>",
+                        json!({
+                            "name": message.name.clone().unwrap_or_default(),
+                            "content": content.content_to_string(),
+                        })
+                    ),
+                ),
+                (role, content) => (role_name(&role), content.content_to_string()),
+            };
+            prompt.push_str(&format!("<|im_start|>{}\n{}<|im_end|>\n", role, text));
+        }
+        prompt.push_str("<|im_start|>assistant\n");
+        prompt
+    }
+}
+
+fn role_name(role: &Role) -> &'static str {
+    match role {
+        Role::System => "system",
+        Role::User => "user",
+        Role::Assistant => "assistant",
+        Role::Tool | Role::Function => "tool",
+    }
+}
+
+fn call_json(call: &ToolCall, arguments_key: &str) -> Value {
+    json!({
+        "name": call.name,
+        arguments_key: call.arguments.clone().unwrap_or_else(|| json!({})),
+    })
+}
+
+fn render_plain(messages: &[Message]) -> String {
+    let mut prompt = messages
+        .iter()
+        .map(|m| {
+            let speaker = match m.role {
+                Role::System => "System",
+                Role::User => "User",
+                Role::Assistant => "Assistant",
+                Role::Tool | Role::Function => "Tool",
+            };
+            format!("{}: {}\n", speaker, m.content.content_to_string())
+        })
+        .collect::<String>();
+    prompt.push_str("Assistant:");
+    prompt
+}
+
+fn render_llama3(messages: &[Message]) -> String {
+    let mut prompt = String::new();
+    for message in messages {
+        let (role, text) = match (message.role.clone(), &message.content) {
+            (Role::Assistant, Content::Structured(StructuredText::ToolCall(call))) => (
+                "assistant",
+                format!("<|python_tag|>{}", call_json(call, "parameters")),
+            ),
+            (Role::Tool | Role::Function, content) => ("ipython", content.content_to_string()),
+            (role, content) => (role_name(&role), content.content_to_string()),
+        };
+        prompt.push_str(&format!(
+            "<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>",
+            role, text
+        ));
+    }
+    prompt.push_str("<|start_header_id|>assistant<|end_header_id|>\n\n");
+    prompt
+}
+
+// Gemma has no system or tool role: the system prompt is folded into the first user
+// turn and tool results come back as user turns.
+fn render_gemma(messages: &[Message]) -> String {
+    let mut prompt = String::new();
+    let mut pending_system = Vec::new();
+    for message in messages {
+        let text = match &message.content {
+            Content::Structured(StructuredText::ToolCall(call)) => {
+                format!(
+                    "<tool_call>\n{}\n</tool_call>",
+                    call_json(call, "arguments")
+                )
+            }
+            content => content.content_to_string(),
+        };
+        match message.role {
+            Role::System => pending_system.push(text),
+            Role::Assistant => {
+                prompt.push_str(&format!("<start_of_turn>model\n{}<end_of_turn>\n", text))
+            }
+            _ => {
+                pending_system.push(text);
+                prompt.push_str(&format!(
+                    "<start_of_turn>user\n{}<end_of_turn>\n",
+                    pending_system.join("\n\n")
+                ));
+                pending_system.clear();
+            }
+        }
+    }
+    prompt.push_str("<start_of_turn>model\n");
+    prompt
+}
+
+// `[INST] ... [/INST]` pairs; like Gemma, system text rides along with the next user turn
+fn render_mistral(messages: &[Message]) -> String {
+    let mut prompt = String::new();
+    let mut pending_system = Vec::new();
+    for message in messages {
+        match (&message.role, &message.content) {
+            (Role::System, content) => pending_system.push(content.content_to_string()),
+            (Role::Assistant, Content::Structured(StructuredText::ToolCall(call))) => {
+                prompt.push_str(&format!(
+                    "[TOOL_CALLS] {}</s>",
+                    json!([call_json(call, "arguments")])
+                ));
+            }
+            (Role::Assistant, content) => {
+                prompt.push_str(&format!(" {}</s>", content.content_to_string()));
+            }
+            (Role::Tool | Role::Function, content) => {
+                prompt.push_str(&format!(
+                    "[TOOL_RESULTS] {} [/TOOL_RESULTS]",
+                    json!({ "content": content.content_to_string() })
+                ));
+            }
+            (_, content) => {
+                pending_system.push(content.content_to_string());
+                prompt.push_str(&format!("[INST] {} [/INST]", pending_system.join("\n\n")));
+                pending_system.clear();
+            }
+        }
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(role: Role, text: &str) -> Message {
+        Message::new(Content::Text(text.to_string()), None, role)
+    }
+
+    // System prompt, question, a tool call and its result
+    fn tool_conversation() -> Vec<Message> {
+        vec![
+            text(Role::System, "Be brief."),
+            text(Role::User, "Weather in Paris?"),
+            Message::new(
+                Content::Structured(StructuredText::ToolCall(ToolCall {
+                    id: Some("call_1".to_string()),
+                    name: "get_weather".to_string(),
+                    arguments: Some(json!({"city": "Paris"})),
+                })),
+                None,
+                Role::Assistant,
+            ),
+            Message::tool_result(
+                Some("call_1".to_string()),
+                "get_weather".to_string(),
+                "18C".to_string(),
+            ),
+        ]
+    }
+
+    fn chat_conversation() -> Vec<Message> {
+        vec![
+            text(Role::System, "Be brief."),
+            text(Role::User, "Weather in Paris?"),
+            text(Role::Assistant, "Sunny."),
+            text(Role::User, "Thanks"),
+        ]
+    }
+
+    #[test]
+    fn for_model_picks_the_family() {
+        let cases = [
+            (
+                "meta-llama/Llama-3.3-70B-Instruct-Turbo",
+                ChatTemplate::Llama3,
+            ),
+            ("llama3.1:8b", ChatTemplate::Llama3),
+            ("google/gemma-2-9b-it", ChatTemplate::Gemma),
+            ("mistralai/Mistral-7B-Instruct-v0.3", ChatTemplate::Mistral),
+            ("mixtral-8x7b-instruct.Q4_K_M.gguf", ChatTemplate::Mistral),
+            ("NousResearch/Hermes-3-Llama-3.1-8B", ChatTemplate::Hermes),
+            ("Qwen/Qwen2.5-7B-Instruct", ChatTemplate::Hermes),
+            ("01-ai/Yi-34B-Chat", ChatTemplate::ChatMl),
+            ("openhermes-chatml", ChatTemplate::Hermes),
+            ("gpt2", ChatTemplate::Plain),
+        ];
+        for (model, expected) in cases {
+            assert_eq!(ChatTemplate::for_model(model), expected, "{}", model);
+        }
+        assert_eq!(
+            "Llama-3".parse::<ChatTemplate>().unwrap(),
+            ChatTemplate::Llama3
+        );
+        assert_eq!(
+            "chatml".parse::<ChatTemplate>().unwrap(),
+            ChatTemplate::ChatMl
+        );
+    }
+
+    #[test]
+    fn renders_llama3() {
+        assert_eq!(
+            ChatTemplate::Llama3.render(&tool_conversation()),
+            "<|start_header_id|>system<|end_header_id|>\n\nBe brief.<|eot_id|>\
+             <|start_header_id|>user<|end_header_id|>\n\nWeather in Paris?<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\n\
+             <|python_tag|>{\"name\":\"get_weather\",\"parameters\":{\"city\":\"Paris\"}}<|eot_id|>\
+             <|start_header_id|>ipython<|end_header_id|>\n\n18C<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+    }
+
+    #[test]
+    fn renders_gemma_with_the_system_prompt_in_the_first_user_turn() {
+        assert_eq!(
+            ChatTemplate::Gemma.render(&chat_conversation()),
+            "<start_of_turn>user\nBe brief.\n\nWeather in Paris?<end_of_turn>\n\
+             <start_of_turn>model\nSunny.<end_of_turn>\n\
+             <start_of_turn>user\nThanks<end_of_turn>\n\
+             <start_of_turn>model\n"
+        );
+    }
+
+    #[test]
+    fn renders_mistral() {
+        assert_eq!(
+            ChatTemplate::Mistral.render(&chat_conversation()),
+            "[INST] Be brief.\n\nWeather in Paris? [/INST] Sunny.</s>[INST] Thanks [/INST]"
+        );
+        assert_eq!(
+            ChatTemplate::Mistral.render(&tool_conversation()),
+            "[INST] Be brief.\n\nWeather in Paris? [/INST]\
+             [TOOL_CALLS] [{\"arguments\":{\"city\":\"Paris\"},\"name\":\"get_weather\"}]</s>\
+             [TOOL_RESULTS] {\"content\":\"18C\"} [/TOOL_RESULTS]"
+        );
+    }
+
+    #[test]
+    fn renders_hermes_tool_calls_and_responses() {
+        assert_eq!(
+            ChatTemplate::Hermes.render(&tool_conversation()),
+            "<|im_start|>system\nBe brief.<|im_end|>\n\
+             <|im_start|>user\nWeather in Paris?<|im_end|>\n\
+             <|im_start|>assistant\n<tool_call>\n\
+             {\"arguments\":{\"city\":\"Paris\"},\"name\":\"get_weather\"}\n\
+             </tool_call><|im_end|>\n\
+             <|im_start|>tool\n<tool_response>\n\
+             {\"content\":\"18C\",\"name\":\"get_weather\"}\n\
+             </tool_response><|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+    }
+
+    #[test]
+    fn renders_chatml_tool_results_as_plain_text() {
+        assert_eq!(
+            ChatTemplate::ChatMl.render(&tool_conversation()[3..]),
+            "<|im_start|>tool\n18C<|im_end|>\n<|im_start|>assistant\n"
+        );
+    }
+
+    #[test]
+    fn renders_plain() {
+        assert_eq!(
+            ChatTemplate::Plain.render(&chat_conversation()),
+            "System: Be brief.\nUser: Weather in Paris?\nAssistant: Sunny.\nUser: Thanks\nAssistant:"
+        );
+    }
+}
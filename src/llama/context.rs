@@ -0,0 +1,310 @@
+use crate::immutable_agent::Message;
+use crate::llama::tokens::Tokenizer;
+use crate::llama::{Content, StructuredText};
+use async_openai::types::Role;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum TrimStrategy {
+    // Drop the oldest history first
+    #[default]
+    DropOldest,
+    // Keep the first `first` and last `last` history messages, dropping the middle;
+    // then oldest-first if that is still too much
+    KeepFirstLast {
+        first: usize,
+        last: usize,
+    },
+    // Drop oldest-first, then have the model summarise what was dropped into the system
+    // prompt; the summarising is the agent's job, see `Fitted::dropped`
+    SummarizeOverflow,
+}
+
+#[derive(Debug, Error)]
+pub enum ContextError {
+    #[error("Prompt needs {needed} tokens but only {available} fit in the context window")]
+    Overflow { needed: usize, available: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct Fitted {
+    pub messages: Vec<Message>,
+    // In their original order, for `TrimStrategy::SummarizeOverflow`
+    pub dropped: Vec<Message>,
+    // The output reservation, shrunk when even the untrimmable prompt leaves less room
+    pub max_token: u16,
+}
+
+// Trims `messages` so that they, `extra_tokens` (tool schemas sent alongside) and a
+// reply of `max_token` fit in `context_size`. The leading system prompt and the last
+// message (the current input) are never dropped.
+pub fn fit_to_context(
+    messages: Vec<Message>,
+    tokenizer: &Tokenizer,
+    context_size: usize,
+    extra_tokens: usize,
+    max_token: u16,
+    strategy: &TrimStrategy,
+) -> Result<Fitted, ContextError> {
+    let available = context_size.saturating_sub(extra_tokens);
+    let fits = |tokens: usize| tokens + max_token as usize <= available;
+
+    let mut total = tokenizer.count_messages(&messages);
+    if fits(total) {
+        return Ok(Fitted {
+            messages,
+            dropped: Vec::new(),
+            max_token,
+        });
+    }
+
+    let head = usize::from(messages.first().map_or(false, |m| m.role == Role::System));
+    let tail = messages.len().saturating_sub(1).max(head);
+    let history: Vec<usize> = (head..tail).collect();
+
+    let mut keep = vec![true; messages.len()];
+    let mut drop_index = |i: usize, keep: &mut Vec<bool>, total: &mut usize| {
+        if keep[i] {
+            keep[i] = false;
+            *total -= tokenizer.count_message(&messages[i]);
+        }
+    };
+
+    let order: Vec<usize> = match strategy {
+        TrimStrategy::KeepFirstLast { first, last } => {
+            let first = (*first).min(history.len());
+            let last = (*last).min(history.len() - first);
+            let middle = &history[first..history.len() - last];
+            for &i in middle {
+                drop_index(i, &mut keep, &mut total);
+            }
+            history[history.len() - last..]
+                .iter()
+                .chain(&history[..first])
+                .copied()
+                .collect()
+        }
+        TrimStrategy::DropOldest | TrimStrategy::SummarizeOverflow => history.clone(),
+    };
+    for i in order {
+        if fits(total) {
+            break;
+        }
+        drop_index(i, &mut keep, &mut total);
+    }
+    drop_orphaned_tool_messages(&messages, &mut keep);
+
+    let mut kept = Vec::new();
+    let mut dropped = Vec::new();
+    for (message, keep) in messages.into_iter().zip(keep) {
+        if keep {
+            kept.push(message);
+        } else {
+            dropped.push(message);
+        }
+    }
+
+    let needed = tokenizer.count_messages(&kept);
+    if needed >= available {
+        return Err(ContextError::Overflow { needed, available });
+    }
+    let room = (available - needed).min(u16::MAX as usize) as u16;
+
+    Ok(Fitted {
+        messages: kept,
+        dropped,
+        max_token: max_token.min(room),
+    })
+}
+
+// Chat APIs reject a tool result without the assistant call before it, and a call
+// without its result, so trimming never leaves half of a pair behind.
+fn drop_orphaned_tool_messages(messages: &[Message], keep: &mut [bool]) {
+    let is_call = |m: &Message| {
+        m.role == Role::Assistant
+            && matches!(m.content, Content::Structured(StructuredText::ToolCall(_)))
+    };
+    let is_result = |m: &Message| matches!(m.role, Role::Tool | Role::Function);
+
+    for i in 0..messages.len() {
+        if !keep[i] {
+            continue;
+        }
+        let previous = (0..i).rev().find(|&j| keep[j]).map(|j| &messages[j]);
+        let next = (i + 1..messages.len())
+            .find(|&j| keep[j])
+            .map(|j| &messages[j]);
+        if is_result(&messages[i]) && !previous.map_or(false, |p| is_call(p) || is_result(p)) {
+            keep[i] = false;
+        } else if is_call(&messages[i]) && !next.map_or(false, is_result) {
+            keep[i] = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llama::ToolCall;
+
+    // One token per character, so each message costs its length plus MESSAGE_OVERHEAD (4)
+    // and every prompt a REPLY_PRIMER (3)
+    const TOKENIZER: Tokenizer = Tokenizer::CharEstimate {
+        chars_per_token: 1.0,
+    };
+
+    fn text(role: Role, text: &str) -> Message {
+        Message::new(Content::Text(text.to_string()), None, role)
+    }
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            text(Role::System, "sys"),
+            text(Role::User, "one"),
+            text(Role::Assistant, "two"),
+            text(Role::User, "three"),
+            text(Role::Assistant, "four"),
+            text(Role::User, "now"),
+        ]
+    }
+
+    fn contents(messages: &[Message]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|m| m.content.content_to_string())
+            .collect()
+    }
+
+    #[test]
+    fn prompt_that_fits_is_untouched() {
+        let fitted = fit_to_context(
+            conversation(),
+            &TOKENIZER,
+            1000,
+            0,
+            100,
+            &TrimStrategy::DropOldest,
+        )
+        .unwrap();
+        assert_eq!(fitted.messages.len(), 6);
+        assert!(fitted.dropped.is_empty());
+        assert_eq!(fitted.max_token, 100);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_system_prompt_and_input() {
+        // Whole prompt: 3+3+3+5+4+3 chars + 6*4 + 3 = 48 tokens; 40 fits after two drops
+        let fitted = fit_to_context(
+            conversation(),
+            &TOKENIZER,
+            50,
+            0,
+            10,
+            &TrimStrategy::DropOldest,
+        )
+        .unwrap();
+        assert_eq!(contents(&fitted.messages), ["sys", "three", "four", "now"]);
+        assert_eq!(contents(&fitted.dropped), ["one", "two"]);
+    }
+
+    #[test]
+    fn keep_first_last_drops_the_middle() {
+        let fitted = fit_to_context(
+            conversation(),
+            &TOKENIZER,
+            50,
+            0,
+            10,
+            &TrimStrategy::KeepFirstLast { first: 1, last: 1 },
+        )
+        .unwrap();
+        assert_eq!(contents(&fitted.messages), ["sys", "one", "four", "now"]);
+        assert_eq!(contents(&fitted.dropped), ["two", "three"]);
+    }
+
+    #[test]
+    fn tool_schemas_count_against_the_window() {
+        let fitted = fit_to_context(
+            conversation(),
+            &TOKENIZER,
+            60,
+            10,
+            10,
+            &TrimStrategy::DropOldest,
+        )
+        .unwrap();
+        assert_eq!(fitted.dropped.len(), 2);
+    }
+
+    #[test]
+    fn reply_budget_shrinks_to_the_room_left() {
+        // Only system prompt and input remain: 3+3 chars + 2*4 + 3 = 17 tokens
+        let fitted = fit_to_context(
+            conversation(),
+            &TOKENIZER,
+            25,
+            0,
+            100,
+            &TrimStrategy::DropOldest,
+        )
+        .unwrap();
+        assert_eq!(contents(&fitted.messages), ["sys", "now"]);
+        assert_eq!(fitted.max_token, 8);
+    }
+
+    #[test]
+    fn overflow_when_even_the_input_does_not_fit() {
+        let err = fit_to_context(
+            conversation(),
+            &TOKENIZER,
+            10,
+            0,
+            5,
+            &TrimStrategy::DropOldest,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContextError::Overflow {
+                needed: 17,
+                available: 10
+            }
+        ));
+    }
+
+    #[test]
+    fn tool_call_and_result_are_dropped_together() {
+        let call = Message::new(
+            Content::Structured(StructuredText::ToolCall(ToolCall {
+                id: Some("call_1".to_string()),
+                name: "f".to_string(),
+                arguments: None,
+            })),
+            None,
+            Role::Assistant,
+        );
+        let messages = vec![
+            text(Role::System, "sys"),
+            text(Role::User, "aaaaaaaaaaaaaaaaaaaa"),
+            call,
+            Message::tool_result(
+                Some("call_1".to_string()),
+                "f".to_string(),
+                "ok".to_string(),
+            ),
+            text(Role::User, "now"),
+        ];
+        // Keeping only the last history message would leave the result without its call
+        let total = TOKENIZER.count_messages(&messages);
+        let fitted = fit_to_context(
+            messages,
+            &TOKENIZER,
+            total - 1,
+            0,
+            0,
+            &TrimStrategy::KeepFirstLast { first: 0, last: 1 },
+        )
+        .unwrap();
+        assert_eq!(contents(&fitted.messages), ["sys", "now"]);
+    }
+}
@@ -0,0 +1,432 @@
+use std::iter::Peekable;
+use std::str::Chars;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum JsonRepairError {
+    #[error("No JSON value found in input")]
+    NoJson,
+    #[error("Repaired output is still not valid JSON: {0}")]
+    Unrepairable(String),
+}
+
+// Lenient re-serializer for the JSON-ish text models produce. Handles markdown fences,
+// leading prose, comments, trailing or missing commas, single-quoted strings, unquoted
+// keys, Python/JS literals (None, True, NaN, undefined), stray inner quotes and
+// truncated output, which is closed off at whatever depth it stopped. Keys keep their
+// original order and numbers their original spelling.
+pub fn repair_json(input: &str) -> Result<String, JsonRepairError> {
+    let text = strip_fences(input.trim());
+    if serde_json::from_str::<serde_json::Value>(text).is_ok() {
+        return Ok(text.to_string());
+    }
+
+    let start = text.find(['{', '[']).unwrap_or(0);
+    let mut repairer = Repairer {
+        chars: text[start..].chars().peekable(),
+        out: String::with_capacity(text.len() + 16),
+    };
+    repairer.skip_trivia();
+    if repairer.chars.peek().is_none() {
+        return Err(JsonRepairError::NoJson);
+    }
+    repairer.value();
+
+    serde_json::from_str::<serde_json::Value>(&repairer.out)
+        .map(|_| repairer.out)
+        .map_err(|e| JsonRepairError::Unrepairable(e.to_string()))
+}
+
+fn strip_fences(text: &str) -> &str {
+    let Some(open) = text.find("```") else {
+        return text;
+    };
+    let body = &text[open + 3..];
+    // Skip the info string, e.g. ```json
+    let body = body.find('\n').map_or(body, |nl| &body[nl + 1..]);
+    match body.find("```") {
+        Some(close) => body[..close].trim(),
+        None => body.trim(),
+    }
+}
+
+struct Repairer<'a> {
+    chars: Peekable<Chars<'a>>,
+    out: String,
+}
+
+impl Repairer<'_> {
+    fn skip_trivia(&mut self) {
+        loop {
+            match self.chars.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.chars.next();
+                }
+                Some('/') => {
+                    let mut ahead = self.chars.clone();
+                    ahead.next();
+                    match ahead.peek() {
+                        Some('/') => self.skip_line(),
+                        Some('*') => {
+                            self.chars.next();
+                            self.chars.next();
+                            let mut prev = '\0';
+                            for c in self.chars.by_ref() {
+                                if prev == '*' && c == '/' {
+                                    break;
+                                }
+                                prev = c;
+                            }
+                        }
+                        _ => return,
+                    }
+                }
+                Some('#') => self.skip_line(),
+                _ => return,
+            }
+        }
+    }
+
+    fn skip_line(&mut self) {
+        for c in self.chars.by_ref() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    fn value(&mut self) {
+        self.skip_trivia();
+        match self.chars.peek().copied() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some(q @ ('"' | '\'' | '“' | '”')) => {
+                self.chars.next();
+                self.string(q);
+            }
+            Some(c) if c == '-' || c == '+' || c == '.' || c.is_ascii_digit() => self.number(),
+            Some(_) => self.bare_value(),
+            None => self.out.push_str("null"),
+        }
+    }
+
+    fn object(&mut self) {
+        self.chars.next();
+        self.out.push('{');
+        let mut first = true;
+        loop {
+            self.skip_trivia();
+            match self.chars.peek().copied() {
+                None | Some('}') | Some(']') => {
+                    if self.chars.peek() == Some(&'}') {
+                        self.chars.next();
+                    }
+                    self.out.push('}');
+                    return;
+                }
+                Some(',') => {
+                    self.chars.next();
+                    continue;
+                }
+                Some(_) => {}
+            }
+
+            if !first {
+                self.out.push(',');
+            }
+            first = false;
+            self.key();
+
+            self.skip_trivia();
+            if self.chars.peek() == Some(&':') {
+                self.chars.next();
+            }
+            self.out.push(':');
+            self.skip_trivia();
+            match self.chars.peek() {
+                // `{"a": }` or output cut right after the colon
+                None | Some(',') | Some('}') => self.out.push_str("null"),
+                _ => self.value(),
+            }
+        }
+    }
+
+    fn key(&mut self) {
+        match self.chars.peek().copied() {
+            Some(q @ ('"' | '\'' | '“' | '”')) => {
+                self.chars.next();
+                self.string(q);
+            }
+            _ => {
+                let mut key = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if c == ':' || c == ',' || c == '}' || c.is_whitespace() {
+                        break;
+                    }
+                    key.push(c);
+                    self.chars.next();
+                }
+                self.push_string(&key);
+            }
+        }
+    }
+
+    fn array(&mut self) {
+        self.chars.next();
+        self.out.push('[');
+        let mut first = true;
+        loop {
+            self.skip_trivia();
+            match self.chars.peek().copied() {
+                None | Some(']') | Some('}') => {
+                    if self.chars.peek() == Some(&']') {
+                        self.chars.next();
+                    }
+                    self.out.push(']');
+                    return;
+                }
+                Some(',') => {
+                    self.chars.next();
+                    continue;
+                }
+                Some(_) => {}
+            }
+
+            if !first {
+                self.out.push(',');
+            }
+            first = false;
+            self.value();
+        }
+    }
+
+    // A quote only closes the string when what follows looks like JSON structure,
+    // so `"he said "hi" twice"` survives as one string.
+    fn string(&mut self, open: char) {
+        let closers: &[char] = match open {
+            '\'' => &['\''],
+            _ => &['"', '“', '”'],
+        };
+        let mut content = String::new();
+        while let Some(c) = self.chars.next() {
+            match c {
+                '\\' => match self.chars.next() {
+                    Some('n') => content.push('\n'),
+                    Some('t') => content.push('\t'),
+                    Some('r') => content.push('\r'),
+                    Some('b') => content.push('\u{8}'),
+                    Some('f') => content.push('\u{c}'),
+                    Some('u') => {
+                        let hex: String = self.chars.by_ref().take(4).collect();
+                        match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                            Some(decoded) => content.push(decoded),
+                            None => {
+                                content.push_str("\\u");
+                                content.push_str(&hex);
+                            }
+                        }
+                    }
+                    Some(other) => content.push(other),
+                    None => break,
+                },
+                c if closers.contains(&c) => {
+                    if self.closes_string() {
+                        break;
+                    }
+                    content.push(c);
+                }
+                c => content.push(c),
+            }
+        }
+        self.push_string(&content);
+    }
+
+    fn closes_string(&self) -> bool {
+        let mut ahead = self.chars.clone();
+        while let Some(&c) = ahead.peek() {
+            if c == '\n' {
+                return true;
+            }
+            if !c.is_whitespace() {
+                break;
+            }
+            ahead.next();
+        }
+        matches!(ahead.peek(), None | Some(',' | ':' | '}' | ']' | '/' | '#'))
+    }
+
+    fn number(&mut self) {
+        let mut raw = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.') {
+                raw.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+
+        let mut normalized = raw.trim_start_matches('+').to_string();
+        if normalized.starts_with('.') {
+            normalized.insert(0, '0');
+        } else if normalized.starts_with("-.") {
+            normalized.insert(1, '0');
+        }
+        // Truncated mid-number, e.g. `1.` or `2e`
+        let normalized = normalized.trim_end_matches(['.', 'e', 'E', '-', '+']);
+
+        if serde_json::from_str::<serde_json::Number>(normalized).is_ok() {
+            self.out.push_str(normalized);
+        } else {
+            self.push_string(&raw);
+        }
+    }
+
+    fn bare_value(&mut self) {
+        let mut word = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if matches!(c, ',' | '}' | ']' | '\n') {
+                break;
+            }
+            word.push(c);
+            self.chars.next();
+        }
+
+        let word = word.trim();
+        match word {
+            "true" | "True" | "TRUE" => self.out.push_str("true"),
+            "false" | "False" | "FALSE" => self.out.push_str("false"),
+            "null" | "None" | "NULL" | "nil" | "undefined" | "NaN" | "Infinity" | "-Infinity" => {
+                self.out.push_str("null")
+            }
+            _ => self.push_string(word),
+        }
+    }
+
+    fn push_string(&mut self, s: &str) {
+        self.out
+            .push_str(&serde_json::to_string(s).expect("strings always serialize"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn repaired(input: &str) -> Value {
+        let out = repair_json(input).unwrap_or_else(|e| panic!("{e}: {input}"));
+        serde_json::from_str(&out).unwrap()
+    }
+
+    // Replies captured from models asked for JSON, trimmed to the part that matters
+    #[test]
+    fn valid_json_is_returned_unchanged() {
+        let input =
+            r#"{"name": "get_current_weather", "arguments": {"location": "Paris", "unit": 1.50}}"#;
+        assert_eq!(repair_json(input).unwrap(), input);
+    }
+
+    #[test]
+    fn trailing_commas() {
+        let input = "{\n  \"tasks\": [\n    {\"id\": \"t1\", \"name\": \"Research\",},\n    {\"id\": \"t2\", \"name\": \"Build\"},\n  ],\n}";
+        assert_eq!(
+            repaired(input),
+            json!({"tasks": [{"id": "t1", "name": "Research"}, {"id": "t2", "name": "Build"}]})
+        );
+    }
+
+    #[test]
+    fn single_quotes() {
+        let input = "{'name': 'get_current_weather', 'arguments': {'location': 'New York, NY', 'unit': 'celsius'}}";
+        assert_eq!(
+            repaired(input),
+            json!({"name": "get_current_weather", "arguments": {"location": "New York, NY", "unit": "celsius"}})
+        );
+    }
+
+    #[test]
+    fn unquoted_keys() {
+        let input =
+            "{name: \"process_values\", arguments: {a: 11, b: 2.5, c: true, d: \"x\", e: 3}}";
+        assert_eq!(
+            repaired(input),
+            json!({"name": "process_values", "arguments": {"a": 11, "b": 2.5, "c": true, "d": "x", "e": 3}})
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let input = "{\n  // the tool to call\n  \"name\": \"get_user_feedback\", /* no arguments */\n  \"arguments\": {} # done\n}";
+        assert_eq!(
+            repaired(input),
+            json!({"name": "get_user_feedback", "arguments": {}})
+        );
+    }
+
+    #[test]
+    fn python_literals() {
+        let input = "{\"tool\": None, \"complex\": True, \"done\": False, \"score\": NaN}";
+        assert_eq!(
+            repaired(input),
+            json!({"tool": null, "complex": true, "done": false, "score": null})
+        );
+    }
+
+    #[test]
+    fn truncated_output() {
+        let input = "{\"tasks\": [{\"id\": \"t1\", \"name\": \"Research Amplifier Circuit\", \"depends_on\": [\"t0\", \"t";
+        assert_eq!(
+            repaired(input),
+            json!({"tasks": [{"id": "t1", "name": "Research Amplifier Circuit", "depends_on": ["t0", "t"]}]})
+        );
+
+        assert_eq!(
+            repaired("{\"name\": \"get_current_weather\", \"arguments\": {\"unit\":"),
+            json!({"name": "get_current_weather", "arguments": {"unit": null}})
+        );
+        assert_eq!(repaired("[1, 2.5, 3e"), json!([1, 2.5, 3]));
+    }
+
+    #[test]
+    fn code_fences_and_prose() {
+        let input = "Sure! Here is the plan:\n```json\n{\"tasks\": [{\"id\": \"t1\", \"name\": \"Research\"}]}\n```\nLet me know if you need anything else.";
+        assert_eq!(
+            repaired(input),
+            json!({"tasks": [{"id": "t1", "name": "Research"}]})
+        );
+
+        let input = "The call is {\"name\": \"get_user_feedback\", \"arguments\": {}} as requested";
+        assert_eq!(
+            repaired(input),
+            json!({"name": "get_user_feedback", "arguments": {}})
+        );
+    }
+
+    #[test]
+    fn stray_inner_quotes() {
+        let input = r#"{"description": "Label the "input" and "output" jacks", "tool": null}"#;
+        assert_eq!(
+            repaired(input),
+            json!({"description": "Label the \"input\" and \"output\" jacks", "tool": null})
+        );
+    }
+
+    #[test]
+    fn mixed_damage() {
+        let input = "```\n{tasks: [{'id': 't1', 'tool': None, 'depends_on': [],}, {'id': 't2', 'depends_on': ['t1'] // needs the research\n";
+        assert_eq!(
+            repaired(input),
+            json!({"tasks": [{"id": "t1", "tool": null, "depends_on": []}, {"id": "t2", "depends_on": ["t1"]}]})
+        );
+    }
+
+    #[test]
+    fn empty_input_is_an_error() {
+        assert!(matches!(repair_json("   "), Err(JsonRepairError::NoJson)));
+        assert!(matches!(
+            repair_json("```json\n```"),
+            Err(JsonRepairError::NoJson)
+        ));
+    }
+}
@@ -1,8 +1,13 @@
+use crate::llama::plan::parse_plan;
+use crate::llama::tool_call_parser::{extract_tool_calls, tagged_blocks};
 use crate::llama::{
     LlamaResponseError, LlamaResponseMessage, ParseError, StructuredText, Task, ToolCall,
 };
 use crate::LlmConfig;
-use async_openai::types::{CompletionUsage, CreateChatCompletionResponse, Role};
+use async_openai::types::{
+    ChatCompletionTool, ChatCompletionToolType, CompletionUsage, CreateChatCompletionResponse,
+    FunctionObject, Role,
+};
 use log;
 use regex::Regex;
 use reqwest::{
@@ -16,19 +21,12 @@ use std::collections::HashMap;
 use std::result::Result as StdResult;
 use thiserror::Error;
 
+// Body of the first `<tool_call>` block anywhere in the text
 pub fn extract_json_from_xml_like(xml_like_data: &str) -> StdResult<String, ParseError> {
-    let start_tag = "<tool_call>";
-    let end_tag = "</tool_call>";
-
-    let trimmed = xml_like_data.trim();
-    println!("trimmed xml-like-data:\n{}", trimmed.clone());
-    if trimmed.starts_with(start_tag) && trimmed.ends_with(end_tag) {
-        let start_pos = start_tag.len();
-        let end_pos = trimmed.len() - end_tag.len();
-        Ok(trimmed[start_pos..end_pos].trim().to_string())
-    } else {
-        Err(ParseError::XmlParseError)
-    }
+    tagged_blocks(xml_like_data)
+        .first()
+        .map(|block| block.to_string())
+        .ok_or(ParseError::XmlParseError)
 }
 
 #[derive(Error, Debug)]
@@ -47,55 +45,46 @@ pub enum ExtractError {
     InvalidInput(String),
 }
 
-//is it better to keep it as a free standing function or keep it within the trait of StructuredText
+// First tool call in the text; see `extract_tool_calls` for the formats understood
 pub fn extract_tool_call_json(input: &str) -> StdResult<ToolCall, ExtractError> {
-    let repaired_input =
-        repair_json_with_tool(input).map_err(|e| ExtractError::RepairError(e.to_string()))?;
-    let parsed = serde_json::from_str::<serde_json::Value>(&repaired_input)?;
-
-    let name = parsed
-        .get("name")
-        .and_then(|v| v.as_str())
-        .ok_or(ExtractError::MissingField("missing function name"))?
-        .to_string();
-
-    let arguments = parsed.get("arguments").and_then(|args| {
-        let re_args = Regex::new(r#"(?s)"arguments"\s*:\s*(?P<args>\{.*?\})"#).ok()?;
-        re_args
-            .captures(&repaired_input)
-            .and_then(|cap| cap.name("args"))
-            .map(|m| m.as_str().to_string())
-    });
-
-    if !name.is_empty() {
-        return Ok(ToolCall { name, arguments });
-    }
-
-    Err(ExtractError::InvalidInput(input.to_string()))
+    extract_tool_calls(input)
+        .into_iter()
+        .next()
+        .ok_or_else(|| ExtractError::InvalidInput(input.to_string()))
 }
 
-pub fn repair_json_with_tool(input: &str) -> StdResult<String, impl std::error::Error> {
-    #[derive(Debug, Error)]
-    enum RepairError {
-        #[error("Repair tool failed with error: {0}")]
-        RepairToolError(String),
-        #[error("Invalid UTF-8 in repair tool output: {0}")]
-        Utf8Error(#[from] std::string::FromUtf8Error),
-        #[error("IO error: {0}")]
-        Io(#[from] std::io::Error),
-    }
-
-    let output = std::process::Command::new("./jsonrepair")
-        .args(&["-i", input])
-        .output()?;
-
-    if !output.status.success() {
-        return Err(RepairError::RepairToolError(
-            String::from_utf8_lossy(&output.stderr).to_string(),
-        ));
-    }
-
-    String::from_utf8(output.stdout).map_err(|e| RepairError::Utf8Error(e))
+// Accepts both the OpenAI `{"type": "function", "function": {...}}` shape and bare
+// `{"name": ..., "parameters": ...}` entries, as LlmAgent::build does for tool names.
+pub fn tools_from_meta(tools_map_meta: &Value) -> StdResult<Vec<ChatCompletionTool>, ExtractError> {
+    let Some(tools_array) = tools_map_meta.as_array() else {
+        return Ok(Vec::new());
+    };
+
+    tools_array
+        .iter()
+        .map(|tool| {
+            let function = tool.get("function").unwrap_or(tool);
+            let name = function
+                .get("name")
+                .and_then(|n| n.as_str())
+                .ok_or(ExtractError::MissingField("tool name"))?;
+
+            Ok(ChatCompletionTool {
+                r#type: ChatCompletionToolType::Function,
+                function: FunctionObject {
+                    name: name.to_string(),
+                    description: function
+                        .get("description")
+                        .and_then(|d| d.as_str())
+                        .map(String::from),
+                    parameters: function
+                        .get("parameters")
+                        .filter(|p| p.as_object().map_or(false, |o| !o.is_empty()))
+                        .cloned(),
+                },
+            })
+        })
+        .collect()
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
@@ -107,17 +96,5 @@ pub struct PlanningTask {
 }
 
 pub fn parse_planning_tasks(input: &str) -> StdResult<Vec<Task>, ParseError> {
-    let tasks_regex =
-        Regex::new(r#""tasks":\s*(\[[^\]]*\])"#).map_err(|e| ParseError::RegexError(e));
-
-    let tasks_str = tasks_regex
-        .unwrap()
-        .captures(input)
-        .and_then(|cap| cap.get(1))
-        .map(|m| m.as_str())
-        .ok_or(ParseError::CaptureError)?;
-
-    let tasks: Vec<Task> = serde_json::from_str(tasks_str).map_err(|e| ParseError::JsonError(e))?;
-
-    Ok(tasks)
+    parse_plan(input)
 }
@@ -0,0 +1,185 @@
+use crate::immutable_agent::Message;
+use crate::llama::provider::{
+    single_chunk_response, text_response, usage_from_counts, ChatRequest, LlmProvider,
+};
+use crate::llama::{ChatCompletionStream, ChatInnerError, LlamaResponseMessage};
+use crate::LlmConfig;
+use futures::future::BoxFuture;
+use futures::stream::{self, StreamExt};
+use regex::Regex;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+pub type MockResponder = Arc<dyn Fn(&str, &str) -> Option<String> + Send + Sync>;
+
+#[derive(Clone)]
+enum MockRule {
+    Regex(Regex, String),
+    Closure(MockResponder),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockCall {
+    pub model: String,
+    pub system_prompt: String,
+    pub input: String,
+    pub max_token: u16,
+    // The whole prompt as sent, after any trimming
+    pub messages: Vec<Message>,
+}
+
+// Rules are tried in insertion order against the user prompt, then the canned
+// queue is drained, then the fallback is used. Anything left unanswered is an error
+// so a test fails loudly instead of hanging on a missing script entry.
+#[derive(Default)]
+pub struct MockLlm {
+    rules: Vec<MockRule>,
+    canned: Mutex<VecDeque<String>>,
+    fallback: Option<String>,
+    calls: Mutex<Vec<MockCall>>,
+    stream_chunk_chars: Option<usize>,
+}
+
+impl MockLlm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_response(self, response: impl Into<String>) -> Self {
+        self.canned.lock().unwrap().push_back(response.into());
+        self
+    }
+
+    pub fn with_responses<I, S>(self, responses: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.canned
+            .lock()
+            .unwrap()
+            .extend(responses.into_iter().map(Into::into));
+        self
+    }
+
+    // `response` may reference capture groups of `pattern`, e.g. "$1" or "${city}"
+    pub fn with_regex(
+        mut self,
+        pattern: &str,
+        response: impl Into<String>,
+    ) -> Result<Self, regex::Error> {
+        self.rules
+            .push(MockRule::Regex(Regex::new(pattern)?, response.into()));
+        Ok(self)
+    }
+
+    pub fn with_closure<F>(mut self, responder: F) -> Self
+    where
+        F: Fn(&str, &str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.rules.push(MockRule::Closure(Arc::new(responder)));
+        self
+    }
+
+    pub fn with_fallback(mut self, response: impl Into<String>) -> Self {
+        self.fallback = Some(response.into());
+        self
+    }
+
+    // Streams replies `chars` characters at a time instead of as a single chunk
+    pub fn with_stream_chunks(mut self, chars: usize) -> Self {
+        self.stream_chunk_chars = Some(chars.max(1));
+        self
+    }
+
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn call_count(&self) -> usize {
+        self.calls.lock().unwrap().len()
+    }
+
+    fn respond(&self, system_prompt: &str, input: &str) -> Option<String> {
+        for rule in &self.rules {
+            match rule {
+                MockRule::Regex(re, template) => {
+                    if let Some(caps) = re.captures(input) {
+                        let mut expanded = String::new();
+                        caps.expand(template, &mut expanded);
+                        return Some(expanded);
+                    }
+                }
+                MockRule::Closure(responder) => {
+                    if let Some(response) = responder(system_prompt, input) {
+                        return Some(response);
+                    }
+                }
+            }
+        }
+
+        if let Some(response) = self.canned.lock().unwrap().pop_front() {
+            return Some(response);
+        }
+
+        self.fallback.clone()
+    }
+}
+
+// The mock only speaks plain text, so agents configured for native tool calls fall
+// back to the prompt-based <tool_call> format just as they would against llama.cpp.
+impl LlmProvider for MockLlm {
+    fn chat<'a>(
+        &'a self,
+        llm_config: &'a LlmConfig,
+        request: ChatRequest<'a>,
+    ) -> BoxFuture<'a, Result<LlamaResponseMessage, ChatInnerError>> {
+        Box::pin(async move {
+            if !request.tools.is_empty() {
+                return Err(ChatInnerError::NativeToolsUnsupported);
+            }
+
+            let system_prompt = request.system_prompt();
+            let input = request.last_user_input();
+            self.calls.lock().unwrap().push(MockCall {
+                model: llm_config.model.to_string(),
+                system_prompt: system_prompt.clone(),
+                input: input.clone(),
+                max_token: request.max_token,
+                messages: request.messages.to_vec(),
+            });
+
+            let response = self.respond(&system_prompt, &input).ok_or_else(|| {
+                ChatInnerError::LlamaResponseProcessingError(format!(
+                    "mock provider has no response scripted for input: {}",
+                    input
+                ))
+            })?;
+
+            Ok(text_response(response, usage_from_counts(0, 0)))
+        })
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        llm_config: &'a LlmConfig,
+        request: ChatRequest<'a>,
+    ) -> BoxFuture<'a, Result<ChatCompletionStream, ChatInnerError>> {
+        Box::pin(async move {
+            let response = self.chat(llm_config, request).await?;
+            let text: Vec<char> = response.content_to_string().chars().collect();
+            let size = self.stream_chunk_chars.unwrap_or(text.len()).max(1);
+            let chunks: Vec<_> = text
+                .chunks(size)
+                .map(|chunk| {
+                    Ok(single_chunk_response(
+                        llm_config,
+                        chunk.iter().collect(),
+                        response.usage.clone(),
+                    ))
+                })
+                .collect();
+            Ok(stream::iter(chunks).boxed())
+        })
+    }
+}
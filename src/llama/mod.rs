@@ -1,25 +1,58 @@
+pub mod cache;
+pub mod cassette;
+pub mod chat_template;
+pub mod context;
+pub mod json_repair;
 pub mod llama_utils;
+pub mod mock;
+pub mod plan;
+pub mod provider;
+pub mod retry;
+pub mod structured;
+pub mod tokens;
+pub mod tool_call_parser;
 
+use crate::immutable_agent::Message;
 use crate::LlmConfig;
-use async_openai::types::{CompletionUsage, CreateChatCompletionResponse, Role};
+use async_openai::error::{retry_after_from_headers, OpenAIError};
+use async_openai::types::{
+    ChatCompletionRequestSystemMessage, ChatCompletionRequestUserMessage, CompletionUsage,
+    CreateChatCompletionStreamResponse, Role,
+};
+use futures::stream::BoxStream;
 use llama_utils::*;
 use log;
 use regex::Regex;
 use reqwest::{
-    header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, USER_AGENT},
+    header::{HeaderMap, HeaderName, HeaderValue},
     ClientBuilder,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{from_str, json, Value};
 use std::collections::HashMap;
 use std::result::Result as StdResult;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct ToolCall {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub arguments: Option<String>,
+    pub arguments: Option<Value>,
+}
+
+impl ToolCall {
+    // The arguments as the JSON text `Tool::run` and the OpenAI wire format expect
+    pub fn arguments_json(&self) -> String {
+        match &self.arguments {
+            Some(Value::String(raw)) => raw.clone(),
+            Some(arguments) => arguments.to_string(),
+            None => String::new(),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -88,13 +121,36 @@ impl StructuredText {
     }
 }
 
+// One node of a plan; `depends_on` holds the ids of tasks that must finish first.
+// Everything but name and description is optional in planner output, see
+// `plan::normalize_tasks` for how gaps are filled.
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Task {
+    #[serde(default, deserialize_with = "plan::lenient_id")]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub id: String,
     pub name: String,
     pub description: String,
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Value>,
+    #[serde(default, deserialize_with = "plan::lenient_ids")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_output: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub acceptance_criteria: Vec<String>,
+    // The planner's judgement that this needs breaking down further; only asked for by
+    // hierarchical planning, see `decomposer::Decomposer`
+    #[serde(default, deserialize_with = "plan::lenient_flag")]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub complex: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
@@ -102,6 +158,18 @@ pub struct LlamaResponseMessage {
     pub content: Content,
     pub role: Role,
     pub usage: CompletionUsage,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    // One Role::Tool message per executed call, carrying its tool_call_id
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_results: Vec<Message>,
+    // The model that produced the answer, filled in by the agent once a fallback
+    // chain has settled on one
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
 }
 
 impl LlamaResponseMessage {
@@ -134,18 +202,118 @@ pub enum ChatInnerError {
     MissingApiKey(#[from] std::env::VarError),
     #[error("Invalid authorization header: {0}")]
     InvalidAuthHeader(#[from] reqwest::header::InvalidHeaderValue),
+    #[error("Invalid header name: {0}")]
+    InvalidHeaderName(#[from] reqwest::header::InvalidHeaderName),
     #[error("JSON serialization failed: {0}")]
     SerializationError(#[from] serde_json::Error),
     #[error("HTTP client build failed: {0}")]
     ClientBuildError(#[from] reqwest::Error),
     // #[error("HTTP request failed: {0}")]
     // RequestError(#[from] reqwest::Error),
+    #[error("OpenAI-compatible API call failed: {0}")]
+    ApiError(OpenAIError),
+    #[error("HTTP {status}: {body}")]
+    Http {
+        status: u16,
+        body: String,
+        retry_after: Option<Duration>,
+    },
     #[error("Empty choices in response")]
     EmptyChoices,
     #[error("Missing message content")]
     MissingMessageContent,
     #[error("LLama response processing failed: {0}")]
     LlamaResponseProcessingError(String),
+    #[error("Native tool calling is not supported by this provider")]
+    NativeToolsUnsupported,
+    #[error("No recorded response in cassette {path} for input: {input}")]
+    CassetteMiss { path: String, input: String },
+}
+
+impl From<OpenAIError> for ChatInnerError {
+    fn from(e: OpenAIError) -> Self {
+        match e {
+            OpenAIError::HttpStatus {
+                status,
+                body,
+                retry_after,
+                ..
+            } => ChatInnerError::Http {
+                status,
+                body,
+                retry_after,
+            },
+            other => ChatInnerError::ApiError(other),
+        }
+    }
+}
+
+impl ChatInnerError {
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ChatInnerError::Http { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ChatInnerError::Http { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    // Rate limits, server errors and transport hiccups are worth another attempt;
+    // other 4xx (bad key, bad request, unknown model) will fail the same way again.
+    pub fn is_retryable(&self) -> bool {
+        let transient = |e: &reqwest::Error| e.is_timeout() || e.is_connect() || e.is_request();
+        match self {
+            ChatInnerError::Http { status, .. } => {
+                *status == 408 || *status == 429 || *status >= 500
+            }
+            ChatInnerError::ClientBuildError(e) => transient(e),
+            ChatInnerError::ApiError(OpenAIError::Reqwest(e)) => transient(e),
+            ChatInnerError::ApiError(OpenAIError::StreamError(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+// Turns a non-success response from the hand-rolled providers into `ChatInnerError::Http`
+pub(crate) async fn error_for_status(
+    response: reqwest::Response,
+) -> Result<reqwest::Response, ChatInnerError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = retry_after_from_headers(response.headers());
+    let body = response.text().await.unwrap_or_default();
+    Err(ChatInnerError::Http {
+        status: status.as_u16(),
+        body,
+        retry_after,
+    })
+}
+
+// Applies the config's extra headers (which win over the defaults) and request timeout
+pub(crate) fn client_with_headers(
+    llm_config: &LlmConfig,
+    mut headers: HeaderMap,
+) -> Result<reqwest::Client, ChatInnerError> {
+    for (name, value) in &llm_config.extra_headers {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+
+    let mut builder = ClientBuilder::new().default_headers(headers);
+    if let Some(timeout) = llm_config.timeout() {
+        builder = builder.timeout(timeout);
+    }
+    Ok(builder.build()?)
 }
 
 pub async fn chat_inner_async_wrapper(
@@ -154,56 +322,39 @@ pub async fn chat_inner_async_wrapper(
     input: &str,
     max_token: u16,
 ) -> Result<(String, CompletionUsage), ChatInnerError> {
-    let api_key = std::env::var(&llm_config.api_key_str)?;
-    let bearer_token = format!("Bearer {}", api_key);
-
-    let mut headers = HeaderMap::new();
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-    headers.insert(USER_AGENT, HeaderValue::from_static("MyClient/1.0.0"));
-    headers.insert(AUTHORIZATION, HeaderValue::from_str(&bearer_token)?);
-
-    let messages = json!([
-        { "role": "system", "content": system_prompt },
-        { "role": "user", "content": input }
-    ]);
-    let payload = json!({
-        "temperature": 0.3,
-        "max_tokens": max_token,
-        "model": llm_config.model,
-        "messages": messages,
-    });
-
-    let body = serde_json::to_vec(&payload)?;
-    let client = ClientBuilder::new().default_headers(headers).build()?;
-    let response = client.post(llm_config.base_url).body(body).send().await?;
-
-    let chat_response = response.json::<CreateChatCompletionResponse>().await?;
-    let usage = chat_response.usage.unwrap_or_else(|| CompletionUsage {
-        prompt_tokens: 0,
-        completion_tokens: 0,
-        total_tokens: 0,
-    });
-
-    let choice = chat_response
-        .choices
-        .first()
-        .ok_or(ChatInnerError::EmptyChoices)?;
-    let data = choice
-        .message
+    let messages = vec![
+        ChatCompletionRequestSystemMessage {
+            content: system_prompt.to_string(),
+            name: None,
+        }
+        .into(),
+        ChatCompletionRequestUserMessage {
+            content: input.to_string().into(),
+            name: None,
+        }
+        .into(),
+    ];
+
+    let (message, usage) =
+        provider::openai::chat_with_tools_async(llm_config, &messages, &[], None, max_token)
+            .await?;
+    let data = message
         .content
-        .as_deref()
         .ok_or(ChatInnerError::MissingMessageContent)?;
 
-    Ok((data.to_owned(), usage))
+    Ok((data, usage))
 }
 
+pub type ChatCompletionStream =
+    BoxStream<'static, Result<CreateChatCompletionStreamResponse, ChatInnerError>>;
+
 impl Content {
     pub fn content_to_string(&self) -> String {
         match self {
             Content::Text(text) => text.clone(),
             Content::Structured(structured) => match structured {
                 StructuredText::ToolCall(tc) => {
-                    format!("ToolCall: {} ({:?})", tc.name, tc.arguments)
+                    format!("ToolCall: {} ({})", tc.name, tc.arguments_json())
                 }
                 StructuredText::Tasks(tasks) => tasks
                     .iter()
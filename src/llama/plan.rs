@@ -0,0 +1,331 @@
+use crate::llama::json_repair::repair_json;
+use crate::llama::structured::tasks_from_value;
+use crate::llama::{ParseError, Task};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum PlanError {
+    #[error("The plan has no tasks")]
+    Empty,
+    #[error("Task id {0} is used by more than one task")]
+    DuplicateId(String),
+    #[error("Task {task} depends on {dependency}, which is not a task in the plan")]
+    UnknownDependency { task: String, dependency: String },
+    #[error("Task {task} uses tool {tool}, which is not available")]
+    UnknownTool { task: String, tool: String },
+    #[error("Tasks depend on each other in a cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+}
+
+// Planner output as a task list: the JSON is located and repaired (prose, fences,
+// trailing commas, a `]` inside a description), then normalised, see `normalize_tasks`.
+pub fn parse_plan(input: &str) -> Result<Vec<Task>, ParseError> {
+    let repaired = repair_json(input).map_err(|_| ParseError::CaptureError)?;
+    let value: Value = serde_json::from_str(&repaired)?;
+    Ok(tasks_from_value(&value)?)
+}
+
+// Gives every task an id ("t1", "t2", ... by position when the model left it out),
+// resolves dependencies written as task names or 1-based positions to ids, and drops
+// placeholder tool names such as "null" or "none".
+pub fn normalize_tasks(tasks: &mut [Task]) {
+    for (index, task) in tasks.iter_mut().enumerate() {
+        task.id = task.id.trim().to_string();
+        if task.id.is_empty() {
+            task.id = format!("t{}", index + 1);
+        }
+        let placeholder = task.tool.as_deref().map_or(false, |tool| {
+            matches!(
+                tool.trim().to_lowercase().as_str(),
+                "" | "null" | "none" | "n/a"
+            )
+        });
+        if placeholder {
+            task.tool = None;
+        }
+    }
+
+    let ids: HashSet<String> = tasks.iter().map(|t| t.id.clone()).collect();
+    let by_name: HashMap<String, String> = tasks
+        .iter()
+        .map(|t| (t.name.trim().to_lowercase(), t.id.clone()))
+        .collect();
+    let by_position: Vec<String> = tasks.iter().map(|t| t.id.clone()).collect();
+
+    for task in tasks.iter_mut() {
+        let mut resolved: Vec<String> = Vec::new();
+        for dependency in &task.depends_on {
+            let dependency = dependency.trim();
+            let id = if ids.contains(dependency) {
+                dependency.to_string()
+            } else if let Some(id) = by_name.get(&dependency.to_lowercase()) {
+                id.clone()
+            } else if let Some(id) = dependency
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| by_position.get(n.checked_sub(1)?))
+            {
+                id.clone()
+            } else {
+                // Left as written so validation can report it
+                dependency.to_string()
+            };
+            if !resolved.contains(&id) {
+                resolved.push(id);
+            }
+        }
+        task.depends_on = resolved;
+    }
+}
+
+// Every problem at once, so the planner can fix them in a single round
+pub fn validate_plan(tasks: &[Task], tool_exists: impl Fn(&str) -> bool) -> Vec<PlanError> {
+    if tasks.is_empty() {
+        return vec![PlanError::Empty];
+    }
+
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    for task in tasks {
+        if !seen.insert(task.id.as_str()) {
+            errors.push(PlanError::DuplicateId(task.id.clone()));
+        }
+    }
+    for task in tasks {
+        for dependency in &task.depends_on {
+            if !seen.contains(dependency.as_str()) {
+                errors.push(PlanError::UnknownDependency {
+                    task: task.id.clone(),
+                    dependency: dependency.clone(),
+                });
+            }
+        }
+        if let Some(tool) = task.tool.as_deref().filter(|tool| !tool_exists(tool)) {
+            errors.push(PlanError::UnknownTool {
+                task: task.id.clone(),
+                tool: tool.to_string(),
+            });
+        }
+    }
+    if let Err(cycle) = topological_order(tasks) {
+        errors.push(cycle);
+    }
+    errors
+}
+
+// Task indices in an order that runs every task after its dependencies, keeping the
+// planner's order among tasks that are ready at the same time. Unknown dependencies
+// are ignored here; `validate_plan` reports them.
+pub fn topological_order(tasks: &[Task]) -> Result<Vec<usize>, PlanError> {
+    let index: HashMap<&str, usize> = tasks
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.id.as_str(), i))
+        .collect();
+    let dependencies: Vec<Vec<usize>> = tasks
+        .iter()
+        .map(|t| {
+            t.depends_on
+                .iter()
+                .filter_map(|d| index.get(d.as_str()).copied())
+                .collect()
+        })
+        .collect();
+
+    let mut done = vec![false; tasks.len()];
+    let mut order = Vec::with_capacity(tasks.len());
+    while order.len() < tasks.len() {
+        let ready =
+            (0..tasks.len()).find(|&i| !done[i] && dependencies[i].iter().all(|&d| done[d]));
+        match ready {
+            Some(i) => {
+                done[i] = true;
+                order.push(i);
+            }
+            None => return Err(PlanError::Cycle(find_cycle(tasks, &dependencies, &done))),
+        }
+    }
+    Ok(order)
+}
+
+// Walks dependencies from any unfinished task until one repeats; every unfinished task
+// has an unfinished dependency, so the walk always closes a loop.
+fn find_cycle(tasks: &[Task], dependencies: &[Vec<usize>], done: &[bool]) -> Vec<String> {
+    let Some(start) = (0..tasks.len()).find(|&i| !done[i]) else {
+        return Vec::new();
+    };
+    let mut path = vec![start];
+    loop {
+        let current = *path.last().unwrap();
+        let Some(next) = dependencies[current].iter().copied().find(|&d| !done[d]) else {
+            return path.iter().map(|&i| tasks[i].id.clone()).collect();
+        };
+        if let Some(position) = path.iter().position(|&i| i == next) {
+            let mut cycle: Vec<String> = path[position..]
+                .iter()
+                .map(|&i| tasks[i].id.clone())
+                .collect();
+            cycle.push(tasks[next].id.clone());
+            return cycle;
+        }
+        path.push(next);
+    }
+}
+
+pub fn correction_prompt(errors: &[String]) -> String {
+    format!(
+        "Your plan is invalid:\n- {}\nReply again with the corrected plan as JSON only.",
+        errors.join("\n- ")
+    )
+}
+
+// Models write ids as 1 as often as "1"
+pub(crate) fn lenient_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(id_to_string(Value::deserialize(deserializer)?))
+}
+
+pub(crate) fn lenient_ids<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Array(items) => items.into_iter().map(id_to_string).collect(),
+        Value::Null => Vec::new(),
+        single => vec![id_to_string(single)],
+    })
+}
+
+// ... and true as "true" or "yes"
+pub(crate) fn lenient_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Bool(flag) => flag,
+        Value::String(s) => matches!(s.trim().to_lowercase().as_str(), "true" | "yes"),
+        Value::Number(n) => n.as_i64() == Some(1),
+        _ => false,
+    })
+}
+
+fn id_to_string(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(json: &str) -> Vec<Task> {
+        parse_plan(json).unwrap()
+    }
+
+    fn no_tools(_: &str) -> bool {
+        false
+    }
+
+    #[test]
+    fn bracket_inside_a_description_does_not_end_the_plan() {
+        let tasks = plan(
+            r#"Here is the plan:
+```json
+{"tasks": [
+  {"name": "Collect", "description": "Read the items] from the list"},
+  {"name": "Summarize", "description": "Write [one line] per item", "depends_on": [1]},
+]}
+```"#,
+        );
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].description, "Read the items] from the list");
+        assert_eq!(tasks[1].description, "Write [one line] per item");
+    }
+
+    #[test]
+    fn missing_ids_and_placeholder_tools_are_filled_in() {
+        let tasks = plan(
+            r#"[{"name": "a", "description": "x", "tool": "none"},
+                {"id": 7, "name": "b", "description": "y", "tool": "search"}]"#,
+        );
+        assert_eq!(tasks[0].id, "t1");
+        assert_eq!(tasks[0].tool, None);
+        assert_eq!(tasks[1].id, "7");
+        assert_eq!(tasks[1].tool.as_deref(), Some("search"));
+    }
+
+    #[test]
+    fn dependencies_resolve_by_name_and_position() {
+        let tasks = plan(
+            r#"[{"id": "fetch", "name": "Fetch data", "description": "x"},
+                {"id": "clean", "name": "Clean data", "description": "y", "depends_on": "fetch data"},
+                {"id": "report", "name": "Report", "description": "z", "depends_on": [2, "1", "Clean Data"]}]"#,
+        );
+        assert_eq!(tasks[1].depends_on, ["fetch"]);
+        assert_eq!(tasks[2].depends_on, ["clean", "fetch"]);
+        assert!(validate_plan(&tasks, no_tools).is_empty());
+    }
+
+    #[test]
+    fn reports_duplicate_ids_unknown_dependencies_and_tools() {
+        let tasks = plan(
+            r#"[{"id": "a", "name": "one", "description": "x", "tool": "search"},
+                {"id": "a", "name": "two", "description": "y"},
+                {"id": "b", "name": "three", "description": "z", "depends_on": ["missing"]}]"#,
+        );
+        let errors = validate_plan(&tasks, |tool| tool == "calculator");
+        assert_eq!(
+            errors,
+            [
+                PlanError::DuplicateId("a".into()),
+                PlanError::UnknownTool {
+                    task: "a".into(),
+                    tool: "search".into()
+                },
+                PlanError::UnknownDependency {
+                    task: "b".into(),
+                    dependency: "missing".into()
+                },
+            ]
+        );
+        assert_eq!(validate_plan(&[], no_tools), [PlanError::Empty]);
+    }
+
+    #[test]
+    fn self_dependency_is_a_cycle() {
+        let tasks = plan(r#"[{"id": "a", "name": "a", "description": "x", "depends_on": ["a"]}]"#);
+        assert_eq!(
+            topological_order(&tasks),
+            Err(PlanError::Cycle(vec!["a".into(), "a".into()]))
+        );
+    }
+
+    #[test]
+    fn longer_cycle_is_reported_in_order() {
+        let tasks = plan(
+            r#"[{"id": "start", "name": "s", "description": "x"},
+                {"id": "a", "name": "a", "description": "x", "depends_on": ["c", "start"]},
+                {"id": "b", "name": "b", "description": "x", "depends_on": ["a"]},
+                {"id": "c", "name": "c", "description": "x", "depends_on": ["b"]}]"#,
+        );
+        let cycle = PlanError::Cycle(vec!["a".into(), "c".into(), "b".into(), "a".into()]);
+        assert_eq!(topological_order(&tasks), Err(cycle.clone()));
+        assert_eq!(validate_plan(&tasks, no_tools), [cycle.clone()]);
+        assert_eq!(
+            cycle.to_string(),
+            "Tasks depend on each other in a cycle: a -> c -> b -> a"
+        );
+    }
+
+    #[test]
+    fn order_runs_dependencies_first_and_keeps_planner_order() {
+        let tasks = plan(
+            r#"[{"id": "1", "name": "a", "description": "x", "depends_on": ["3"]},
+                {"id": "2", "name": "b", "description": "x"},
+                {"id": "3", "name": "c", "description": "x"},
+                {"id": "4", "name": "d", "description": "x", "depends_on": ["1", "2"]}]"#,
+        );
+        assert_eq!(topological_order(&tasks), Ok(vec![1, 2, 0, 3]));
+    }
+}
@@ -0,0 +1,319 @@
+use crate::immutable_agent::Message;
+use crate::llama::provider::{usage_from_counts, ChatRequest, LlmProvider};
+use crate::llama::tool_call_parser::normalize_arguments;
+use crate::llama::{
+    client_with_headers, error_for_status, ChatInnerError, Content, LlamaResponseMessage,
+    StructuredText, ToolCall,
+};
+use crate::LlmConfig;
+use async_openai::types::Role;
+use futures::future::BoxFuture;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+// The Messages API, e.g. base_url "https://api.anthropic.com/v1/messages". Replies are
+// not streamed: `chat_stream` is the trait default, which delivers the whole completion
+// as a single chunk.
+#[derive(Debug, Clone, Default)]
+pub struct AnthropicProvider;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        #[serde(default)]
+        input: Value,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicUsage {
+    #[serde(default)]
+    input_tokens: u32,
+    #[serde(default)]
+    output_tokens: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicResponse {
+    content: Vec<AnthropicBlock>,
+    usage: AnthropicUsage,
+}
+
+fn build_anthropic_client(llm_config: &LlmConfig) -> Result<reqwest::Client, ChatInnerError> {
+    let api_key = std::env::var(&llm_config.api_key_str)?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert("x-api-key", HeaderValue::from_str(&api_key)?);
+    headers.insert(
+        "anthropic-version",
+        HeaderValue::from_static(ANTHROPIC_VERSION),
+    );
+
+    client_with_headers(llm_config, headers)
+}
+
+fn to_block(message: &Message) -> (&'static str, Value) {
+    match (&message.role, &message.content) {
+        (Role::Assistant, Content::Structured(StructuredText::ToolCall(tool_call))) => {
+            let input = tool_call
+                .arguments
+                .clone()
+                .map(normalize_arguments)
+                .unwrap_or_else(|| json!({}));
+            (
+                "assistant",
+                json!({
+                    "type": "tool_use",
+                    "id": tool_call.id.clone().unwrap_or_default(),
+                    "name": tool_call.name,
+                    "input": input,
+                }),
+            )
+        }
+        (Role::Assistant, content) => (
+            "assistant",
+            json!({ "type": "text", "text": content.content_to_string() }),
+        ),
+        (Role::Tool | Role::Function, content) => (
+            "user",
+            json!({
+                "type": "tool_result",
+                "tool_use_id": message.tool_call_id.clone().unwrap_or_default(),
+                "content": content.content_to_string(),
+            }),
+        ),
+        (_, content) => (
+            "user",
+            json!({ "type": "text", "text": content.content_to_string() }),
+        ),
+    }
+}
+
+// The API wants strictly alternating user/assistant turns, so consecutive blocks
+// from the same side are folded into one message.
+fn to_anthropic_messages(messages: &[Message]) -> Vec<Value> {
+    let mut turns: Vec<(&'static str, Vec<Value>)> = Vec::new();
+    for message in messages.iter().filter(|m| m.role != Role::System) {
+        let (role, block) = to_block(message);
+        match turns.last_mut() {
+            Some((last_role, blocks)) if *last_role == role => blocks.push(block),
+            _ => turns.push((role, vec![block])),
+        }
+    }
+
+    turns
+        .into_iter()
+        .map(|(role, content)| json!({ "role": role, "content": content }))
+        .collect()
+}
+
+impl LlmProvider for AnthropicProvider {
+    fn chat<'a>(
+        &'a self,
+        llm_config: &'a LlmConfig,
+        request: ChatRequest<'a>,
+    ) -> BoxFuture<'a, Result<LlamaResponseMessage, ChatInnerError>> {
+        Box::pin(async move {
+            let mut payload = json!({
+                "model": llm_config.model,
+                "max_tokens": request.max_token,
+                "messages": to_anthropic_messages(request.messages),
+            });
+
+            // The Messages API has no seed or penalty parameters
+            let sampling = &llm_config.sampling;
+            if let Some(temperature) = sampling.temperature {
+                payload["temperature"] = json!(temperature);
+            }
+            if let Some(top_p) = sampling.top_p {
+                payload["top_p"] = json!(top_p);
+            }
+            if !sampling.stop.is_empty() {
+                payload["stop_sequences"] = json!(sampling.stop);
+            }
+
+            let system_prompt = request.system_prompt();
+            if !system_prompt.is_empty() {
+                payload["system"] = Value::String(system_prompt);
+            }
+
+            if !request.tools.is_empty() {
+                let tools = request
+                    .tools
+                    .iter()
+                    .map(|tool| {
+                        json!({
+                            "name": tool.function.name,
+                            "description": tool.function.description.clone().unwrap_or_default(),
+                            "input_schema": tool
+                                .function
+                                .parameters
+                                .clone()
+                                .unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                payload["tools"] = Value::Array(tools);
+                payload["tool_choice"] = json!({ "type": "auto" });
+            }
+
+            let client = build_anthropic_client(llm_config)?;
+            let response = client
+                .post(&llm_config.base_url)
+                .body(serde_json::to_vec(&payload)?)
+                .send()
+                .await?;
+            let response = error_for_status(response).await?;
+            let anthropic_response = response.json::<AnthropicResponse>().await?;
+
+            let mut text = String::new();
+            let mut tool_calls = Vec::new();
+            for block in anthropic_response.content {
+                match block {
+                    AnthropicBlock::Text { text: chunk } => text.push_str(&chunk),
+                    AnthropicBlock::ToolUse { id, name, input } => tool_calls.push(ToolCall {
+                        id: Some(id),
+                        name,
+                        arguments: Some(input),
+                    }),
+                    AnthropicBlock::Other => {}
+                }
+            }
+
+            if text.is_empty() && tool_calls.is_empty() {
+                return Err(ChatInnerError::MissingMessageContent);
+            }
+
+            Ok(LlamaResponseMessage {
+                content: Content::Text(text),
+                role: Role::Assistant,
+                usage: usage_from_counts(
+                    anthropic_response.usage.input_tokens,
+                    anthropic_response.usage.output_tokens,
+                ),
+                tool_calls,
+                tool_results: Vec::new(),
+                model: None,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llama::provider::testing::MockServer;
+
+    const KEY_VAR: &str = "ANTHROPIC_PROVIDER_TEST_KEY";
+
+    fn config(server: &MockServer) -> LlmConfig {
+        std::env::set_var(KEY_VAR, "test-key");
+        LlmConfig::new("claude-test", format!("{}/v1/messages", server.url))
+            .with_api_key_var(KEY_VAR)
+    }
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            Message::new(Content::Text("Be brief.".to_string()), None, Role::System),
+            Message::new(
+                Content::Text("Weather in Paris?".to_string()),
+                None,
+                Role::User,
+            ),
+        ]
+    }
+
+    #[tokio::test]
+    async fn sends_system_prompt_apart_and_reads_tool_use() {
+        let server = MockServer::json(
+            200,
+            r#"{"content": [
+                {"type": "text", "text": "Checking."},
+                {"type": "tool_use", "id": "toolu_1", "name": "get_current_weather",
+                 "input": {"location": "Paris"}}
+            ], "usage": {"input_tokens": 30, "output_tokens": 10}}"#,
+        );
+        let messages = conversation();
+        let response = AnthropicProvider
+            .chat(&config(&server), ChatRequest::new(&messages, 100))
+            .await
+            .unwrap();
+
+        assert_eq!(response.content_to_string(), "Checking.");
+        assert_eq!(response.tool_calls[0].id.as_deref(), Some("toolu_1"));
+        assert_eq!(response.usage.total_tokens, 40);
+
+        let received = server.received();
+        assert_eq!(received.path, "/v1/messages");
+        assert_eq!(received.headers["x-api-key"], "test-key");
+        assert_eq!(received.headers["anthropic-version"], ANTHROPIC_VERSION);
+        assert_eq!(received.body["system"], "Be brief.");
+        assert_eq!(received.body["max_tokens"], 100);
+        assert_eq!(received.body["messages"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn tool_results_go_back_under_their_call_id() {
+        let server = MockServer::json(
+            200,
+            r#"{"content": [{"type": "text", "text": "Sunny."}],
+                "usage": {"input_tokens": 1, "output_tokens": 1}}"#,
+        );
+        let mut messages = conversation();
+        messages.push(Message::new(
+            Content::Structured(StructuredText::ToolCall(ToolCall {
+                id: Some("toolu_1".to_string()),
+                name: "get_current_weather".to_string(),
+                arguments: Some(json!({ "location": "Paris" })),
+            })),
+            None,
+            Role::Assistant,
+        ));
+        messages.push(Message::tool_result(
+            Some("toolu_1".to_string()),
+            "get_current_weather".to_string(),
+            "sunny".to_string(),
+        ));
+        AnthropicProvider
+            .chat(&config(&server), ChatRequest::new(&messages, 100))
+            .await
+            .unwrap();
+
+        let received = server.received();
+        let turns = received.body["messages"].as_array().unwrap();
+        assert_eq!(turns[1]["content"][0]["type"], "tool_use");
+        assert_eq!(turns[2]["content"][0]["type"], "tool_result");
+        assert_eq!(turns[2]["content"][0]["tool_use_id"], "toolu_1");
+    }
+
+    #[tokio::test]
+    async fn rate_limit_carries_retry_after() {
+        let server = MockServer::start(
+            429,
+            "application/json",
+            &[("retry-after", "7")],
+            r#"{"type": "error"}"#,
+        );
+        let messages = conversation();
+        let err = AnthropicProvider
+            .chat(&config(&server), ChatRequest::new(&messages, 100))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.status(), Some(429));
+        assert_eq!(err.retry_after(), Some(std::time::Duration::from_secs(7)));
+        assert!(err.is_retryable());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::llama::provider::{
+    optional_bearer_client, text_response, usage_from_counts, ChatRequest, LlmProvider,
+};
+use crate::llama::{error_for_status, ChatInnerError, LlamaResponseMessage};
+use crate::LlmConfig;
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use serde_json::json;
+
+// The llama.cpp server's raw `/completion` endpoint, e.g. base_url
+// "http://localhost:8080/completion". The conversation is flattened into a single
+// prompt with the config's `ChatTemplate`, so there is no native tool calling here.
+// Replies are not streamed: `chat_stream` is the trait default, which delivers the
+// whole completion as a single chunk.
+#[derive(Debug, Clone, Default)]
+pub struct LlamaCppProvider;
+
+#[derive(Debug, Deserialize)]
+struct LlamaCppCompletion {
+    content: String,
+    #[serde(default)]
+    tokens_evaluated: u32,
+    #[serde(default)]
+    tokens_predicted: u32,
+}
+
+impl LlmProvider for LlamaCppProvider {
+    fn chat<'a>(
+        &'a self,
+        llm_config: &'a LlmConfig,
+        request: ChatRequest<'a>,
+    ) -> BoxFuture<'a, Result<LlamaResponseMessage, ChatInnerError>> {
+        Box::pin(async move {
+            if !request.tools.is_empty() {
+                return Err(ChatInnerError::NativeToolsUnsupported);
+            }
+
+            let template = llm_config.chat_template();
+            let mut stop = template.stop_sequences();
+            stop.extend(llm_config.sampling.stop.iter().cloned());
+
+            let mut payload = json!({
+                "prompt": template.render(request.messages),
+                "n_predict": request.max_token,
+            });
+            llm_config.sampling.apply_to(&mut payload);
+            payload["stop"] = json!(stop);
+            if let Some(schema) = request.response_format {
+                payload["json_schema"] = schema.schema.clone();
+            }
+
+            let client = optional_bearer_client(llm_config)?;
+            let response = client
+                .post(&llm_config.base_url)
+                .body(serde_json::to_vec(&payload)?)
+                .send()
+                .await?;
+            let response = error_for_status(response).await?;
+            let completion = response.json::<LlamaCppCompletion>().await?;
+
+            Ok(text_response(
+                completion.content.trim().to_string(),
+                usage_from_counts(completion.tokens_evaluated, completion.tokens_predicted),
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::immutable_agent::Message;
+    use crate::llama::chat_template::ChatTemplate;
+    use crate::llama::provider::testing::MockServer;
+    use crate::llama::Content;
+    use async_openai::types::Role;
+
+    #[tokio::test]
+    async fn renders_the_prompt_with_the_chat_template() {
+        let server = MockServer::json(
+            200,
+            r#"{"content": " Hello\n", "tokens_evaluated": 20, "tokens_predicted": 2}"#,
+        );
+        let config = LlmConfig::new("model.gguf", format!("{}/completion", server.url))
+            .with_chat_template(ChatTemplate::ChatMl);
+        let messages = vec![Message::new(
+            Content::Text("Hi".to_string()),
+            None,
+            Role::User,
+        )];
+        let response = LlamaCppProvider
+            .chat(&config, ChatRequest::new(&messages, 32))
+            .await
+            .unwrap();
+
+        assert_eq!(response.content_to_string(), "Hello");
+        assert_eq!(response.usage.total_tokens, 22);
+        let received = server.received();
+        assert_eq!(received.path, "/completion");
+        assert_eq!(received.body["n_predict"], 32);
+        assert_eq!(
+            received.body["prompt"],
+            ChatTemplate::ChatMl.render(&messages)
+        );
+        assert!(received.body["stop"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("<|im_end|>")));
+    }
+}
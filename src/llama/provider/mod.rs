@@ -0,0 +1,175 @@
+pub mod anthropic;
+pub mod llamacpp;
+pub mod ollama;
+pub mod openai;
+#[cfg(test)]
+pub(crate) mod testing;
+
+use crate::immutable_agent::Message;
+use crate::llama::structured::OutputSchema;
+use crate::llama::{
+    client_with_headers, ChatCompletionStream, ChatInnerError, Content, LlamaResponseMessage,
+};
+use crate::{LlmConfig, ProviderKind};
+use async_openai::types::{
+    ChatChoiceStream, ChatCompletionStreamResponseDelta, ChatCompletionTool,
+    ChatCompletionToolChoiceOption, CompletionUsage, CreateChatCompletionStreamResponse,
+    FinishReason, Role,
+};
+use futures::future::BoxFuture;
+use futures::stream::{self, StreamExt};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChatRequest<'a> {
+    pub messages: &'a [Message],
+    pub tools: &'a [ChatCompletionTool],
+    pub tool_choice: Option<&'a ChatCompletionToolChoiceOption>,
+    pub response_format: Option<&'a OutputSchema>,
+    pub max_token: u16,
+}
+
+impl<'a> ChatRequest<'a> {
+    pub fn new(messages: &'a [Message], max_token: u16) -> Self {
+        Self {
+            messages,
+            tools: &[],
+            tool_choice: None,
+            response_format: None,
+            max_token,
+        }
+    }
+
+    pub fn with_tools(
+        mut self,
+        tools: &'a [ChatCompletionTool],
+        tool_choice: Option<&'a ChatCompletionToolChoiceOption>,
+    ) -> Self {
+        self.tools = tools;
+        self.tool_choice = tool_choice;
+        self
+    }
+
+    // Providers without native structured output ignore this; the agent still
+    // validates the reply and asks for corrections.
+    pub fn with_response_format(mut self, schema: &'a OutputSchema) -> Self {
+        self.response_format = Some(schema);
+        self
+    }
+
+    pub fn system_prompt(&self) -> String {
+        self.messages
+            .iter()
+            .filter(|m| m.role == Role::System)
+            .map(|m| m.content.content_to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn last_user_input(&self) -> String {
+        self.messages
+            .iter()
+            .rev()
+            .find(|m| m.role == Role::User)
+            .map(|m| m.content.content_to_string())
+            .unwrap_or_default()
+    }
+}
+
+pub trait LlmProvider: Send + Sync {
+    fn chat<'a>(
+        &'a self,
+        llm_config: &'a LlmConfig,
+        request: ChatRequest<'a>,
+    ) -> BoxFuture<'a, Result<LlamaResponseMessage, ChatInnerError>>;
+
+    // Providers that cannot stream deliver the whole completion as a single chunk.
+    fn chat_stream<'a>(
+        &'a self,
+        llm_config: &'a LlmConfig,
+        request: ChatRequest<'a>,
+    ) -> BoxFuture<'a, Result<ChatCompletionStream, ChatInnerError>> {
+        Box::pin(async move {
+            let response = self.chat(llm_config, request).await?;
+            let chunk = single_chunk_response(
+                llm_config,
+                response.content.content_to_string(),
+                response.usage,
+            );
+            Ok(stream::once(async move { Ok(chunk) }).boxed())
+        })
+    }
+}
+
+pub fn provider_for(llm_config: &LlmConfig) -> Arc<dyn LlmProvider> {
+    match llm_config.provider {
+        ProviderKind::OpenAiCompatible => Arc::new(openai::OpenAiCompatibleProvider),
+        ProviderKind::Ollama => Arc::new(ollama::OllamaProvider),
+        ProviderKind::LlamaCpp => Arc::new(llamacpp::LlamaCppProvider),
+        ProviderKind::Anthropic => Arc::new(anthropic::AnthropicProvider),
+    }
+}
+
+// Local servers (Ollama, llama.cpp) usually run without auth: an empty `api_key_str`
+// or an unset variable simply means no Authorization header.
+pub(crate) fn optional_bearer_client(
+    llm_config: &LlmConfig,
+) -> Result<reqwest::Client, ChatInnerError> {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if !llm_config.api_key_str.is_empty() {
+        if let Ok(api_key) = std::env::var(&llm_config.api_key_str) {
+            let bearer_token = format!("Bearer {}", api_key);
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&bearer_token)?);
+        }
+    }
+
+    client_with_headers(llm_config, headers)
+}
+
+pub(crate) fn text_response(text: String, usage: CompletionUsage) -> LlamaResponseMessage {
+    LlamaResponseMessage {
+        content: Content::Text(text),
+        role: Role::Assistant,
+        usage,
+        tool_calls: Vec::new(),
+        tool_results: Vec::new(),
+        model: None,
+    }
+}
+
+pub(crate) fn usage_from_counts(prompt_tokens: u32, completion_tokens: u32) -> CompletionUsage {
+    CompletionUsage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+    }
+}
+
+#[allow(deprecated)]
+pub(crate) fn single_chunk_response(
+    llm_config: &LlmConfig,
+    text: String,
+    usage: CompletionUsage,
+) -> CreateChatCompletionStreamResponse {
+    CreateChatCompletionStreamResponse {
+        id: String::new(),
+        choices: vec![ChatChoiceStream {
+            index: 0,
+            delta: ChatCompletionStreamResponseDelta {
+                content: Some(text),
+                function_call: None,
+                tool_calls: None,
+                role: Some(Role::Assistant),
+            },
+            finish_reason: Some(FinishReason::Stop),
+            logprobs: None,
+        }],
+        created: 0,
+        model: llm_config.model.to_string(),
+        system_fingerprint: None,
+        object: "chat.completion.chunk".to_string(),
+        usage: Some(usage),
+    }
+}
@@ -0,0 +1,225 @@
+use crate::immutable_agent::Message;
+use crate::llama::provider::{optional_bearer_client, usage_from_counts, ChatRequest, LlmProvider};
+use crate::llama::tool_call_parser::normalize_arguments;
+use crate::llama::{
+    error_for_status, ChatInnerError, Content, LlamaResponseMessage, StructuredText, ToolCall,
+};
+use crate::LlmConfig;
+use async_openai::types::Role;
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+// Ollama's native `/api/chat` endpoint, e.g. base_url "http://localhost:11434/api/chat".
+// Replies are not streamed: `chat_stream` is the trait default, which delivers the
+// whole completion as a single chunk.
+#[derive(Debug, Clone, Default)]
+pub struct OllamaProvider;
+
+#[derive(Debug, Deserialize)]
+struct OllamaFunction {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaToolCall {
+    function: OllamaFunction,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaMessage {
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    tool_calls: Vec<OllamaToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaChatResponse {
+    message: OllamaMessage,
+    #[serde(default)]
+    prompt_eval_count: u32,
+    #[serde(default)]
+    eval_count: u32,
+}
+
+fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::System => "system",
+        Role::User => "user",
+        Role::Assistant => "assistant",
+        Role::Tool | Role::Function => "tool",
+    }
+}
+
+fn to_ollama_message(message: &Message) -> Value {
+    match &message.content {
+        Content::Structured(StructuredText::ToolCall(tool_call))
+            if message.role == Role::Assistant =>
+        {
+            let arguments = tool_call
+                .arguments
+                .clone()
+                .map(normalize_arguments)
+                .unwrap_or_else(|| json!({}));
+            json!({
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{ "function": { "name": tool_call.name, "arguments": arguments } }],
+            })
+        }
+        content => json!({
+            "role": role_name(message.role),
+            "content": content.content_to_string(),
+        }),
+    }
+}
+
+impl LlmProvider for OllamaProvider {
+    fn chat<'a>(
+        &'a self,
+        llm_config: &'a LlmConfig,
+        request: ChatRequest<'a>,
+    ) -> BoxFuture<'a, Result<LlamaResponseMessage, ChatInnerError>> {
+        Box::pin(async move {
+            let messages = request
+                .messages
+                .iter()
+                .map(to_ollama_message)
+                .collect::<Vec<_>>();
+
+            let mut payload = json!({
+                "model": llm_config.model,
+                "messages": messages,
+                "stream": false,
+                "options": {
+                    "num_predict": request.max_token,
+                },
+            });
+            llm_config.sampling.apply_to(&mut payload["options"]);
+            if let Some(schema) = request.response_format {
+                payload["format"] = schema.schema.clone();
+            }
+            if !request.tools.is_empty() {
+                payload["tools"] = serde_json::to_value(request.tools)?;
+            }
+
+            let client = optional_bearer_client(llm_config)?;
+            let response = client
+                .post(&llm_config.base_url)
+                .body(serde_json::to_vec(&payload)?)
+                .send()
+                .await?;
+            let response = error_for_status(response).await?;
+            let chat_response = response.json::<OllamaChatResponse>().await?;
+
+            // Ollama doesn't number its tool calls; results are matched back by these ids
+            let tool_calls = chat_response
+                .message
+                .tool_calls
+                .into_iter()
+                .map(|call| ToolCall {
+                    id: Some(format!("call_{}", Uuid::new_v4().simple())),
+                    name: call.function.name,
+                    arguments: Some(call.function.arguments),
+                })
+                .collect();
+
+            Ok(LlamaResponseMessage {
+                content: Content::Text(chat_response.message.content),
+                role: Role::Assistant,
+                usage: usage_from_counts(chat_response.prompt_eval_count, chat_response.eval_count),
+                tool_calls,
+                tool_results: Vec::new(),
+                model: None,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llama::provider::testing::MockServer;
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            Message::new(Content::Text("Be brief.".to_string()), None, Role::System),
+            Message::new(Content::Text("Hi".to_string()), None, Role::User),
+        ]
+    }
+
+    fn config(server: &MockServer) -> LlmConfig {
+        LlmConfig::new("llama3.2", format!("{}/api/chat", server.url))
+    }
+
+    #[tokio::test]
+    async fn sends_the_conversation_and_reads_the_reply() {
+        let server = MockServer::json(
+            200,
+            r#"{"message": {"role": "assistant", "content": "Hello"},
+                "prompt_eval_count": 12, "eval_count": 3}"#,
+        );
+        let messages = conversation();
+        let response = OllamaProvider
+            .chat(&config(&server), ChatRequest::new(&messages, 64))
+            .await
+            .unwrap();
+
+        assert_eq!(response.content_to_string(), "Hello");
+        assert_eq!(response.usage.total_tokens, 15);
+        let received = server.received();
+        assert_eq!(received.path, "/api/chat");
+        assert_eq!(received.body["stream"], false);
+        assert_eq!(received.body["options"]["num_predict"], 64);
+        assert_eq!(received.body["messages"][0]["role"], "system");
+        assert_eq!(received.body["messages"][1]["content"], "Hi");
+    }
+
+    #[tokio::test]
+    async fn tool_calls_get_distinct_ids() {
+        let server = MockServer::json(
+            200,
+            r#"{"message": {"role": "assistant", "content": "", "tool_calls": [
+                {"function": {"name": "get_current_weather", "arguments": {"location": "Paris"}}},
+                {"function": {"name": "get_current_weather", "arguments": {"location": "Rome"}}}
+            ]}}"#,
+        );
+        let messages = conversation();
+        let response = OllamaProvider
+            .chat(&config(&server), ChatRequest::new(&messages, 64))
+            .await
+            .unwrap();
+
+        let ids: Vec<_> = response
+            .tool_calls
+            .iter()
+            .map(|c| c.id.clone().unwrap())
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(
+            response.tool_calls[1].arguments,
+            Some(json!({ "location": "Rome" }))
+        );
+    }
+
+    #[tokio::test]
+    async fn error_status_is_reported() {
+        let server = MockServer::json(503, r#"{"error": "model is loading"}"#);
+        let messages = conversation();
+        let err = OllamaProvider
+            .chat(&config(&server), ChatRequest::new(&messages, 64))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            ChatInnerError::Http { status: 503, ref body, .. } if body.contains("model is loading")
+        ));
+        assert!(err.is_retryable());
+    }
+}
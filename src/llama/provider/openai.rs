@@ -0,0 +1,414 @@
+use crate::llama::provider::{ChatRequest, LlmProvider};
+use crate::llama::structured::OutputSchema;
+use crate::llama::tool_call_parser::normalize_arguments;
+use crate::llama::{ChatCompletionStream, ChatInnerError, Content, LlamaResponseMessage, ToolCall};
+use crate::LlmConfig;
+use async_openai::config::Config;
+use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionResponseFormat, ChatCompletionResponseFormatType,
+    ChatCompletionResponseMessage, ChatCompletionStreamOptions, ChatCompletionTool,
+    ChatCompletionToolChoiceOption, CompletionUsage, CreateChatCompletionRequest,
+    ResponseFormatJsonSchema, Stop,
+};
+use async_openai::Client;
+use futures::future::BoxFuture;
+use futures::stream::StreamExt;
+use once_cell::sync::Lazy;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, USER_AGENT};
+use secrecy::Secret;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const CHAT_COMPLETIONS_PATH: &str = "/chat/completions";
+
+// One reqwest client (and so one connection pool) per distinct timeout; the
+// per-provider auth and extra headers travel with each request via `Config::headers`.
+static HTTP_CLIENTS: Lazy<Mutex<HashMap<Option<Duration>, reqwest::Client>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// `Config` for any server speaking the OpenAI wire format. `base_url` may be either
+// the API root ("https://api.together.xyz/v1") or the full chat endpoint, which is
+// how the existing configs spell it.
+#[derive(Clone, Debug)]
+pub struct CompatibleConfig {
+    api_base: String,
+    api_key: Secret<String>,
+    headers: HeaderMap,
+}
+
+impl CompatibleConfig {
+    pub fn from_llm_config(llm_config: &LlmConfig) -> Result<Self, ChatInnerError> {
+        let api_key = if llm_config.api_key_str.is_empty() {
+            String::new()
+        } else {
+            std::env::var(&llm_config.api_key_str)?
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            USER_AGENT,
+            HeaderValue::from_static(concat!("autogen_rust/", env!("CARGO_PKG_VERSION"))),
+        );
+        if !api_key.is_empty() {
+            headers.insert(
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {}", api_key))?,
+            );
+        }
+        for (name, value) in &llm_config.extra_headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+
+        let api_base = llm_config
+            .base_url
+            .trim_end_matches('/')
+            .trim_end_matches(CHAT_COMPLETIONS_PATH)
+            .to_string();
+
+        Ok(Self {
+            api_base,
+            api_key: Secret::new(api_key),
+            headers,
+        })
+    }
+}
+
+impl Config for CompatibleConfig {
+    fn headers(&self) -> HeaderMap {
+        self.headers.clone()
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.api_base, path)
+    }
+
+    fn query(&self) -> Vec<(&str, &str)> {
+        Vec::new()
+    }
+
+    fn api_base(&self) -> &str {
+        &self.api_base
+    }
+
+    fn api_key(&self) -> &Secret<String> {
+        &self.api_key
+    }
+}
+
+pub fn openai_client(llm_config: &LlmConfig) -> Result<Client<CompatibleConfig>, ChatInnerError> {
+    let config = CompatibleConfig::from_llm_config(llm_config)?;
+
+    let http_client = {
+        let mut clients = HTTP_CLIENTS.lock().unwrap();
+        match clients.get(&llm_config.timeout()) {
+            Some(client) => client.clone(),
+            None => {
+                let mut builder = reqwest::ClientBuilder::new();
+                if let Some(timeout) = llm_config.timeout() {
+                    builder = builder.timeout(timeout);
+                }
+                let client = builder.build()?;
+                clients.insert(llm_config.timeout(), client.clone());
+                client
+            }
+        }
+    };
+
+    // Retries are the agent's `RetryPolicy`'s job, so the client's own 429 backoff is off
+    let no_backoff = backoff::ExponentialBackoffBuilder::new()
+        .with_max_elapsed_time(Some(Duration::ZERO))
+        .build();
+
+    Ok(Client::with_config(config)
+        .with_http_client(http_client)
+        .with_backoff(no_backoff))
+}
+
+#[allow(deprecated)]
+pub fn build_chat_request(
+    llm_config: &LlmConfig,
+    messages: Vec<ChatCompletionRequestMessage>,
+    tools: &[ChatCompletionTool],
+    tool_choice: Option<&ChatCompletionToolChoiceOption>,
+    max_token: u16,
+) -> CreateChatCompletionRequest {
+    let sampling = &llm_config.sampling;
+    let (tools, tool_choice) = if tools.is_empty() {
+        (None, None)
+    } else {
+        (Some(tools.to_vec()), tool_choice.cloned())
+    };
+
+    CreateChatCompletionRequest {
+        messages,
+        model: llm_config.model.clone(),
+        max_tokens: Some(max_token as u32),
+        temperature: sampling.temperature.map(|t| t as f32),
+        top_p: sampling.top_p.map(|p| p as f32),
+        stop: (!sampling.stop.is_empty()).then(|| Stop::StringArray(sampling.stop.clone())),
+        seed: sampling.seed,
+        presence_penalty: sampling.presence_penalty.map(|p| p as f32),
+        frequency_penalty: sampling.frequency_penalty.map(|p| p as f32),
+        tools,
+        tool_choice,
+        ..Default::default()
+    }
+}
+
+pub fn response_format(schema: &OutputSchema) -> ChatCompletionResponseFormat {
+    ChatCompletionResponseFormat {
+        r#type: ChatCompletionResponseFormatType::JsonSchema,
+        json_schema: Some(ResponseFormatJsonSchema {
+            name: schema.name.clone(),
+            description: None,
+            schema: Some(schema.schema.clone()),
+            strict: Some(schema.strict),
+        }),
+    }
+}
+
+// Together, DeepInfra, OpenAI itself and anything else speaking `/chat/completions`
+#[derive(Debug, Clone, Default)]
+pub struct OpenAiCompatibleProvider;
+
+impl LlmProvider for OpenAiCompatibleProvider {
+    fn chat<'a>(
+        &'a self,
+        llm_config: &'a LlmConfig,
+        request: ChatRequest<'a>,
+    ) -> BoxFuture<'a, Result<LlamaResponseMessage, ChatInnerError>> {
+        Box::pin(async move {
+            let messages = request
+                .messages
+                .iter()
+                .map(|m| m.to_request_message())
+                .collect::<Vec<_>>();
+            let mut chat_request = build_chat_request(
+                llm_config,
+                messages,
+                request.tools,
+                request.tool_choice,
+                request.max_token,
+            );
+            if llm_config.native_response_format {
+                chat_request.response_format = request.response_format.map(response_format);
+            }
+            let (message, usage) = create_chat_completion(llm_config, chat_request).await?;
+
+            let tool_calls = tool_calls_from_response(&message);
+            if tool_calls.is_empty() && message.content.is_none() {
+                return Err(ChatInnerError::MissingMessageContent);
+            }
+
+            Ok(LlamaResponseMessage {
+                content: Content::Text(message.content.unwrap_or_default()),
+                role: message.role,
+                usage,
+                tool_calls,
+                tool_results: Vec::new(),
+                model: None,
+            })
+        })
+    }
+
+    fn chat_stream<'a>(
+        &'a self,
+        llm_config: &'a LlmConfig,
+        request: ChatRequest<'a>,
+    ) -> BoxFuture<'a, Result<ChatCompletionStream, ChatInnerError>> {
+        Box::pin(async move {
+            let messages = request
+                .messages
+                .iter()
+                .map(|m| m.to_request_message())
+                .collect::<Vec<_>>();
+            chat_stream_async(llm_config, &messages, request.max_token).await
+        })
+    }
+}
+
+pub async fn chat_stream_async(
+    llm_config: &LlmConfig,
+    messages: &[ChatCompletionRequestMessage],
+    max_token: u16,
+) -> Result<ChatCompletionStream, ChatInnerError> {
+    let mut request = build_chat_request(llm_config, messages.to_vec(), &[], None, max_token);
+    request.stream_options = Some(ChatCompletionStreamOptions {
+        include_usage: true,
+    });
+
+    let client = openai_client(llm_config)?;
+    let stream = client.chat().create_stream(request).await?;
+
+    Ok(stream
+        .map(|chunk| chunk.map_err(ChatInnerError::from))
+        .boxed())
+}
+
+pub async fn chat_with_tools_async(
+    llm_config: &LlmConfig,
+    messages: &[ChatCompletionRequestMessage],
+    tools: &[ChatCompletionTool],
+    tool_choice: Option<&ChatCompletionToolChoiceOption>,
+    max_token: u16,
+) -> Result<(ChatCompletionResponseMessage, CompletionUsage), ChatInnerError> {
+    let request = build_chat_request(llm_config, messages.to_vec(), tools, tool_choice, max_token);
+    create_chat_completion(llm_config, request).await
+}
+
+pub async fn create_chat_completion(
+    llm_config: &LlmConfig,
+    request: CreateChatCompletionRequest,
+) -> Result<(ChatCompletionResponseMessage, CompletionUsage), ChatInnerError> {
+    let client = openai_client(llm_config)?;
+    let chat_response = client.chat().create(request).await?;
+
+    let usage = chat_response.usage.unwrap_or_else(|| CompletionUsage {
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        total_tokens: 0,
+    });
+
+    let choice = chat_response
+        .choices
+        .into_iter()
+        .next()
+        .ok_or(ChatInnerError::EmptyChoices)?;
+
+    Ok((choice.message, usage))
+}
+
+pub fn tool_calls_from_response(message: &ChatCompletionResponseMessage) -> Vec<ToolCall> {
+    message
+        .tool_calls
+        .as_ref()
+        .map(|calls| {
+            calls
+                .iter()
+                .map(|call| ToolCall {
+                    id: Some(call.id.clone()),
+                    name: call.function.name.clone(),
+                    arguments: Some(normalize_arguments(Value::String(
+                        call.function.arguments.clone(),
+                    ))),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::immutable_agent::Message;
+    use crate::llama::provider::testing::MockServer;
+    use async_openai::types::Role;
+
+    fn conversation() -> Vec<Message> {
+        vec![Message::new(
+            Content::Text("Hi".to_string()),
+            None,
+            Role::User,
+        )]
+    }
+
+    #[tokio::test]
+    async fn chat_reads_text_and_tool_calls() {
+        let server = MockServer::json(
+            200,
+            r#"{"id": "chatcmpl-1", "object": "chat.completion", "created": 0, "model": "m",
+                "choices": [{"index": 0, "finish_reason": "tool_calls", "message": {
+                    "role": "assistant", "content": null,
+                    "tool_calls": [{"id": "call_1", "type": "function", "function":
+                        {"name": "get_current_weather", "arguments": "{\"location\": \"Paris\"}"}}]
+                }}],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 7, "total_tokens": 12}}"#,
+        );
+        let config = LlmConfig::new("m", format!("{}/v1/chat/completions", server.url));
+        let messages = conversation();
+        let response = OpenAiCompatibleProvider
+            .chat(&config, ChatRequest::new(&messages, 50))
+            .await
+            .unwrap();
+
+        assert_eq!(response.tool_calls[0].id.as_deref(), Some("call_1"));
+        assert_eq!(
+            response.tool_calls[0].arguments,
+            Some(serde_json::json!({ "location": "Paris" }))
+        );
+        assert_eq!(response.usage.total_tokens, 12);
+
+        let received = server.received();
+        assert_eq!(received.path, "/v1/chat/completions");
+        assert_eq!(received.body["max_tokens"], 50);
+        assert_eq!(received.body["messages"][0]["content"], "Hi");
+    }
+
+    #[tokio::test]
+    async fn response_format_is_only_sent_when_enabled() {
+        let reply = r#"{"id": "chatcmpl-1", "object": "chat.completion", "created": 0, "model": "m",
+            "choices": [{"index": 0, "finish_reason": "stop",
+                "message": {"role": "assistant", "content": "{}"}}]}"#;
+        let schema = OutputSchema::new("answer", serde_json::json!({"type": "object"})).unwrap();
+        let messages = conversation();
+
+        let mut bodies = Vec::new();
+        for native in [false, true] {
+            let server = MockServer::json(200, reply);
+            let config = LlmConfig::new("m", format!("{}/v1/chat/completions", server.url))
+                .with_native_response_format(native);
+            let request = ChatRequest::new(&messages, 50).with_response_format(&schema);
+            OpenAiCompatibleProvider
+                .chat(&config, request)
+                .await
+                .unwrap();
+            bodies.push(server.received().body);
+        }
+
+        assert!(bodies[0].get("response_format").is_none());
+        assert_eq!(bodies[1]["response_format"]["type"], "json_schema");
+        assert_eq!(
+            bodies[1]["response_format"]["json_schema"]["name"],
+            "answer"
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_delivers_each_chunk() {
+        let chunk = |text: &str| {
+            format!(
+                "data: {{\"id\": \"c\", \"object\": \"chat.completion.chunk\", \"created\": 0, \
+                 \"model\": \"m\", \"choices\": [{{\"index\": 0, \"delta\": {{\"content\": \"{}\"}}, \
+                 \"finish_reason\": null}}]}}\n\n",
+                text
+            )
+        };
+        let body = format!("{}{}data: [DONE]\n\n", chunk("Hel"), chunk("lo"));
+        let server = MockServer::start(200, "text/event-stream", &[], &body);
+        let config = LlmConfig::new("m", format!("{}/v1/chat/completions", server.url));
+        let messages = conversation();
+
+        let stream = OpenAiCompatibleProvider
+            .chat_stream(&config, ChatRequest::new(&messages, 50))
+            .await
+            .unwrap();
+        let deltas: Vec<String> = stream
+            .map(|chunk| {
+                chunk.unwrap().choices[0]
+                    .delta
+                    .content
+                    .clone()
+                    .unwrap_or_default()
+            })
+            .collect()
+            .await;
+
+        assert_eq!(deltas, ["Hel", "lo"]);
+        assert_eq!(server.received().body["stream"], true);
+    }
+}
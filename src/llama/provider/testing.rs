@@ -0,0 +1,85 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+
+// The request a `MockServer` received
+#[derive(Debug)]
+pub(crate) struct Received {
+    pub path: String,
+    // Names lowercased
+    pub headers: HashMap<String, String>,
+    pub body: Value,
+}
+
+// A one-shot HTTP server on localhost standing in for a backend: it answers the first
+// request with a canned reply, then hands back what it was sent.
+pub(crate) struct MockServer {
+    pub url: String,
+    handle: JoinHandle<Received>,
+}
+
+impl MockServer {
+    pub fn json(status: u16, body: &str) -> Self {
+        Self::start(status, "application/json", &[], body)
+    }
+
+    pub fn start(status: u16, content_type: &str, headers: &[(&str, &str)], body: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let mut reply = format!(
+            "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            status,
+            content_type,
+            body.len()
+        );
+        for (name, value) in headers {
+            reply.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        reply.push_str("\r\n");
+        reply.push_str(body);
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let path = request_line
+                .split_whitespace()
+                .nth(1)
+                .unwrap_or_default()
+                .to_string();
+
+            let mut headers = HashMap::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let Some((name, value)) = line.trim_end().split_once(':') else {
+                    break;
+                };
+                headers.insert(name.to_lowercase(), value.trim().to_string());
+            }
+            let length = headers
+                .get("content-length")
+                .and_then(|l| l.parse().ok())
+                .unwrap_or(0);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            reader.get_mut().write_all(reply.as_bytes()).unwrap();
+            Received {
+                path,
+                headers,
+                body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+            }
+        });
+
+        Self { url, handle }
+    }
+
+    pub fn received(self) -> Received {
+        self.handle.join().unwrap()
+    }
+}
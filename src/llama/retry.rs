@@ -0,0 +1,190 @@
+use crate::llama::ChatInnerError;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+
+// How an agent retries failed LLM calls. Only errors `ChatInnerError::is_retryable`
+// accepts are retried; a server-supplied Retry-After wins over the computed backoff.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Self::default()
+        }
+    }
+
+    pub fn none() -> Self {
+        Self::new(0)
+    }
+
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff_ms = initial.as_millis() as u64;
+        self.max_backoff_ms = max.as_millis() as u64;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    // `attempt` is 1-based, as tryhard counts it. Exponential backoff with "full jitter":
+    // a uniform delay between zero and the exponential ceiling.
+    pub fn delay_for(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let max = Duration::from_millis(self.max_backoff_ms);
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(max);
+        }
+
+        let exponent = attempt.saturating_sub(1).min(31);
+        let ceiling = self
+            .initial_backoff_ms
+            .saturating_mul(1u64 << exponent)
+            .min(self.max_backoff_ms);
+        let millis = if self.jitter && ceiling > 0 {
+            rand::thread_rng().gen_range(0..=ceiling)
+        } else {
+            ceiling
+        };
+        Duration::from_millis(millis)
+    }
+
+    pub fn decide(&self, attempt: u32, error: &ChatInnerError) -> tryhard::RetryPolicy {
+        if error.is_retryable() {
+            tryhard::RetryPolicy::Delay(self.delay_for(attempt, error.retry_after()))
+        } else {
+            tryhard::RetryPolicy::Break
+        }
+    }
+
+    pub async fn run<T, F, Fut>(&self, f: F) -> Result<T, ChatInnerError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, ChatInnerError>>,
+    {
+        tryhard::retry_fn(f)
+            .retries(self.max_retries)
+            .custom_backoff(|attempt, error: &ChatInnerError| self.decide(attempt, error))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn http(status: u16, retry_after: Option<Duration>) -> ChatInnerError {
+        ChatInnerError::Http {
+            status,
+            body: String::new(),
+            retry_after,
+        }
+    }
+
+    fn no_jitter() -> RetryPolicy {
+        RetryPolicy::default().with_jitter(false)
+    }
+
+    #[test]
+    fn client_errors_fail_fast() {
+        for status in [400, 401, 404] {
+            assert!(!http(status, None).is_retryable(), "{}", status);
+            assert!(matches!(
+                no_jitter().decide(1, &http(status, None)),
+                tryhard::RetryPolicy::Break
+            ));
+        }
+        assert!(!ChatInnerError::MissingMessageContent.is_retryable());
+    }
+
+    #[test]
+    fn timeouts_rate_limits_and_server_errors_are_retried() {
+        for status in [408, 429, 500, 502, 503] {
+            assert!(http(status, None).is_retryable(), "{}", status);
+            assert!(matches!(
+                no_jitter().decide(1, &http(status, None)),
+                tryhard::RetryPolicy::Delay(delay) if delay == Duration::from_millis(500)
+            ));
+        }
+    }
+
+    #[test]
+    fn retry_after_wins_and_is_capped() {
+        let policy = RetryPolicy::default();
+        assert_eq!(
+            policy.delay_for(5, Some(Duration::from_secs(2))),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            policy.delay_for(1, Some(Duration::from_secs(120))),
+            Duration::from_secs(30)
+        );
+        assert!(matches!(
+            policy.decide(1, &http(429, Some(Duration::from_secs(3)))),
+            tryhard::RetryPolicy::Delay(delay) if delay == Duration::from_secs(3)
+        ));
+    }
+
+    #[test]
+    fn backoff_doubles_from_half_a_second_up_to_thirty() {
+        let delays: Vec<u64> = (1..=8)
+            .map(|attempt| no_jitter().delay_for(attempt, None).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [500, 1000, 2000, 4000, 8000, 16000, 30000, 30000]);
+        assert_eq!(
+            no_jitter().delay_for(u32::MAX, None),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn jitter_stays_under_the_ceiling() {
+        let policy = RetryPolicy::default();
+        for _ in 0..100 {
+            assert!(policy.delay_for(3, None) <= Duration::from_millis(2000));
+        }
+    }
+
+    async fn calls_made(policy: RetryPolicy, status: u16) -> u32 {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), _> = policy
+            .with_backoff(Duration::ZERO, Duration::ZERO)
+            .run(|| async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(http(status, None))
+            })
+            .await;
+        assert!(result.is_err());
+        calls.into_inner()
+    }
+
+    #[tokio::test]
+    async fn max_retries_is_honoured() {
+        assert_eq!(calls_made(RetryPolicy::new(2), 503).await, 3);
+        assert_eq!(calls_made(RetryPolicy::new(4), 429).await, 5);
+        assert_eq!(calls_made(RetryPolicy::none(), 503).await, 1);
+        assert_eq!(calls_made(RetryPolicy::new(4), 401).await, 1);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::llama::json_repair::repair_json;
+use crate::llama::plan::normalize_tasks;
+use crate::llama::Task;
+use jsonschema::JSONSchema;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use thiserror::Error;
+
+const DEFAULT_MAX_CORRECTIONS: u32 = 2;
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("Invalid JSON schema: {0}")]
+    InvalidSchema(String),
+}
+
+// A JSON Schema an agent's reply must satisfy. The schema is compiled once up front so
+// a broken schema fails at build time rather than on the first reply.
+#[derive(Debug, Clone)]
+pub struct OutputSchema {
+    pub name: String,
+    pub schema: Value,
+    pub strict: bool,
+    pub max_corrections: u32,
+    compiled: Arc<JSONSchema>,
+}
+
+impl OutputSchema {
+    pub fn new(name: impl Into<String>, schema: Value) -> Result<Self, SchemaError> {
+        let compiled =
+            JSONSchema::compile(&schema).map_err(|e| SchemaError::InvalidSchema(e.to_string()))?;
+
+        Ok(Self {
+            name: name.into(),
+            schema,
+            strict: false,
+            max_corrections: DEFAULT_MAX_CORRECTIONS,
+            compiled: Arc::new(compiled),
+        })
+    }
+
+    // OpenAI's strict mode additionally requires `additionalProperties: false` and every
+    // property listed in `required`.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_max_corrections(mut self, max_corrections: u32) -> Self {
+        self.max_corrections = max_corrections;
+        self
+    }
+
+    // The shape `parse_planning_tasks` scrapes for, as a schema
+    pub fn task_list() -> Self {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "tasks": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "id": { "type": ["string", "integer"] },
+                            "name": { "type": "string", "minLength": 1 },
+                            "description": { "type": "string", "minLength": 1 },
+                            "tool": { "type": ["string", "null"] },
+                            "arguments": { "type": ["object", "null"] },
+                            "depends_on": {
+                                "type": "array",
+                                "items": { "type": ["string", "integer"] }
+                            },
+                            "expected_output": { "type": ["string", "null"] },
+                            "acceptance_criteria": {
+                                "type": "array",
+                                "items": { "type": "string" }
+                            }
+                        },
+                        "required": ["name", "description"]
+                    }
+                }
+            },
+            "required": ["tasks"]
+        });
+        Self::new("task_list", schema).expect("task_list schema is valid")
+    }
+
+    pub fn validate(&self, value: &Value) -> Result<(), Vec<String>> {
+        self.compiled.validate(value).map_err(|errors| {
+            errors
+                .map(|e| {
+                    let path = e.instance_path.to_string();
+                    if path.is_empty() {
+                        e.to_string()
+                    } else {
+                        format!("at {}: {}", path, e)
+                    }
+                })
+                .collect()
+        })
+    }
+
+    pub fn parse(&self, reply: &str) -> Result<Value, Vec<String>> {
+        let value = extract_json_value(reply)
+            .ok_or_else(|| vec!["reply does not contain a JSON value".to_string()])?;
+        self.validate(&value)?;
+        Ok(value)
+    }
+
+    pub fn instructions(&self) -> String {
+        format!(
+            "Respond only with a JSON value that conforms to this JSON Schema, without any \
+             surrounding text:\n{}",
+            self.schema
+        )
+    }
+
+    pub fn correction_prompt(errors: &[String]) -> String {
+        format!(
+            "Your previous reply did not conform to the required JSON Schema:\n- {}\n\
+             Reply again with only the corrected JSON.",
+            errors.join("\n- ")
+        )
+    }
+}
+
+// Accepts a bare value, a ```json fenced block, or a value embedded in prose, and
+// repairs the near-JSON models tend to produce along the way
+pub fn extract_json_value(text: &str) -> Option<Value> {
+    let repaired = repair_json(text).ok()?;
+    serde_json::from_str(&repaired).ok()
+}
+
+// Planner replies may be `{"tasks": [...]}` or a bare task array
+pub fn tasks_from_value(value: &Value) -> Result<Vec<Task>, serde_json::Error> {
+    let tasks = value.get("tasks").unwrap_or(value);
+    let mut tasks: Vec<Task> = serde_json::from_value(tasks.clone())?;
+    normalize_tasks(&mut tasks);
+    Ok(tasks)
+}
@@ -0,0 +1,411 @@
+use crate::immutable_agent::Message;
+use crate::LlmConfig;
+use base64::Engine;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+// Per-message framing (role markers, separators) and the assistant reply primer, as
+// counted by OpenAI for its chat models; close enough for the other families.
+const MESSAGE_OVERHEAD: usize = 4;
+const REPLY_PRIMER: usize = 3;
+
+// tiktoken's pre-tokenizer splits minus the `\s+(?!\S)` lookahead, which the regex
+// crate can't express; whitespace runs then fall through to `\s+`, which only moves a
+// trailing space from one piece to the next.
+const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+";
+const O200K_PATTERN: &str = r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+";
+
+// Where `Tokenizer::for_config` looks for the model family's vocabulary, under its
+// standard file name, when the config names no tokenizer
+pub const DEFAULT_VOCAB_DIR: &str = "tokenizers";
+
+static BPE_CACHE: Lazy<Mutex<HashMap<PathBuf, Arc<BpeTokenizer>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Error)]
+pub enum TokenizerError {
+    #[error("Failed to read vocabulary: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid vocabulary line {line}: {reason}")]
+    InvalidLine { line: usize, reason: String },
+    #[error("Invalid pre-tokenizer pattern: {0}")]
+    Pattern(#[from] regex::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenizerFamily {
+    // GPT-4, GPT-3.5 and Qwen-style byte-level BPE
+    Cl100k,
+    // GPT-4o, o1 and later
+    O200k,
+    Llama3,
+    // Llama-2, Mistral, Gemma and other SentencePiece vocabularies
+    SentencePiece,
+}
+
+impl TokenizerFamily {
+    pub fn for_model(model: &str) -> Self {
+        let model = model.to_lowercase();
+        if model.contains("gpt-4o")
+            || model.contains("gpt-4.1")
+            || model.starts_with("o1")
+            || model.starts_with("o3")
+        {
+            TokenizerFamily::O200k
+        } else if model.contains("llama-3") || model.contains("llama3") {
+            TokenizerFamily::Llama3
+        } else if model.contains("gpt-") || model.contains("qwen") {
+            TokenizerFamily::Cl100k
+        } else {
+            TokenizerFamily::SentencePiece
+        }
+    }
+
+    // Average over English prose and code; SentencePiece vocabularies split finer
+    pub fn chars_per_token(&self) -> f64 {
+        match self {
+            TokenizerFamily::Cl100k | TokenizerFamily::Llama3 => 4.0,
+            TokenizerFamily::O200k => 4.2,
+            TokenizerFamily::SentencePiece => 3.5,
+        }
+    }
+
+    // The file name each family's vocabulary is published under; SentencePiece models
+    // have no tiktoken vocabulary and are always estimated
+    pub fn vocab_file(&self) -> Option<&'static str> {
+        match self {
+            TokenizerFamily::Cl100k => Some("cl100k_base.tiktoken"),
+            TokenizerFamily::O200k => Some("o200k_base.tiktoken"),
+            TokenizerFamily::Llama3 => Some("llama3.tiktoken"),
+            TokenizerFamily::SentencePiece => None,
+        }
+    }
+
+    fn pattern(&self) -> &'static str {
+        match self {
+            TokenizerFamily::O200k => O200K_PATTERN,
+            _ => CL100K_PATTERN,
+        }
+    }
+}
+
+// Byte-level BPE over a tiktoken-format vocabulary: one `<base64 token> <rank>` per
+// line, as shipped for cl100k_base, o200k_base and Llama-3.
+pub struct BpeTokenizer {
+    ranks: HashMap<Vec<u8>, u32>,
+    splitter: Regex,
+}
+
+impl std::fmt::Debug for BpeTokenizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BpeTokenizer")
+            .field("vocab_size", &self.ranks.len())
+            .finish()
+    }
+}
+
+impl BpeTokenizer {
+    pub fn from_tiktoken(data: &str, family: TokenizerFamily) -> Result<Self, TokenizerError> {
+        let mut ranks = HashMap::new();
+        for (index, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |reason: String| TokenizerError::InvalidLine {
+                line: index + 1,
+                reason,
+            };
+            let (token, rank) = line
+                .split_once(' ')
+                .ok_or_else(|| invalid("expected `<token> <rank>`".to_string()))?;
+            let token = base64::engine::general_purpose::STANDARD
+                .decode(token)
+                .map_err(|e| invalid(e.to_string()))?;
+            let rank = rank
+                .trim()
+                .parse::<u32>()
+                .map_err(|e| invalid(e.to_string()))?;
+            ranks.insert(token, rank);
+        }
+
+        Ok(Self {
+            ranks,
+            splitter: Regex::new(family.pattern())?,
+        })
+    }
+
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        family: TokenizerFamily,
+    ) -> Result<Self, TokenizerError> {
+        Self::from_tiktoken(&std::fs::read_to_string(path)?, family)
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        let mut tokens = Vec::new();
+        for piece in self.splitter.find_iter(text) {
+            let piece = piece.as_str().as_bytes();
+            if let Some(rank) = self.ranks.get(piece) {
+                tokens.push(*rank);
+                continue;
+            }
+            let parts = self.merge(piece);
+            tokens.extend(parts.windows(2).map(|w| {
+                self.ranks
+                    .get(&piece[w[0].0..w[1].0])
+                    .copied()
+                    .unwrap_or(u32::MAX)
+            }));
+        }
+        tokens
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.splitter
+            .find_iter(text)
+            .map(|piece| {
+                let piece = piece.as_str().as_bytes();
+                if self.ranks.contains_key(piece) {
+                    1
+                } else {
+                    self.merge(piece).len() - 1
+                }
+            })
+            .sum()
+    }
+
+    // tiktoken's merge loop: repeatedly join the adjacent pair with the lowest rank.
+    // Returns the start offset of every resulting token plus the end of the piece.
+    fn merge(&self, piece: &[u8]) -> Vec<(usize, u32)> {
+        let rank_of = |bytes: &[u8]| self.ranks.get(bytes).copied().unwrap_or(u32::MAX);
+
+        let mut parts: Vec<(usize, u32)> = (0..piece.len().saturating_sub(1))
+            .map(|i| (i, rank_of(&piece[i..i + 2])))
+            .collect();
+        parts.push((piece.len().saturating_sub(1), u32::MAX));
+        parts.push((piece.len(), u32::MAX));
+
+        let pair_rank = |parts: &[(usize, u32)], i: usize| {
+            if i + 3 < parts.len() {
+                rank_of(&piece[parts[i].0..parts[i + 3].0])
+            } else {
+                u32::MAX
+            }
+        };
+
+        loop {
+            let Some((i, _)) = parts[..parts.len() - 1]
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, rank))| *rank != u32::MAX)
+                .min_by_key(|(_, (_, rank))| *rank)
+            else {
+                break;
+            };
+            if i > 0 {
+                parts[i - 1].1 = pair_rank(&parts, i - 1);
+            }
+            parts[i].1 = pair_rank(&parts, i);
+            parts.remove(i + 1);
+        }
+        parts
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Tokenizer {
+    Bpe(Arc<BpeTokenizer>),
+    CharEstimate { chars_per_token: f64 },
+}
+
+impl Tokenizer {
+    // Exact counts from the vocabulary the model name calls for, otherwise an estimate
+    // from the model family. `tokenizer_path` is either a vocabulary file or a directory
+    // of them under their standard names, `DEFAULT_VOCAB_DIR` when unset. Vocabularies
+    // are loaded once per path.
+    pub fn for_config(llm_config: &LlmConfig) -> Self {
+        let family = TokenizerFamily::for_model(&llm_config.model);
+        let configured = llm_config.tokenizer_path.is_some();
+        let path = match &llm_config.tokenizer_path {
+            Some(path) if !path.is_dir() => Some(path.clone()),
+            dir => family.vocab_file().map(|file| {
+                dir.as_deref()
+                    .unwrap_or(Path::new(DEFAULT_VOCAB_DIR))
+                    .join(file)
+            }),
+        };
+
+        match path {
+            Some(path) if configured || path.is_file() => match load_cached(&path, family) {
+                Ok(bpe) => return Tokenizer::Bpe(bpe),
+                Err(e) => log::warn!(
+                    "Falling back to estimated token counts, could not load {}: {}",
+                    path.display(),
+                    e
+                ),
+            },
+            _ => log::debug!(
+                "No vocabulary for {}, estimating token counts",
+                llm_config.model
+            ),
+        }
+        Tokenizer::CharEstimate {
+            chars_per_token: family.chars_per_token(),
+        }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        match self {
+            Tokenizer::Bpe(bpe) => bpe.count(text),
+            Tokenizer::CharEstimate { chars_per_token } => {
+                (text.chars().count() as f64 / chars_per_token).ceil() as usize
+            }
+        }
+    }
+
+    pub fn count_message(&self, message: &Message) -> usize {
+        self.count(&message.content.content_to_string()) + MESSAGE_OVERHEAD
+    }
+
+    pub fn count_messages(&self, messages: &[Message]) -> usize {
+        messages
+            .iter()
+            .map(|m| self.count_message(m))
+            .sum::<usize>()
+            + REPLY_PRIMER
+    }
+}
+
+fn load_cached(path: &Path, family: TokenizerFamily) -> Result<Arc<BpeTokenizer>, TokenizerError> {
+    let mut cache = BPE_CACHE.lock().unwrap();
+    if let Some(bpe) = cache.get(path) {
+        return Ok(bpe.clone());
+    }
+    let bpe = Arc::new(BpeTokenizer::from_file(path, family)?);
+    cache.insert(path.to_path_buf(), bpe.clone());
+    Ok(bpe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+
+    // A toy vocabulary: every single byte used below, then merges in rank order
+    fn vocab(merges: &[&str]) -> String {
+        let singles = "abcdefghijklmnopqrstuvwxyz ,.!".chars().map(String::from);
+        singles
+            .chain(merges.iter().map(|m| m.to_string()))
+            .enumerate()
+            .map(|(rank, token)| format!("{} {}", STANDARD.encode(token), rank))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn bpe(merges: &[&str]) -> BpeTokenizer {
+        BpeTokenizer::from_tiktoken(&vocab(merges), TokenizerFamily::Cl100k).unwrap()
+    }
+
+    fn rank(merges: &[&str], token: &str) -> u32 {
+        30 + merges.iter().position(|m| *m == token).unwrap() as u32
+    }
+
+    #[test]
+    fn unknown_pairs_stay_single_bytes() {
+        let bpe = bpe(&[]);
+        assert_eq!(bpe.encode("abc"), vec![0, 1, 2]);
+        assert_eq!(bpe.count("abc"), 3);
+    }
+
+    #[test]
+    fn lowest_ranked_pair_merges_first() {
+        // "bc" outranks "ab", so "abc" becomes a + bc rather than ab + c
+        let merges = ["bc", "ab"];
+        let bpe = bpe(&merges);
+        assert_eq!(bpe.encode("abc"), vec![0, rank(&merges, "bc")]);
+    }
+
+    #[test]
+    fn merges_chain_into_longer_tokens() {
+        let merges = ["th", "he", "the", "er", "ther"];
+        let bpe = bpe(&merges);
+        assert_eq!(bpe.encode("ther"), vec![rank(&merges, "ther")]);
+        // " the" is its own piece: a space, then the merged word
+        assert_eq!(
+            bpe.encode("ther the"),
+            vec![rank(&merges, "ther"), 26, rank(&merges, "the")]
+        );
+        assert_eq!(bpe.count("ther the"), 3);
+    }
+
+    #[test]
+    fn whole_piece_in_vocabulary_is_one_token() {
+        let merges = ["hi", " hi"];
+        let bpe = bpe(&merges);
+        assert_eq!(
+            bpe.encode("hi hi"),
+            vec![rank(&merges, "hi"), rank(&merges, " hi")]
+        );
+    }
+
+    #[test]
+    fn invalid_vocabulary_line_is_reported() {
+        let err =
+            BpeTokenizer::from_tiktoken("YQ== 0\nnot-a-line", TokenizerFamily::Cl100k).unwrap_err();
+        assert!(matches!(err, TokenizerError::InvalidLine { line: 2, .. }));
+    }
+
+    #[test]
+    fn family_follows_the_model_name() {
+        assert_eq!(
+            TokenizerFamily::for_model("gpt-4o-mini"),
+            TokenizerFamily::O200k
+        );
+        assert_eq!(
+            TokenizerFamily::for_model("gpt-4-turbo"),
+            TokenizerFamily::Cl100k
+        );
+        assert_eq!(
+            TokenizerFamily::for_model("meta-llama/Llama-3.3-70B-Instruct-Turbo"),
+            TokenizerFamily::Llama3
+        );
+        assert_eq!(
+            TokenizerFamily::for_model("mistral-7b"),
+            TokenizerFamily::SentencePiece
+        );
+    }
+
+    #[test]
+    fn vocabulary_directory_is_searched_by_family() {
+        let dir = std::env::temp_dir().join(format!("autogen-vocab-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("o200k_base.tiktoken"), vocab(&["ab"])).unwrap();
+
+        let config = LlmConfig::new("gpt-4o", "http://localhost").with_tokenizer_path(&dir);
+        let tokenizer = Tokenizer::for_config(&config);
+        assert!(matches!(tokenizer, Tokenizer::Bpe(_)));
+        assert_eq!(tokenizer.count("ab"), 1);
+
+        // No cl100k vocabulary in the directory, so gpt-4 is estimated
+        let config = LlmConfig::new("gpt-4", "http://localhost").with_tokenizer_path(&dir);
+        assert!(matches!(
+            Tokenizer::for_config(&config),
+            Tokenizer::CharEstimate { .. }
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn estimate_rounds_up() {
+        let tokenizer = Tokenizer::CharEstimate {
+            chars_per_token: 4.0,
+        };
+        assert_eq!(tokenizer.count("abcde"), 2);
+        assert_eq!(tokenizer.count(""), 0);
+    }
+}
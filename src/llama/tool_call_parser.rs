@@ -0,0 +1,381 @@
+use crate::llama::json_repair::repair_json;
+use crate::llama::ToolCall;
+use serde_json::{Map, Value};
+
+const TOOL_CALL_OPEN: &str = "<tool_call>";
+const TOOL_CALL_CLOSE: &str = "</tool_call>";
+const PYTHON_TAG: &str = "<|python_tag|>";
+const MISTRAL_TAG: &str = "[TOOL_CALLS]";
+const LLAMA_END_TAGS: [&str; 3] = ["<|eom_id|>", "<|eot_id|>", "<|end_of_text|>"];
+
+const ARGUMENT_KEYS: [&str; 4] = ["arguments", "parameters", "args", "input"];
+
+// Finds every tool call a model wrote into its reply, wherever it sits in the text.
+// Formats are tried from most to least explicit and the first one that yields calls
+// wins: `<tool_call>` blocks (Hermes/Qwen, any number of them, the last one may be
+// unclosed), Mistral `[TOOL_CALLS] [...]`, Llama-3 `<|python_tag|>` (JSON or
+// `name.call(key=value)`), fenced code blocks and finally bare JSON in the prose.
+pub fn extract_tool_calls(text: &str) -> Vec<ToolCall> {
+    let tagged = tagged_blocks(text);
+    if !tagged.is_empty() {
+        return calls_from_segments(tagged, false);
+    }
+
+    if let Some(pos) = text.find(MISTRAL_TAG) {
+        let rest = &text[pos + MISTRAL_TAG.len()..];
+        let calls = calls_from_segments(json_spans(rest), false);
+        if !calls.is_empty() {
+            return calls;
+        }
+    }
+
+    if let Some(pos) = text.find(PYTHON_TAG) {
+        let calls = python_tag_calls(&text[pos + PYTHON_TAG.len()..]);
+        if !calls.is_empty() {
+            return calls;
+        }
+    }
+
+    let fenced = fenced_blocks(text);
+    if !fenced.is_empty() {
+        let calls = calls_from_segments(fenced, true);
+        if !calls.is_empty() {
+            return calls;
+        }
+    }
+
+    calls_from_segments(json_spans(text), true)
+}
+
+// Bodies of all `<tool_call>...</tool_call>` blocks; a block cut off by the token
+// limit runs to the next opening tag or the end of the text.
+pub fn tagged_blocks(text: &str) -> Vec<&str> {
+    let mut blocks = Vec::new();
+    let mut rest = text;
+    while let Some(open) = rest.find(TOOL_CALL_OPEN) {
+        let body = &rest[open + TOOL_CALL_OPEN.len()..];
+        let end = match (body.find(TOOL_CALL_CLOSE), body.find(TOOL_CALL_OPEN)) {
+            (Some(close), Some(next)) if next < close => next,
+            (Some(close), _) => close,
+            (None, Some(next)) => next,
+            (None, None) => body.len(),
+        };
+        let block = body[..end].trim();
+        if !block.is_empty() {
+            blocks.push(block);
+        }
+        rest = body[end..]
+            .strip_prefix(TOOL_CALL_CLOSE)
+            .unwrap_or(&body[end..]);
+    }
+    blocks
+}
+
+fn fenced_blocks(text: &str) -> Vec<&str> {
+    let mut blocks = Vec::new();
+    let mut rest = text;
+    while let Some(open) = rest.find("```") {
+        let after = &rest[open + 3..];
+        // Skip the info string, e.g. ```json
+        let body = after.find('\n').map_or(after, |nl| &after[nl + 1..]);
+        let (block, next) = match body.find("```") {
+            Some(close) => (&body[..close], &body[close + 3..]),
+            None => (body, ""),
+        };
+        if !block.trim().is_empty() {
+            blocks.push(block.trim());
+        }
+        rest = next;
+    }
+    blocks
+}
+
+// Top-level `{...}` / `[...]` spans, skipping brackets inside strings. An unbalanced
+// span runs to the end of the text and is left for `repair_json` to close.
+fn json_spans(text: &str) -> Vec<&str> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' if start.is_some() => in_string = true,
+            '{' | '[' => {
+                if depth == 0 {
+                    start = Some(i);
+                }
+                depth += 1;
+            }
+            '}' | ']' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    if let Some(s) = start.take() {
+                        spans.push(&text[s..=i]);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        spans.push(&text[s..]);
+    }
+    spans
+}
+
+fn python_tag_calls(text: &str) -> Vec<ToolCall> {
+    let end = LLAMA_END_TAGS
+        .iter()
+        .filter_map(|tag| text.find(tag))
+        .min()
+        .unwrap_or(text.len());
+    let body = text[..end].trim();
+
+    if body.starts_with(['{', '[']) {
+        return calls_from_segments(vec![body], false);
+    }
+
+    // Built-in tools: one `name.call(key="value", ...)` per line
+    body.lines()
+        .filter_map(|line| python_call(line.trim()))
+        .collect()
+}
+
+fn python_call(line: &str) -> Option<ToolCall> {
+    let open = line.find('(')?;
+    let inner = line[open + 1..].trim_end().strip_suffix(')')?;
+    let name = line[..open].trim().trim_end_matches(".call");
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+    {
+        return None;
+    }
+
+    let mut arguments = Map::new();
+    for pair in split_top_level(inner) {
+        let (key, raw) = pair.split_once('=')?;
+        let raw = raw.trim();
+        let value = repair_json(raw)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_else(|| Value::String(raw.trim_matches(['"', '\'']).to_string()));
+        arguments.insert(key.trim().to_string(), value);
+    }
+
+    Some(ToolCall {
+        id: None,
+        name: name.to_string(),
+        arguments: Some(Value::Object(arguments)),
+    })
+}
+
+// Splits call arguments on commas that are not inside quotes or brackets
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(text[start..].trim());
+    parts.retain(|p| !p.is_empty());
+    parts
+}
+
+// `require_arguments` guards the untagged formats: a bare `{"name": ...}` in prose is
+// as likely to be a task or a record as a call, so only objects that also carry an
+// arguments-style key count there.
+fn calls_from_segments(segments: Vec<&str>, require_arguments: bool) -> Vec<ToolCall> {
+    let mut calls = Vec::new();
+    for segment in segments {
+        let Ok(repaired) = repair_json(segment) else {
+            continue;
+        };
+        if let Ok(value) = serde_json::from_str::<Value>(&repaired) {
+            collect_calls(&value, require_arguments, &mut calls);
+        }
+    }
+    calls
+}
+
+fn collect_calls(value: &Value, require_arguments: bool, calls: &mut Vec<ToolCall>) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                collect_calls(item, require_arguments, calls);
+            }
+        }
+        Value::Object(object) => {
+            if let Some(nested) = object.get("tool_calls") {
+                collect_calls(nested, require_arguments, calls);
+            } else if let Some(call) = tool_call_from_object(object, require_arguments) {
+                calls.push(call);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Accepts `{"name", "arguments" | "parameters" | ...}` and the OpenAI
+// `{"id", "function": {"name", "arguments"}}` shape.
+fn tool_call_from_object(object: &Map<String, Value>, require_arguments: bool) -> Option<ToolCall> {
+    let id = object.get("id").and_then(Value::as_str).map(String::from);
+    let function = match object.get("function") {
+        Some(Value::Object(function)) => function,
+        _ => object,
+    };
+
+    let name = function
+        .get("name")
+        .or_else(|| object.get("function"))
+        .and_then(Value::as_str)
+        .filter(|name| !name.is_empty())?;
+    let arguments = ARGUMENT_KEYS
+        .iter()
+        .find_map(|key| function.get(*key).or_else(|| object.get(*key)))
+        .cloned();
+    if require_arguments && arguments.is_none() {
+        return None;
+    }
+
+    Some(ToolCall {
+        id,
+        name: name.to_string(),
+        arguments: arguments.map(normalize_arguments),
+    })
+}
+
+// Some models (and the OpenAI wire format) send arguments as a JSON-encoded string
+pub fn normalize_arguments(arguments: Value) -> Value {
+    match arguments {
+        Value::String(raw) if raw.trim_start().starts_with(['{', '[']) => repair_json(&raw)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or(Value::String(raw)),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn calls(text: &str) -> Vec<(String, Option<Value>)> {
+        extract_tool_calls(text)
+            .into_iter()
+            .map(|call| (call.name, call.arguments))
+            .collect()
+    }
+
+    fn call(name: &str, arguments: Value) -> (String, Option<Value>) {
+        (name.to_string(), Some(arguments))
+    }
+
+    #[test]
+    fn tagged_blocks_with_an_unclosed_last_tag() {
+        let text = r#"Let me check both.
+<tool_call>{"name": "get_weather", "arguments": {"city": "Paris"}}</tool_call>
+<tool_call>{"name": "get_weather", "arguments": {"city": "Rome"}}</tool_call>
+<tool_call>{"name": "get_time", "arguments": {"zone": "CET""#;
+        assert_eq!(
+            calls(text),
+            [
+                call("get_weather", json!({"city": "Paris"})),
+                call("get_weather", json!({"city": "Rome"})),
+                call("get_time", json!({"zone": "CET"})),
+            ]
+        );
+    }
+
+    #[test]
+    fn fenced_json_block() {
+        let text = "Calling the tool:\n```json\n{\"name\": \"search\", \"arguments\": {\"query\": \"rust\"}}\n```\nDone.";
+        assert_eq!(calls(text), [call("search", json!({"query": "rust"}))]);
+    }
+
+    #[test]
+    fn python_tag_json() {
+        let text =
+            r#"<|python_tag|>{"name": "get_weather", "parameters": {"city": "Oslo"}}<|eom_id|>"#;
+        assert_eq!(calls(text), [call("get_weather", json!({"city": "Oslo"}))]);
+    }
+
+    #[test]
+    fn python_tag_builtin_call() {
+        let text = "<|python_tag|>brave_search.call(query=\"rust actors\", count=3)\n<|eom_id|>";
+        assert_eq!(
+            calls(text),
+            [call(
+                "brave_search",
+                json!({"query": "rust actors", "count": 3})
+            )]
+        );
+    }
+
+    #[test]
+    fn mistral_tool_calls_array() {
+        let text = r#"[TOOL_CALLS] [{"name": "add", "arguments": {"a": 1, "b": 2}}, {"name": "neg", "arguments": {"x": 5}}]"#;
+        assert_eq!(
+            calls(text),
+            [
+                call("add", json!({"a": 1, "b": 2})),
+                call("neg", json!({"x": 5}))
+            ]
+        );
+    }
+
+    #[test]
+    fn bare_json_without_arguments_is_not_a_call() {
+        let text = r#"The task list is {"name": "write report", "deadline": "friday"}."#;
+        assert!(calls(text).is_empty());
+
+        let text = r#"Sure: {"name": "get_weather", "arguments": {"city": "Lima"}}"#;
+        assert_eq!(calls(text), [call("get_weather", json!({"city": "Lima"}))]);
+    }
+
+    #[test]
+    fn string_and_object_arguments_agree() {
+        let as_object = r#"<tool_call>{"name": "f", "arguments": {"x": 1}}</tool_call>"#;
+        let as_string = r#"<tool_call>{"name": "f", "arguments": "{\"x\": 1}"}</tool_call>"#;
+        assert_eq!(calls(as_object), [call("f", json!({"x": 1}))]);
+        assert_eq!(calls(as_string), calls(as_object));
+
+        // A plain string argument stays a string
+        assert_eq!(normalize_arguments(json!("hello")), json!("hello"));
+    }
+
+    #[test]
+    fn openai_function_shape_keeps_the_id() {
+        let text = r#"<tool_call>{"id": "call_7", "function": {"name": "f", "arguments": "{}"}}</tool_call>"#;
+        let parsed = extract_tool_calls(text);
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].id.as_deref(), Some("call_7"));
+        assert_eq!(parsed[0].arguments, Some(json!({})));
+    }
+}
@@ -0,0 +1,492 @@
+use crate::llama::chat_template::ChatTemplate;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+pub const DEFAULT_MAX_TOKENS: u16 = 1000;
+const DEFAULT_CONTEXT_SIZE: usize = 8192;
+const DEFAULT_ENV_PREFIX: &str = "LLM_";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    #[default]
+    #[serde(rename = "openai_compatible", alias = "openai")]
+    OpenAiCompatible,
+    Ollama,
+    #[serde(alias = "llamacpp")]
+    LlamaCpp,
+    Anthropic,
+}
+
+impl FromStr for ProviderKind {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(Value::String(s.trim().to_lowercase()))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct SamplingParams {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: u16,
+    pub stop: Vec<String>,
+    pub seed: Option<i64>,
+    pub presence_penalty: Option<f64>,
+    pub frequency_penalty: Option<f64>,
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        Self {
+            temperature: Some(0.3),
+            top_p: None,
+            max_tokens: DEFAULT_MAX_TOKENS,
+            stop: Vec::new(),
+            seed: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+        }
+    }
+}
+
+impl SamplingParams {
+    // OpenAI-style keys, shared by Ollama's `options` and llama.cpp's `/completion`.
+    // `max_tokens` is left out: every API names it differently and callers may
+    // override it per request.
+    pub fn to_payload(&self) -> Map<String, Value> {
+        let mut payload = Map::new();
+        let mut put = |key: &str, value: Option<Value>| {
+            if let Some(value) = value {
+                payload.insert(key.to_string(), value);
+            }
+        };
+        put("temperature", self.temperature.map(Value::from));
+        put("top_p", self.top_p.map(Value::from));
+        put(
+            "stop",
+            (!self.stop.is_empty()).then(|| Value::from(self.stop.clone())),
+        );
+        put("seed", self.seed.map(Value::from));
+        put("presence_penalty", self.presence_penalty.map(Value::from));
+        put("frequency_penalty", self.frequency_penalty.map(Value::from));
+        payload
+    }
+
+    pub fn apply_to(&self, payload: &mut Value) {
+        if let Value::Object(map) = payload {
+            map.extend(self.to_payload());
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LlmConfigError {
+    #[error("Failed to read config file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid JSON config: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid TOML config: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("Unsupported config file format: {0}")]
+    UnsupportedFormat(String),
+    #[error("Missing environment variable: {0}")]
+    MissingEnv(String),
+    #[error("Invalid value for {key}: {value}")]
+    InvalidEnv { key: String, value: String },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct LlmConfig {
+    pub model: String,
+    pub base_url: String,
+    #[serde(default = "default_context_size")]
+    pub context_size: usize,
+    // Name of the environment variable holding the key, never the key itself
+    #[serde(default)]
+    pub api_key_str: String,
+    #[serde(default)]
+    pub native_tool_calls: bool,
+    // Send output schemas as `response_format`; off for servers that reject it, which
+    // then rely on the schema in the prompt and validation alone
+    #[serde(default)]
+    pub native_response_format: bool,
+    #[serde(default)]
+    pub provider: ProviderKind,
+    #[serde(default)]
+    pub sampling: SamplingParams,
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    // Takes precedence over `timeout_secs`
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub extra_headers: HashMap<String, String>,
+    // Only used by raw-completion providers; guessed from `model` when unset
+    #[serde(default)]
+    pub chat_template: Option<ChatTemplate>,
+    // tiktoken-format vocabulary for exact token counts; estimated from `model` when unset
+    #[serde(default)]
+    pub tokenizer_path: Option<PathBuf>,
+}
+
+fn default_context_size() -> usize {
+    DEFAULT_CONTEXT_SIZE
+}
+
+impl LlmConfig {
+    pub fn new(model: impl Into<String>, base_url: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            base_url: base_url.into(),
+            context_size: DEFAULT_CONTEXT_SIZE,
+            api_key_str: String::new(),
+            native_tool_calls: false,
+            native_response_format: false,
+            provider: ProviderKind::default(),
+            sampling: SamplingParams::default(),
+            timeout_secs: None,
+            timeout_ms: None,
+            extra_headers: HashMap::new(),
+            chat_template: None,
+            tokenizer_path: None,
+        }
+    }
+
+    pub fn with_api_key_var(mut self, api_key_var: impl Into<String>) -> Self {
+        self.api_key_str = api_key_var.into();
+        self
+    }
+
+    pub fn with_provider(mut self, provider: ProviderKind) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    pub fn with_context_size(mut self, context_size: usize) -> Self {
+        self.context_size = context_size;
+        self
+    }
+
+    pub fn with_native_tool_calls(mut self, native_tool_calls: bool) -> Self {
+        self.native_tool_calls = native_tool_calls;
+        self
+    }
+
+    pub fn with_native_response_format(mut self, native_response_format: bool) -> Self {
+        self.native_response_format = native_response_format;
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: SamplingParams) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout_ms = Some(timeout.as_millis() as u64);
+        self.timeout_secs = None;
+        self
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra_headers.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_chat_template(mut self, chat_template: ChatTemplate) -> Self {
+        self.chat_template = Some(chat_template);
+        self
+    }
+
+    pub fn with_tokenizer_path(mut self, tokenizer_path: impl Into<PathBuf>) -> Self {
+        self.tokenizer_path = Some(tokenizer_path.into());
+        self
+    }
+
+    pub fn chat_template(&self) -> ChatTemplate {
+        self.chat_template
+            .unwrap_or_else(|| ChatTemplate::for_model(&self.model))
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms
+            .map(Duration::from_millis)
+            .or_else(|| self.timeout_secs.map(Duration::from_secs))
+    }
+
+    pub fn from_json_str(s: &str) -> Result<Self, LlmConfigError> {
+        Ok(serde_json::from_str(s)?)
+    }
+
+    pub fn from_toml_str(s: &str) -> Result<Self, LlmConfigError> {
+        Ok(toml::from_str(s)?)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, LlmConfigError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml_str(&contents),
+            Some("json") => Self::from_json_str(&contents),
+            other => Err(LlmConfigError::UnsupportedFormat(
+                other.unwrap_or_default().to_string(),
+            )),
+        }
+    }
+
+    // LLM_MODEL and LLM_BASE_URL are required; everything else falls back to the defaults.
+    pub fn from_env() -> Result<Self, LlmConfigError> {
+        Self::from_env_prefixed(DEFAULT_ENV_PREFIX)
+    }
+
+    pub fn from_env_prefixed(prefix: &str) -> Result<Self, LlmConfigError> {
+        let required = |key: &str| {
+            env_var(prefix, key)
+                .ok_or_else(|| LlmConfigError::MissingEnv(format!("{}{}", prefix, key)))
+        };
+
+        let mut config = Self::new(required("MODEL")?, required("BASE_URL")?);
+
+        if let Some(api_key_var) = env_var(prefix, "API_KEY_VAR") {
+            config.api_key_str = api_key_var;
+        }
+        if let Some(provider) = parse_env(prefix, "PROVIDER")? {
+            config.provider = provider;
+        }
+        if let Some(context_size) = parse_env(prefix, "CONTEXT_SIZE")? {
+            config.context_size = context_size;
+        }
+        if let Some(native_tool_calls) = parse_bool_env(prefix, "NATIVE_TOOL_CALLS")? {
+            config.native_tool_calls = native_tool_calls;
+        }
+        if let Some(native_response_format) = parse_bool_env(prefix, "NATIVE_RESPONSE_FORMAT")? {
+            config.native_response_format = native_response_format;
+        }
+        config.timeout_secs = parse_env(prefix, "TIMEOUT_SECS")?;
+        config.timeout_ms = parse_env(prefix, "TIMEOUT_MS")?;
+        config.chat_template = parse_env(prefix, "CHAT_TEMPLATE")?;
+        config.tokenizer_path = env_var(prefix, "TOKENIZER_PATH").map(PathBuf::from);
+
+        let sampling = &mut config.sampling;
+        if let Some(temperature) = parse_env(prefix, "TEMPERATURE")? {
+            sampling.temperature = Some(temperature);
+        }
+        if let Some(max_tokens) = parse_env(prefix, "MAX_TOKENS")? {
+            sampling.max_tokens = max_tokens;
+        }
+        sampling.top_p = parse_env(prefix, "TOP_P")?;
+        sampling.seed = parse_env(prefix, "SEED")?;
+        sampling.presence_penalty = parse_env(prefix, "PRESENCE_PENALTY")?;
+        sampling.frequency_penalty = parse_env(prefix, "FREQUENCY_PENALTY")?;
+        // Comma separated, e.g. LLM_STOP="</answer>,Observation:"
+        if let Some(stop) = env_var(prefix, "STOP") {
+            sampling.stop = stop
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect();
+        }
+
+        // Comma separated name=value pairs, e.g. LLM_EXTRA_HEADERS="X-Org=acme,X-Trace=1"
+        if let Some(headers) = env_var(prefix, "EXTRA_HEADERS") {
+            for pair in headers.split(',').filter(|p| !p.trim().is_empty()) {
+                let (name, value) =
+                    pair.split_once('=')
+                        .ok_or_else(|| LlmConfigError::InvalidEnv {
+                            key: format!("{}EXTRA_HEADERS", prefix),
+                            value: pair.to_string(),
+                        })?;
+                config
+                    .extra_headers
+                    .insert(name.trim().to_string(), value.trim().to_string());
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn env_var(prefix: &str, key: &str) -> Option<String> {
+    std::env::var(format!("{}{}", prefix, key)).ok()
+}
+
+fn parse_env<T: FromStr>(prefix: &str, key: &str) -> Result<Option<T>, LlmConfigError> {
+    env_var(prefix, key)
+        .map(|value| {
+            value
+                .trim()
+                .parse::<T>()
+                .map_err(|_| LlmConfigError::InvalidEnv {
+                    key: format!("{}{}", prefix, key),
+                    value,
+                })
+        })
+        .transpose()
+}
+
+// Accepts true/false, 1/0, yes/no and on/off, in any case
+fn parse_bool_env(prefix: &str, key: &str) -> Result<Option<bool>, LlmConfigError> {
+    env_var(prefix, key)
+        .map(|value| match value.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Ok(true),
+            "false" | "0" | "no" | "off" => Ok(false),
+            _ => Err(LlmConfigError::InvalidEnv {
+                key: format!("{}{}", prefix, key),
+                value,
+            }),
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each test uses its own prefix, so tests running in parallel never see each
+    // other's variables
+    fn set_env(prefix: &str, vars: &[(&str, &str)]) {
+        for (key, value) in vars {
+            std::env::set_var(format!("{}{}", prefix, key), value);
+        }
+    }
+
+    #[test]
+    fn with_timeout_keeps_milliseconds() {
+        let config =
+            LlmConfig::new("m", "http://localhost").with_timeout(Duration::from_millis(1500));
+        assert_eq!(config.timeout(), Some(Duration::from_millis(1500)));
+
+        let config =
+            LlmConfig::new("m", "http://localhost").with_timeout(Duration::from_millis(250));
+        assert_eq!(config.timeout(), Some(Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn from_env_prefixed_reads_every_setting() {
+        let prefix = "LLM_CONFIG_TEST_ALL_";
+        set_env(
+            prefix,
+            &[
+                ("MODEL", "llama3"),
+                ("BASE_URL", "http://localhost:11434"),
+                ("API_KEY_VAR", "MY_KEY"),
+                ("PROVIDER", "ollama"),
+                ("CONTEXT_SIZE", "4096"),
+                ("NATIVE_TOOL_CALLS", "1"),
+                ("TIMEOUT_MS", "2500"),
+                ("TEMPERATURE", "0.7"),
+                ("MAX_TOKENS", "256"),
+                ("STOP", "</answer>, Observation:"),
+                ("EXTRA_HEADERS", "X-Org=acme,X-Trace=1"),
+            ],
+        );
+
+        let config = LlmConfig::from_env_prefixed(prefix).unwrap();
+        assert_eq!(config.model, "llama3");
+        assert_eq!(config.base_url, "http://localhost:11434");
+        assert_eq!(config.api_key_str, "MY_KEY");
+        assert_eq!(config.provider, ProviderKind::Ollama);
+        assert_eq!(config.context_size, 4096);
+        assert!(config.native_tool_calls);
+        assert_eq!(config.timeout(), Some(Duration::from_millis(2500)));
+        assert_eq!(config.sampling.temperature, Some(0.7));
+        assert_eq!(config.sampling.max_tokens, 256);
+        assert_eq!(config.sampling.stop, ["</answer>", "Observation:"]);
+        assert_eq!(config.extra_headers["X-Org"], "acme");
+        assert_eq!(config.extra_headers["X-Trace"], "1");
+    }
+
+    #[test]
+    fn from_env_prefixed_parses_boolean_words() {
+        for (value, expected) in [("yes", true), ("TRUE", true), ("0", false), ("no", false)] {
+            let prefix = format!("LLM_CONFIG_TEST_BOOL_{}_", value);
+            set_env(
+                &prefix,
+                &[
+                    ("MODEL", "m"),
+                    ("BASE_URL", "http://localhost"),
+                    ("NATIVE_TOOL_CALLS", value),
+                ],
+            );
+            let config = LlmConfig::from_env_prefixed(&prefix).unwrap();
+            assert_eq!(config.native_tool_calls, expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn from_env_prefixed_rejects_bad_values() {
+        let prefix = "LLM_CONFIG_TEST_BAD_";
+        set_env(
+            prefix,
+            &[
+                ("MODEL", "m"),
+                ("BASE_URL", "http://localhost"),
+                ("NATIVE_TOOL_CALLS", "maybe"),
+            ],
+        );
+        let err = LlmConfig::from_env_prefixed(prefix).unwrap_err();
+        assert!(
+            matches!(err, LlmConfigError::InvalidEnv { key, .. } if key == "LLM_CONFIG_TEST_BAD_NATIVE_TOOL_CALLS")
+        );
+
+        let err = LlmConfig::from_env_prefixed("LLM_CONFIG_TEST_UNSET_").unwrap_err();
+        assert!(
+            matches!(err, LlmConfigError::MissingEnv(key) if key == "LLM_CONFIG_TEST_UNSET_MODEL")
+        );
+    }
+
+    #[test]
+    fn from_toml_str_fills_in_defaults() {
+        let config = LlmConfig::from_toml_str(
+            r#"
+            model = "gpt-4o"
+            base_url = "https://api.openai.com/v1"
+            provider = "openai"
+            timeout_secs = 30
+
+            [sampling]
+            temperature = 0.1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.model, "gpt-4o");
+        assert_eq!(config.provider, ProviderKind::OpenAiCompatible);
+        assert_eq!(config.context_size, DEFAULT_CONTEXT_SIZE);
+        assert_eq!(config.timeout(), Some(Duration::from_secs(30)));
+        assert_eq!(config.sampling.temperature, Some(0.1));
+        assert_eq!(config.sampling.max_tokens, DEFAULT_MAX_TOKENS);
+
+        assert!(matches!(
+            LlmConfig::from_toml_str("model = 1"),
+            Err(LlmConfigError::Toml(_))
+        ));
+    }
+
+    #[test]
+    fn from_json_str_round_trips() {
+        let config = LlmConfig::new("claude", "https://api.anthropic.com")
+            .with_provider(ProviderKind::Anthropic)
+            .with_timeout(Duration::from_millis(750))
+            .with_header("anthropic-beta", "tools");
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(LlmConfig::from_json_str(&json).unwrap(), config);
+
+        let config =
+            LlmConfig::from_json_str(r#"{"model": "m", "base_url": "u", "timeout_ms": 100}"#)
+                .unwrap();
+        assert_eq!(config.timeout(), Some(Duration::from_millis(100)));
+        assert!(matches!(
+            LlmConfig::from_json_str("{}"),
+            Err(LlmConfigError::Json(_))
+        ));
+    }
+}
@@ -141,8 +141,8 @@ async fn main() -> Result<()> {
 
     let task_message = Message::new(
         Content::Text(
-            "<|im_start|>get user's instruction in terminal<|im_end|>".to_string(),
-            // "<|im_start|>user Fetch the weather of New York in Celsius unit<|im_end|>".to_string(),
+            "get user's instruction in terminal".to_string(),
+            // "Fetch the weather of New York in Celsius unit".to_string(),
         ),
         None,
         Role::User,
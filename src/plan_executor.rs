@@ -0,0 +1,819 @@
+use crate::immutable_agent::LlmAgent;
+use crate::llama::plan::{topological_order, validate_plan, PlanError};
+use crate::llama::{Content, StructuredText, Task, ToolCall};
+use crate::STORE;
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+const DEFAULT_MAX_PARALLEL: usize = 4;
+
+#[derive(Debug, Error)]
+pub enum ExecutorError {
+    #[error("Plan is invalid: {}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; "))]
+    InvalidPlan(Vec<PlanError>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Succeeded,
+    Failed,
+    // Not run because a task it depends on did not succeed
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskReport {
+    pub id: String,
+    pub name: String,
+    // "tool:<name>", "agent:<name>" or "general"
+    pub executor: String,
+    pub status: TaskStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanVersion {
+    pub version: usize,
+    pub tasks: Vec<Task>,
+    // Why the previous version was revised; `None` for the original plan
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutionReport {
+    // In the order of the last plan version
+    pub tasks: Vec<TaskReport>,
+    // Every version that was executed, oldest first
+    pub plans: Vec<PlanVersion>,
+    // Why `run_adaptive` stopped revising the plan early, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replan_error: Option<String>,
+}
+
+impl ExecutionReport {
+    pub fn succeeded(&self) -> bool {
+        self.tasks.iter().all(|t| t.status == TaskStatus::Succeeded)
+    }
+
+    pub fn task(&self, id: &str) -> Option<&TaskReport> {
+        self.tasks.iter().find(|t| t.id == id)
+    }
+
+    pub fn output(&self, id: &str) -> Option<&str> {
+        self.task(id).and_then(|t| t.output.as_deref())
+    }
+}
+
+// Runs a planner's tasks in dependency order, up to `max_parallel` at a time. A task
+// whose `tool` names a registered agent goes to that agent, one naming a tool in
+// `STORE` runs the tool with the task's arguments, and one without a tool goes to the
+// general agent. Agents get their dependencies' outputs in the prompt; tool arguments
+// can take them through `{{task_id}}` placeholders. A failed task doesn't stop the
+// run, but everything downstream of it is skipped.
+pub struct PlanExecutor {
+    general: LlmAgent,
+    agents: HashMap<String, LlmAgent>,
+    max_parallel: usize,
+    task_timeout: Option<Duration>,
+    planner: Option<LlmAgent>,
+    max_replans: usize,
+    reviewer: Option<LlmAgent>,
+}
+
+impl PlanExecutor {
+    pub fn new(general: LlmAgent) -> Self {
+        Self {
+            general,
+            agents: HashMap::new(),
+            max_parallel: DEFAULT_MAX_PARALLEL,
+            task_timeout: None,
+            planner: None,
+            max_replans: 0,
+            reviewer: None,
+        }
+    }
+
+    // A task still running after `task_timeout` counts as failed
+    pub fn with_task_timeout(mut self, task_timeout: Duration) -> Self {
+        self.task_timeout = Some(task_timeout);
+        self
+    }
+
+    // Lets `run_adaptive` hand failures back to `planner` for a revised plan, at most
+    // `max_replans` times per run
+    pub fn with_replanning(mut self, planner: LlmAgent, max_replans: usize) -> Self {
+        self.planner = Some(planner);
+        self.max_replans = max_replans;
+        self
+    }
+
+    // Has `reviewer` judge each output against the task's `expected_output` and
+    // `acceptance_criteria`; an output it rejects fails the task
+    pub fn with_acceptance_check(mut self, reviewer: LlmAgent) -> Self {
+        self.reviewer = Some(reviewer);
+        self
+    }
+
+    pub fn with_agent(mut self, name: impl Into<String>, agent: LlmAgent) -> Self {
+        self.agents.insert(name.into(), agent);
+        self
+    }
+
+    pub fn with_max_parallel(mut self, max_parallel: usize) -> Self {
+        self.max_parallel = max_parallel.max(1);
+        self
+    }
+
+    pub fn validate(&self, tasks: &[Task]) -> Vec<PlanError> {
+        let registry = STORE.lock().unwrap();
+        validate_plan(tasks, |name| {
+            self.agents.contains_key(name) || registry.contains_key(name)
+        })
+    }
+
+    pub async fn run(&self, tasks: &[Task]) -> Result<ExecutionReport, ExecutorError> {
+        let errors = self.validate(tasks);
+        if !errors.is_empty() {
+            return Err(ExecutorError::InvalidPlan(errors));
+        }
+        Ok(ExecutionReport {
+            tasks: self.execute(tasks, HashMap::new()).await,
+            plans: vec![PlanVersion {
+                version: 1,
+                tasks: tasks.to_vec(),
+                reason: None,
+            }],
+            replan_error: None,
+        })
+    }
+
+    // Like `run`, but when tasks fail the planner gets `goal`, the results so far and
+    // the failures, and returns the whole plan revised. A task that already succeeded is
+    // not run again if the planner kept it unchanged and reuses everything it depends
+    // on. Stops when everything succeeds, the replanning budget is spent, or the planner
+    // can't produce a usable plan.
+    pub async fn run_adaptive(
+        &self,
+        goal: &str,
+        tasks: &[Task],
+    ) -> Result<ExecutionReport, ExecutorError> {
+        let mut report = self.run(tasks).await?;
+        let Some(planner) = &self.planner else {
+            return Ok(report);
+        };
+
+        for replan in 1..=self.max_replans {
+            let failures: Vec<&TaskReport> = report
+                .tasks
+                .iter()
+                .filter(|t| t.status == TaskStatus::Failed)
+                .collect();
+            if failures.is_empty() {
+                break;
+            }
+            let reason = failures
+                .iter()
+                .map(|t| format!("{} failed: {}", t.id, t.error.as_deref().unwrap_or("")))
+                .collect::<Vec<_>>()
+                .join("; ");
+            log::info!(
+                "plan: replanning ({}/{}) because {}",
+                replan,
+                self.max_replans,
+                reason
+            );
+
+            let current = &report.plans.last().unwrap().tasks;
+            let revised = match self.replan(planner, goal, current, &report.tasks).await {
+                Ok(revised) => revised,
+                Err(e) => {
+                    log::warn!("plan: replanning failed: {}", e);
+                    report.replan_error = Some(e);
+                    break;
+                }
+            };
+
+            let carried = carried_results(current, &revised, &report.tasks);
+            report.tasks = self.execute(&revised, carried).await;
+            report.plans.push(PlanVersion {
+                version: report.plans.len() + 1,
+                tasks: revised,
+                reason: Some(reason),
+            });
+        }
+        Ok(report)
+    }
+
+    async fn replan(
+        &self,
+        planner: &LlmAgent,
+        goal: &str,
+        tasks: &[Task],
+        reports: &[TaskReport],
+    ) -> Result<Vec<Task>, String> {
+        let response = planner
+            .default_method(&replan_prompt(goal, tasks, reports))
+            .await
+            .map_err(|e| e.to_string())?;
+        let Content::Structured(StructuredText::Tasks(revised)) = response.content else {
+            return Err("the planner did not return a task list".to_string());
+        };
+
+        let errors = self.validate(&revised);
+        if !errors.is_empty() {
+            return Err(ExecutorError::InvalidPlan(errors).to_string());
+        }
+        Ok(revised)
+    }
+
+    // Runs whatever in `tasks` isn't already in `finished`, which seeds the results
+    async fn execute(
+        &self,
+        tasks: &[Task],
+        mut finished: HashMap<String, TaskReport>,
+    ) -> Vec<TaskReport> {
+        // Only called with validated plans
+        let order = topological_order(tasks).unwrap_or_default();
+        let mut started: Vec<bool> = tasks.iter().map(|t| finished.contains_key(&t.id)).collect();
+        let mut running = FuturesUnordered::new();
+
+        loop {
+            // `order` is topological, so a skip is seen by later dependents in the same pass
+            for &i in &order {
+                if started[i] || running.len() >= self.max_parallel {
+                    continue;
+                }
+                let task = &tasks[i];
+                let Some(inputs) = task
+                    .depends_on
+                    .iter()
+                    .map(|d| finished.get(d))
+                    .collect::<Option<Vec<_>>>()
+                else {
+                    continue;
+                };
+                started[i] = true;
+
+                if let Some(failed) = inputs.iter().find(|r| r.status != TaskStatus::Succeeded) {
+                    log::info!(
+                        "plan: skipping {} because {} did not succeed",
+                        task.id,
+                        failed.id
+                    );
+                    finished.insert(
+                        task.id.clone(),
+                        TaskReport {
+                            id: task.id.clone(),
+                            name: task.name.clone(),
+                            executor: self.executor_name(task),
+                            status: TaskStatus::Skipped,
+                            output: None,
+                            error: Some(format!("dependency {} did not succeed", failed.id)),
+                            elapsed_ms: 0,
+                        },
+                    );
+                    continue;
+                }
+
+                let inputs: Vec<(String, String)> = inputs
+                    .iter()
+                    .map(|r| (r.id.clone(), r.output.clone().unwrap_or_default()))
+                    .collect();
+                running.push(self.run_task(task, inputs));
+            }
+
+            let Some(report) = running.next().await else {
+                break;
+            };
+            finished.insert(report.id.clone(), report);
+        }
+
+        tasks
+            .iter()
+            .filter_map(|t| finished.remove(&t.id))
+            .collect()
+    }
+
+    fn executor_name(&self, task: &Task) -> String {
+        match task.tool.as_deref() {
+            Some(name) if self.agents.contains_key(name) => format!("agent:{}", name),
+            Some(name) => format!("tool:{}", name),
+            None => "general".to_string(),
+        }
+    }
+
+    async fn run_task(&self, task: &Task, inputs: Vec<(String, String)>) -> TaskReport {
+        log::info!("plan: starting {} ({})", task.id, task.name);
+        let start = Instant::now();
+
+        let work = async {
+            let output = match task.tool.as_deref() {
+                Some(name) if self.agents.contains_key(name) => {
+                    self.run_agent(&self.agents[name], task, &inputs).await
+                }
+                Some(name) => self.run_tool(name, task, &inputs).await,
+                None => self.run_agent(&self.general, task, &inputs).await,
+            }?;
+            self.check_acceptance(task, &output).await?;
+            Ok(output)
+        };
+        let result = match self.task_timeout {
+            Some(limit) => tokio::time::timeout(limit, work)
+                .await
+                .unwrap_or_else(|_| Err(format!("timed out after {:?}", limit))),
+            None => work.await,
+        };
+
+        let (status, output, error) = match result {
+            Ok(output) => (TaskStatus::Succeeded, Some(output), None),
+            Err(error) => {
+                log::warn!("plan: task {} failed: {}", task.id, error);
+                (TaskStatus::Failed, None, Some(error))
+            }
+        };
+        TaskReport {
+            id: task.id.clone(),
+            name: task.name.clone(),
+            executor: self.executor_name(task),
+            status,
+            output,
+            error,
+            elapsed_ms: start.elapsed().as_millis() as u64,
+        }
+    }
+
+    async fn run_agent(
+        &self,
+        agent: &LlmAgent,
+        task: &Task,
+        inputs: &[(String, String)],
+    ) -> Result<String, String> {
+        agent
+            .default_method(&task_prompt(task, inputs))
+            .await
+            .map(|response| response.content_to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    // Passes when there is no reviewer or nothing to check the output against
+    async fn check_acceptance(&self, task: &Task, output: &str) -> Result<(), String> {
+        let Some(reviewer) = &self.reviewer else {
+            return Ok(());
+        };
+        if task.expected_output.is_none() && task.acceptance_criteria.is_empty() {
+            return Ok(());
+        }
+        let verdict = reviewer
+            .default_method(&review_prompt(task, output))
+            .await
+            .map_err(|e| format!("acceptance check failed: {}", e))?
+            .content_to_string();
+
+        let verdict = verdict.trim();
+        if verdict.to_uppercase().starts_with("PASS") {
+            return Ok(());
+        }
+        let reason = verdict
+            .strip_prefix("FAIL")
+            .unwrap_or(verdict)
+            .trim_start_matches([':', ' '])
+            .trim();
+        Err(format!(
+            "output rejected by the acceptance check: {}",
+            reason
+        ))
+    }
+
+    // Tools run through the general agent so they get its timeout
+    async fn run_tool(
+        &self,
+        name: &str,
+        task: &Task,
+        inputs: &[(String, String)],
+    ) -> Result<String, String> {
+        let call = ToolCall {
+            id: Some(task.id.clone()),
+            name: name.to_string(),
+            arguments: task
+                .arguments
+                .clone()
+                .map(|arguments| fill_placeholders(arguments, inputs)),
+        };
+        self.general
+            .run_tool_call(&call)
+            .await
+            .map(|message| message.content.content_to_string())
+            .map_err(|e| e.to_string())
+    }
+}
+
+// The succeeded results `revised` can reuse: its task must be identical to the one that
+// produced the result, and so must everything it depends on, or its inputs may differ
+fn carried_results(
+    previous: &[Task],
+    revised: &[Task],
+    reports: &[TaskReport],
+) -> HashMap<String, TaskReport> {
+    let mut carried = HashMap::new();
+    // Only called with validated plans
+    for i in topological_order(revised).unwrap_or_default() {
+        let task = &revised[i];
+        let unchanged = previous.iter().any(|p| p == task);
+        let inputs_carried = task.depends_on.iter().all(|d| carried.contains_key(d));
+        let succeeded = reports
+            .iter()
+            .find(|r| r.id == task.id && r.status == TaskStatus::Succeeded);
+        if let Some(report) = succeeded.filter(|_| unchanged && inputs_carried) {
+            carried.insert(task.id.clone(), report.clone());
+        }
+    }
+    carried
+}
+
+fn review_prompt(task: &Task, output: &str) -> String {
+    let mut prompt = format!("Task: {}\n{}", task.name, task.description);
+    if let Some(expected) = &task.expected_output {
+        prompt.push_str(&format!("\n\nExpected output: {}", expected));
+    }
+    if !task.acceptance_criteria.is_empty() {
+        prompt.push_str("\n\nAcceptance criteria:");
+        for criterion in &task.acceptance_criteria {
+            prompt.push_str(&format!("\n- {}", criterion));
+        }
+    }
+    prompt.push_str(&format!(
+        "\n\nOutput:\n{}\n\nDoes the output meet the expected output and every \
+         acceptance criterion? Reply PASS if it does, otherwise FAIL: followed by what \
+         is missing.",
+        output
+    ));
+    prompt
+}
+
+fn replan_prompt(goal: &str, tasks: &[Task], reports: &[TaskReport]) -> String {
+    let mut completed = Vec::new();
+    let mut failed = Vec::new();
+    for report in reports {
+        match report.status {
+            TaskStatus::Succeeded => completed.push(format!(
+                "[{}] {}\n{}",
+                report.id,
+                report.name,
+                report.output.as_deref().unwrap_or("")
+            )),
+            TaskStatus::Failed => failed.push(format!(
+                "[{}] {}: {}",
+                report.id,
+                report.name,
+                report.error.as_deref().unwrap_or("")
+            )),
+            TaskStatus::Skipped => {}
+        }
+    }
+    format!(
+        "{}\n\nA plan for this goal was being executed and some tasks failed.\n\n\
+         Current plan:\n{}\n\nCompleted tasks and their results:\n{}\n\n\
+         Failed tasks:\n{}\n\n\
+         Revise the plan so the goal can still be reached. Return the whole plan: keep \
+         completed tasks unchanged with the same ids, change or replace the failed tasks \
+         and anything after them, and give new tasks new ids.",
+        goal,
+        serde_json::to_string_pretty(tasks).unwrap_or_default(),
+        if completed.is_empty() {
+            "(none)".to_string()
+        } else {
+            completed.join("\n\n")
+        },
+        failed.join("\n")
+    )
+}
+
+fn task_prompt(task: &Task, inputs: &[(String, String)]) -> String {
+    let mut prompt = format!("Task: {}\n{}", task.name, task.description);
+    if let Some(expected) = &task.expected_output {
+        prompt.push_str(&format!("\n\nExpected output: {}", expected));
+    }
+    if !task.acceptance_criteria.is_empty() {
+        prompt.push_str("\n\nAcceptance criteria:");
+        for criterion in &task.acceptance_criteria {
+            prompt.push_str(&format!("\n- {}", criterion));
+        }
+    }
+    if !inputs.is_empty() {
+        prompt.push_str("\n\nResults of the tasks this one depends on:");
+        for (id, output) in inputs {
+            prompt.push_str(&format!("\n[{}]\n{}", id, output));
+        }
+    }
+    prompt
+}
+
+// Replaces `{{id}}` in string arguments with that dependency's output
+fn fill_placeholders(value: Value, inputs: &[(String, String)]) -> Value {
+    match value {
+        Value::String(mut text) => {
+            for (id, output) in inputs {
+                text = text.replace(&format!("{{{{{}}}}}", id), output);
+            }
+            Value::String(text)
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| fill_placeholders(item, inputs))
+                .collect(),
+        ),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, item)| (key, fill_placeholders(item, inputs)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llama::mock::MockLlm;
+    use crate::llama::provider::{text_response, usage_from_counts, ChatRequest, LlmProvider};
+    use crate::llama::{ChatInnerError, LlamaResponseMessage};
+    use crate::LlmConfig;
+    use futures::future::BoxFuture;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn agent(mock: &Arc<MockLlm>) -> LlmAgent {
+        LlmAgent::build(
+            "You are a helpful assistant.".to_string(),
+            None,
+            None,
+            None,
+            "assistant".to_string(),
+        )
+        .unwrap()
+        .with_provider(mock.clone())
+    }
+
+    fn task(id: &str, depends_on: &[&str]) -> Task {
+        Task {
+            id: id.to_string(),
+            name: format!("Task {}", id),
+            description: format!("Do {}", id),
+            tool: None,
+            arguments: None,
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            expected_output: None,
+            acceptance_criteria: Vec::new(),
+            complex: false,
+        }
+    }
+
+    fn succeeded(id: &str) -> TaskReport {
+        TaskReport {
+            id: id.to_string(),
+            name: format!("Task {}", id),
+            executor: "general".to_string(),
+            status: TaskStatus::Succeeded,
+            output: Some(format!("output of {}", id)),
+            error: None,
+            elapsed_ms: 0,
+        }
+    }
+
+    // Answers after a short delay and records the most requests it had in flight at once
+    #[derive(Default)]
+    struct ConcurrencyProbe {
+        in_flight: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    impl LlmProvider for ConcurrencyProbe {
+        fn chat<'a>(
+            &'a self,
+            _llm_config: &'a LlmConfig,
+            _request: ChatRequest<'a>,
+        ) -> BoxFuture<'a, Result<LlamaResponseMessage, ChatInnerError>> {
+            Box::pin(async move {
+                let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(text_response("done".to_string(), usage_from_counts(0, 0)))
+            })
+        }
+    }
+
+    fn input_task_ids(mock: &MockLlm) -> Vec<String> {
+        mock.calls()
+            .iter()
+            .map(|c| c.input.split("Task: Task ").nth(1).unwrap()[..1].to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn tasks_run_after_their_dependencies() {
+        let general = Arc::new(MockLlm::new().with_fallback("done"));
+        let tasks = [
+            task("1", &["2"]),
+            task("2", &[]),
+            task("3", &["1"]),
+            task("4", &[]),
+        ];
+        let report = PlanExecutor::new(agent(&general))
+            .with_max_parallel(1)
+            .run(&tasks)
+            .await
+            .unwrap();
+
+        assert!(report.succeeded());
+        // Among tasks that are ready, the planner's order decides
+        assert_eq!(input_task_ids(&general), ["2", "1", "3", "4"]);
+        // Reports follow the plan, not the order tasks finished in
+        let ids: Vec<_> = report.tasks.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["1", "2", "3", "4"]);
+    }
+
+    #[tokio::test]
+    async fn dependency_outputs_reach_the_prompt() {
+        let general = Arc::new(
+            MockLlm::new()
+                .with_response("Paris")
+                .with_response("about 2 million"),
+        );
+        let tasks = [task("1", &[]), task("2", &["1"])];
+        let report = PlanExecutor::new(agent(&general))
+            .run(&tasks)
+            .await
+            .unwrap();
+
+        assert_eq!(report.output("2"), Some("about 2 million"));
+        assert!(general.calls()[1].input.contains("[1]\nParis"));
+    }
+
+    #[tokio::test]
+    async fn parallelism_is_capped() {
+        let probe = Arc::new(ConcurrencyProbe::default());
+        let general = LlmAgent::build(String::new(), None, None, None, "assistant".to_string())
+            .unwrap()
+            .with_provider(probe.clone());
+        let tasks: Vec<Task> = (1..=6).map(|i| task(&i.to_string(), &[])).collect();
+
+        let report = PlanExecutor::new(general)
+            .with_max_parallel(2)
+            .run(&tasks)
+            .await
+            .unwrap();
+
+        assert!(report.succeeded());
+        assert_eq!(probe.peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn failure_skips_everything_downstream() {
+        // Only task 4 has a scripted answer, so task 1 fails
+        let general = Arc::new(MockLlm::new().with_regex("Do 4", "done").unwrap());
+        let tasks = [
+            task("1", &[]),
+            task("2", &["1"]),
+            task("3", &["2"]),
+            task("4", &[]),
+        ];
+        let report = PlanExecutor::new(agent(&general))
+            .run(&tasks)
+            .await
+            .unwrap();
+
+        let status = |id| report.task(id).unwrap().status;
+        assert_eq!(status("1"), TaskStatus::Failed);
+        assert_eq!(status("2"), TaskStatus::Skipped);
+        assert_eq!(status("3"), TaskStatus::Skipped);
+        assert_eq!(status("4"), TaskStatus::Succeeded);
+        assert_eq!(
+            report.task("3").unwrap().error.as_deref(),
+            Some("dependency 2 did not succeed")
+        );
+        assert_eq!(general.call_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn tool_arguments_take_dependency_outputs() {
+        let general = Arc::new(MockLlm::new().with_response("New York"));
+        let mut weather = task("2", &["1"]);
+        weather.tool = Some("get_current_weather".to_string());
+        weather.arguments = Some(json!({ "location": "{{1}}", "unit": "celsius" }));
+
+        let report = PlanExecutor::new(agent(&general))
+            .run(&[task("1", &[]), weather])
+            .await
+            .unwrap();
+
+        let weather = report.task("2").unwrap();
+        assert_eq!(weather.executor, "tool:get_current_weather");
+        assert_eq!(
+            weather.output.as_deref(),
+            Some("Weather for New York in 25 celsius ")
+        );
+    }
+
+    #[test]
+    fn placeholders_are_filled_at_any_depth() {
+        let inputs = vec![
+            ("a".to_string(), "Paris".to_string()),
+            ("b".to_string(), "3".to_string()),
+        ];
+        let filled = fill_placeholders(
+            json!({
+                "city": "{{a}}",
+                "trips": ["{{b}} days in {{a}}", 7],
+                "unknown": "{{c}}",
+            }),
+            &inputs,
+        );
+        assert_eq!(
+            filled,
+            json!({
+                "city": "Paris",
+                "trips": ["3 days in Paris", 7],
+                "unknown": "{{c}}",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_output_fails_the_task() {
+        let general = Arc::new(MockLlm::new().with_response("London"));
+        let reviewer = Arc::new(MockLlm::new().with_response("FAIL: it names London"));
+        let mut capital = task("1", &[]);
+        capital.acceptance_criteria = vec!["names the capital of France".to_string()];
+
+        let report = PlanExecutor::new(agent(&general))
+            .with_acceptance_check(agent(&reviewer))
+            .run(&[capital, task("2", &["1"])])
+            .await
+            .unwrap();
+
+        let capital = report.task("1").unwrap();
+        assert_eq!(capital.status, TaskStatus::Failed);
+        assert_eq!(
+            capital.error.as_deref(),
+            Some("output rejected by the acceptance check: it names London")
+        );
+        assert_eq!(report.task("2").unwrap().status, TaskStatus::Skipped);
+        assert!(reviewer.calls()[0]
+            .input
+            .contains("names the capital of France"));
+    }
+
+    #[tokio::test]
+    async fn only_tasks_with_criteria_are_reviewed() {
+        let general = Arc::new(MockLlm::new().with_fallback("Paris"));
+        let reviewer = Arc::new(MockLlm::new().with_response("PASS"));
+        let mut capital = task("1", &[]);
+        capital.expected_output = Some("The capital of France".to_string());
+
+        let report = PlanExecutor::new(agent(&general))
+            .with_acceptance_check(agent(&reviewer))
+            .run(&[capital, task("2", &[])])
+            .await
+            .unwrap();
+
+        assert!(report.succeeded());
+        assert_eq!(reviewer.call_count(), 1);
+    }
+
+    #[test]
+    fn unchanged_tasks_keep_their_results() {
+        let previous = vec![task("1", &[]), task("2", &["1"])];
+        let reports = vec![succeeded("1"), succeeded("2")];
+
+        let carried = carried_results(&previous, &previous, &reports);
+        assert_eq!(carried.len(), 2);
+
+        let revised = vec![task("1", &[]), task("3", &["1"])];
+        let carried = carried_results(&previous, &revised, &reports);
+        assert_eq!(carried.keys().collect::<Vec<_>>(), ["1"]);
+    }
+
+    #[test]
+    fn rewritten_tasks_and_their_dependents_run_again() {
+        let previous = vec![task("1", &[]), task("2", &["1"])];
+        let reports = vec![succeeded("1"), succeeded("2")];
+
+        let mut rewritten = task("1", &[]);
+        rewritten.description = "Do 1 differently".to_string();
+        let carried = carried_results(&previous, &[rewritten, task("2", &["1"])], &reports);
+        assert!(carried.is_empty());
+    }
+}
@@ -9,7 +9,7 @@ use autogen_rust::agent_runtime::{
     router::{RouterActor, RouterState, RouterStatus},
     ActorContext, AgentId, MessageContext, RouterCommand, TopicId,
 };
-use autogen_rust::{immutable_agent::*, llama::*, FormatterWrapper, LlmConfig};
+use autogen_rust::{immutable_agent::*, llama::*, FormatterWrapper, LlmConfig, ProviderKind};
 use autogen_rust::{
     STORE, TEMPLATE_SYSTEM_PROMPT_TOOL_USE, TEMPLATE_USER_PROMPT_TASK_JSON,
     TEMPLATE_USER_PROMPT_TOOL_USE,
@@ -98,18 +98,19 @@ async fn main() -> Result<()> {
 }
 }]).to_string();
 
-    let TOGETHER_CONFIG: LlmConfig = LlmConfig {
-        model: "meta-llama/Meta-Llama-3.1-8B-Instruct-Turbo",
-        // model: "Qwen/Qwen2.5-7B-Instruct-Turbo",
-        // model: "google/gemma-2-9b-it",
-        // model: "mistralai/Mistral-Small-24B-Instruct-2501",
-        // model: "meta-llama/Llama-3.3-70B-Instruct-Turbo",
-        context_size: 8192,
-        base_url: "https://api.together.xyz/v1/chat/completions",
-        api_key_str: "TOGETHER_API_KEY",
-    };
-
-    let max_token = 1000u16;
+    let TOGETHER_CONFIG: LlmConfig = LlmConfig::new(
+        "meta-llama/Meta-Llama-3.1-8B-Instruct-Turbo",
+        // "Qwen/Qwen2.5-7B-Instruct-Turbo",
+        // "google/gemma-2-9b-it",
+        // "mistralai/Mistral-Small-24B-Instruct-2501",
+        // "meta-llama/Llama-3.3-70B-Instruct-Turbo",
+        "https://api.together.xyz/v1/chat/completions",
+    )
+    .with_context_size(8192)
+    .with_api_key_var("TOGETHER_API_KEY")
+    .with_provider(ProviderKind::OpenAiCompatible);
+
+    let max_token = TOGETHER_CONFIG.sampling.max_tokens;
 
     let mut llama_response = String::new();
     let mut usage = CompletionUsage {
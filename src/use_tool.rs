@@ -12,6 +12,7 @@ lazy_static! {
     pub static ref STORE: Mutex<HashMap<String, Tool>> = Mutex::new(HashMap::new());
 }
 
+#[derive(Clone)]
 pub struct Tool {
     pub name: String,
     pub function: Arc<dyn Fn(&[String]) -> MyResult<String> + Send + Sync>,